@@ -0,0 +1,512 @@
+use crate::telemetry;
+use axum::{
+    Json, body,
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::multipart::MultipartError;
+use metrics::counter;
+use nanoid::nanoid;
+use serde::Serialize;
+use std::{fmt, io};
+
+// Larger bodies of rejections are cut off rather than read into memory.
+const MAX_REJECTION_BYTES: usize = 4096;
+
+tokio::task_local! {
+    // The ID of the request being handled, set by `json_errors`.
+    static REQUEST_ID: String;
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    PoolError(deadpool_redis::PoolError),
+    RedisError(redis::RedisError),
+    // S3 errors are boxed, as they are much larger than the others.
+    S3PutError(Box<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>>),
+    S3GetError(Box<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>>),
+    S3CopyError(
+        Box<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::copy_object::CopyObjectError>>,
+    ),
+    // An S3 object whose body couldn't be read to the end.
+    S3BodyError(aws_sdk_s3::primitives::ByteStreamError),
+    UploadError(String),
+    ZipError(zip::result::ZipError),
+    IoError(io::Error),
+    RenderError(String),
+    SerializeError(serde_json::Error),
+    // Data stored in Redis that can't be read back, e.g. written by a newer
+    // version of the backend.
+    CorruptHubData(serde_json::Error),
+    // What wasn't found, e.g. "Hub" or "Snippet".
+    NotFound(&'static str),
+    // The hub changed since the client fetched it; see `if_match`.
+    PreconditionFailed,
+    // A request that can't be understood, like a malformed JSON body.
+    Validation(String),
+    InvalidSnippet(String),
+    UnknownLanguage(String),
+    InvalidTtl {
+        min_secs: u64,
+        max_secs: u64,
+    },
+    // A text bin or snippet longer than `limits.max_text_bytes`.
+    TextTooLarge(usize),
+    // A request body longer than `limits.max_upload_bytes` for uploads, or
+    // `limits.max_text_bytes` for anything else.
+    PayloadTooLarge,
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    // A request without the credentials it needs.
+    Unauthorized,
+    // Any other response a layer or extractor rejected a request with.
+    Rejected {
+        status: StatusCode,
+        message: String,
+    },
+}
+
+/// The body of every error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// A stable, machine-readable code like `not_found`.
+    pub code: &'static str,
+    /// A message for people, safe to show to users.
+    pub message: String,
+    /// Identifies the failed request in the server's logs.
+    pub request_id: String,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            AppError::Validation(_)
+            | AppError::InvalidSnippet(_)
+            | AppError::UnknownLanguage(_)
+            | AppError::InvalidTtl { .. }
+            | AppError::UploadError(_) => StatusCode::BAD_REQUEST,
+            AppError::TextTooLarge(_) | AppError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Rejected { status, .. } => *status,
+            AppError::PoolError(_)
+            | AppError::RedisError(_)
+            | AppError::S3PutError(_)
+            | AppError::S3GetError(_)
+            | AppError::S3CopyError(_)
+            | AppError::S3BodyError(_)
+            | AppError::ZipError(_)
+            | AppError::IoError(_)
+            | AppError::RenderError(_)
+            | AppError::SerializeError(_)
+            | AppError::CorruptHubData(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The code clients can match on. Codes never change once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::PreconditionFailed => "precondition_failed",
+            AppError::Validation(_) => "validation_failed",
+            AppError::InvalidSnippet(_) => "invalid_snippet",
+            AppError::UnknownLanguage(_) => "unknown_language",
+            AppError::InvalidTtl { .. } => "invalid_ttl",
+            AppError::UploadError(_) => "upload_failed",
+            AppError::TextTooLarge(_) => "text_too_large",
+            AppError::PayloadTooLarge => "payload_too_large",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Unauthorized => "unauthorized",
+            AppError::CorruptHubData(_) => "corrupt_hub_data",
+            AppError::Rejected { status, .. } => match *status {
+                StatusCode::NOT_FOUND => "not_found",
+                StatusCode::FORBIDDEN => "forbidden",
+                StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+                status if status.is_client_error() => "bad_request",
+                _ => "internal_error",
+            },
+            AppError::PoolError(_)
+            | AppError::RedisError(_)
+            | AppError::S3PutError(_)
+            | AppError::S3GetError(_)
+            | AppError::S3CopyError(_)
+            | AppError::S3BodyError(_)
+            | AppError::ZipError(_)
+            | AppError::IoError(_)
+            | AppError::RenderError(_)
+            | AppError::SerializeError(_) => "internal_error",
+        }
+    }
+
+    /// The name of the variant, which errors are counted by.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            AppError::PoolError(_) => "PoolError",
+            AppError::RedisError(_) => "RedisError",
+            AppError::S3PutError(_) => "S3PutError",
+            AppError::S3GetError(_) => "S3GetError",
+            AppError::S3CopyError(_) => "S3CopyError",
+            AppError::S3BodyError(_) => "S3BodyError",
+            AppError::UploadError(_) => "UploadError",
+            AppError::ZipError(_) => "ZipError",
+            AppError::IoError(_) => "IoError",
+            AppError::RenderError(_) => "RenderError",
+            AppError::SerializeError(_) => "SerializeError",
+            AppError::CorruptHubData(_) => "CorruptHubData",
+            AppError::NotFound(_) => "NotFound",
+            AppError::PreconditionFailed => "PreconditionFailed",
+            AppError::Validation(_) => "Validation",
+            AppError::InvalidSnippet(_) => "InvalidSnippet",
+            AppError::UnknownLanguage(_) => "UnknownLanguage",
+            AppError::InvalidTtl { .. } => "InvalidTtl",
+            AppError::TextTooLarge(_) => "TextTooLarge",
+            AppError::PayloadTooLarge => "PayloadTooLarge",
+            AppError::RateLimited { .. } => "RateLimited",
+            AppError::Unauthorized => "Unauthorized",
+            AppError::Rejected { .. } => "Rejected",
+        }
+    }
+
+    /// The message sent to the client. Internal details are only logged.
+    fn message(&self) -> String {
+        match self {
+            AppError::NotFound(what) => format!("{} not found", what),
+            AppError::PreconditionFailed => "The hub has changed since it was fetched".to_string(),
+            AppError::Validation(message)
+            | AppError::InvalidSnippet(message)
+            | AppError::Rejected { message, .. } => message.clone(),
+            AppError::UnknownLanguage(language) => format!("Unknown language: {}", language),
+            AppError::InvalidTtl { min_secs, max_secs } => format!(
+                "The TTL must be between {} and {} seconds",
+                min_secs, max_secs
+            ),
+            AppError::UploadError(message) => format!("File upload failed: {}", message),
+            AppError::TextTooLarge(max_bytes) => {
+                format!("Text can't be longer than {} bytes", max_bytes)
+            }
+            AppError::PayloadTooLarge => "The request body is too large".to_string(),
+            AppError::RateLimited { retry_after_secs } => format!(
+                "Too many requests, try again in {} seconds",
+                retry_after_secs
+            ),
+            AppError::Unauthorized => "Unauthorized".to_string(),
+            AppError::CorruptHubData(_) => "The hub's data is corrupt".to_string(),
+            _ => "Internal Server Error".to_string(),
+        }
+    }
+
+    // Turns the error response of an extractor or a layer, like the plain
+    // text one for a malformed JSON body, into the matching error.
+    fn from_rejection(status: StatusCode, headers: &HeaderMap, message: String) -> AppError {
+        match status {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                AppError::Validation(message)
+            }
+            StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge,
+            StatusCode::UNAUTHORIZED => AppError::Unauthorized,
+            StatusCode::TOO_MANY_REQUESTS => AppError::RateLimited {
+                retry_after_secs: headers
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .unwrap_or(1),
+            },
+            _ => AppError::Rejected { status, message },
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::PoolError(e) => write!(f, "pool error: {}", e),
+            AppError::RedisError(e) => write!(f, "Redis error: {}", e),
+            AppError::S3PutError(e) => write!(f, "S3 put object error: {:?}", e),
+            AppError::S3GetError(e) => write!(f, "S3 get object error: {:?}", e),
+            AppError::S3CopyError(e) => write!(f, "S3 copy object error: {:?}", e),
+            AppError::S3BodyError(e) => write!(f, "S3 body error: {}", e),
+            AppError::ZipError(e) => write!(f, "zip creation error: {}", e),
+            AppError::IoError(e) => write!(f, "IO error: {}", e),
+            AppError::RenderError(e) => write!(f, "whiteboard rendering error: {}", e),
+            AppError::SerializeError(e) => write!(f, "serialization error: {}", e),
+            AppError::CorruptHubData(e) => write!(f, "corrupt hub data: {}", e),
+            _ => f.write_str(&self.message()),
+        }
+    }
+}
+
+// Converts our custom AppError into a JSON response with a stable code.
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        counter!("ephemeral_errors_total", "variant" => self.variant()).increment(1);
+        // Errors outside of a request, like in a WebSocket task, get an ID
+        // of their own.
+        let request_id = REQUEST_ID
+            .try_with(Clone::clone)
+            .unwrap_or_else(|_| nanoid!(12));
+        if status.is_server_error() {
+            tracing::error!(request_id, "{}", self);
+        }
+
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id,
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let AppError::RateLimited { retry_after_secs } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+        }
+        response
+    }
+}
+
+/// Middleware giving every error response the JSON body of [`AppError`],
+/// including those of extractors, layers and unknown routes. Error bodies
+/// carry the ID of the request they answer, as in its `X-Request-Id`.
+pub async fn json_errors(request: Request, next: Next) -> Response {
+    let request_id = telemetry::request_id(&request).to_string();
+    REQUEST_ID
+        .scope(request_id, async move {
+            let response = next.run(request).await;
+            into_json_error(response).await
+        })
+        .await
+}
+
+async fn into_json_error(response: Response) -> Response {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let message = match body::to_bytes(body, MAX_REJECTION_BYTES).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => status.canonical_reason().unwrap_or("Error").to_string(),
+    };
+    let mut response = AppError::from_rejection(status, &parts.headers, message).into_response();
+    // Keep headers like `Allow` that explain the rejection.
+    for (name, value) in parts.headers.iter() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+    response
+}
+
+// `From` trait implementations to allow using the `?` operator on different error types.
+impl From<deadpool_redis::PoolError> for AppError {
+    fn from(err: deadpool_redis::PoolError) -> Self {
+        AppError::PoolError(err)
+    }
+}
+impl From<redis::RedisError> for AppError {
+    fn from(err: redis::RedisError) -> Self {
+        AppError::RedisError(err)
+    }
+}
+impl From<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>>
+    for AppError
+{
+    fn from(
+        err: aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>,
+    ) -> Self {
+        AppError::S3PutError(Box::new(err))
+    }
+}
+impl From<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>>
+    for AppError
+{
+    fn from(
+        err: aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>,
+    ) -> Self {
+        AppError::S3GetError(Box::new(err))
+    }
+}
+impl From<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::copy_object::CopyObjectError>>
+    for AppError
+{
+    fn from(
+        err: aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::copy_object::CopyObjectError>,
+    ) -> Self {
+        AppError::S3CopyError(Box::new(err))
+    }
+}
+impl From<aws_sdk_s3::primitives::ByteStreamError> for AppError {
+    fn from(err: aws_sdk_s3::primitives::ByteStreamError) -> Self {
+        AppError::S3BodyError(err)
+    }
+}
+impl From<MultipartError> for AppError {
+    fn from(err: MultipartError) -> Self {
+        if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::PayloadTooLarge
+        } else {
+            AppError::UploadError(err.body_text())
+        }
+    }
+}
+impl From<zip::result::ZipError> for AppError {
+    fn from(err: zip::result::ZipError) -> Self {
+        AppError::ZipError(err)
+    }
+}
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self {
+        AppError::IoError(err)
+    }
+}
+// Reading stored data uses `AppError::CorruptHubData` instead.
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::SerializeError(err)
+    }
+}
+
+/// Parses data stored in Redis.
+pub fn parse_stored<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, AppError> {
+    serde_json::from_str(json).map_err(AppError::CorruptHubData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    async fn body_of(response: Response) -> Value {
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn errors_map_to_statuses_and_codes() {
+        let cases = [
+            (
+                AppError::NotFound("Hub"),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                AppError::PreconditionFailed,
+                StatusCode::PRECONDITION_FAILED,
+                "precondition_failed",
+            ),
+            (
+                AppError::Validation("bad".to_string()),
+                StatusCode::BAD_REQUEST,
+                "validation_failed",
+            ),
+            (
+                AppError::TextTooLarge(10),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "text_too_large",
+            ),
+            (
+                AppError::RateLimited {
+                    retry_after_secs: 3,
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+            (
+                AppError::Unauthorized,
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            ),
+            (
+                AppError::RenderError("font".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+            ),
+            (
+                AppError::Rejected {
+                    status: StatusCode::METHOD_NOT_ALLOWED,
+                    message: "no".to_string(),
+                },
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+            ),
+            (
+                AppError::Rejected {
+                    status: StatusCode::IM_A_TEAPOT,
+                    message: "no".to_string(),
+                },
+                StatusCode::IM_A_TEAPOT,
+                "bad_request",
+            ),
+        ];
+        for (error, status, code) in cases {
+            assert_eq!(error.status(), status, "{:?}", error);
+            assert_eq!(error.code(), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn unreadable_stored_data_is_corrupt() {
+        let error = parse_stored::<Vec<u64>>("{not json").unwrap_err();
+        assert!(matches!(error, AppError::CorruptHubData(_)));
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), "corrupt_hub_data");
+    }
+
+    #[tokio::test]
+    async fn error_bodies_name_the_request() {
+        let response = REQUEST_ID
+            .scope("abc123".to_string(), async {
+                AppError::RateLimited {
+                    retry_after_secs: 3,
+                }
+                .into_response()
+            })
+            .await;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "3");
+        assert_eq!(
+            body_of(response).await,
+            json!({
+                "code": "rate_limited",
+                "message": "Too many requests, try again in 3 seconds",
+                "request_id": "abc123",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejections_get_json_bodies() {
+        let rejection = (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Failed to deserialize the JSON body",
+        )
+            .into_response();
+        let response = REQUEST_ID
+            .scope("abc123".to_string(), into_json_error(rejection))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body_of(response).await;
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["message"], "Failed to deserialize the JSON body");
+        assert_eq!(body["request_id"], "abc123");
+
+        let unauthorized = (StatusCode::UNAUTHORIZED, "no").into_response();
+        let response = into_json_error(unauthorized).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body_of(response).await["code"], "unauthorized");
+    }
+}
@@ -0,0 +1,421 @@
+use crate::{
+    shared_types::{BoardElement, Bounds, LineData, LoggedOperation, StrokePoint, WsMessage},
+    whiteboard,
+};
+use resvg::{tiny_skia, usvg};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, LazyLock};
+
+/// Space left around the drawing in exported images.
+const PADDING: f64 = 20.0;
+
+/// The size of an exported empty page.
+const EMPTY_PAGE_SIZE: (f64, f64) = (800.0, 600.0);
+
+/// The longest pause between two operations in a replay, in seconds.
+/// Real pauses can be minutes long, which nobody wants to sit through.
+const MAX_REPLAY_PAUSE: f64 = 1.0;
+
+/// PNG exports are scaled down to fit this many pixels on their longest side.
+const MAX_PNG_SIZE: f64 = 4096.0;
+
+// These match how the web client renders text and sticky notes.
+const TEXT_LINE_HEIGHT: f64 = 1.2;
+const TEXT_CHAR_WIDTH: f64 = 0.6;
+const STICKY_NOTE_FONT_SIZE: f64 = 14.0;
+const STICKY_NOTE_PADDING: f64 = 8.0;
+const MIN_PRESSURE_WIDTH: f64 = 0.25;
+const FONT_FAMILY: &str = "sans-serif";
+
+// Loading the system fonts is slow, so it is only done once.
+// The Docker image ships DejaVu Sans for text labels and sticky notes.
+static FONTS: LazyLock<Arc<usvg::fontdb::Database>> = LazyLock::new(|| {
+    let mut fonts = usvg::fontdb::Database::new();
+    fonts.load_system_fonts();
+    fonts.set_sans_serif_family("DejaVu Sans");
+    Arc::new(fonts)
+});
+
+/// Renders the elements of a whiteboard page as a standalone SVG document,
+/// cropped to the area they cover.
+pub fn to_svg(elements: &[BoardElement]) -> String {
+    let mut svg = svg_start(view_box(elements));
+    for element in elements {
+        write_element(&mut svg, element);
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Renders the log of a whiteboard page as an animated SVG document that
+/// replays how the page was drawn.
+pub fn to_animated_svg(log: &[LoggedOperation]) -> String {
+    // Each state an element was in, with the replay times it is shown between.
+    struct Version {
+        element: BoardElement,
+        from: f64,
+        until: Option<f64>,
+    }
+    let mut versions: Vec<Version> = Vec::new();
+    // The version currently on the board of each element, by element id.
+    let mut current: HashMap<String, usize> = HashMap::new();
+    let mut board = Vec::new();
+    let mut time = 0.0;
+
+    for (i, op) in log.iter().enumerate() {
+        if i > 0 {
+            let pause = (op.at - log[i - 1].at).num_milliseconds() as f64 / 1000.0;
+            time += pause.clamp(0.0, MAX_REPLAY_PAUSE);
+        }
+        let changed: Vec<String> = match &op.message {
+            WsMessage::ElementAdded { element, .. } => vec![element.id().to_string()],
+            WsMessage::ElementDeleted { id, .. }
+            | WsMessage::ElementMoved { id, .. }
+            | WsMessage::ElementResized { id, .. } => vec![id.clone()],
+            WsMessage::BoardCleared { .. } => current.keys().cloned().collect(),
+            WsMessage::Undo { .. }
+            | WsMessage::Redo { .. }
+            | WsMessage::PageCreated(_)
+            | WsMessage::TextEdit { .. }
+            | WsMessage::TextCursor { .. }
+            | WsMessage::TextSnapshot { .. }
+            | WsMessage::TextUpdated { .. }
+            | WsMessage::SnippetUpdated { .. }
+            | WsMessage::SnippetDeleted { .. }
+            | WsMessage::TextLanguageChanged { .. }
+            | WsMessage::ServerRestarting => Vec::new(),
+        };
+        whiteboard::apply_effect(&mut board, &op.message);
+
+        for id in changed {
+            if let Some(index) = current.remove(&id) {
+                versions[index].until = Some(time);
+            }
+            if let Some(element) = board.iter().find(|e| e.id() == id) {
+                current.insert(id, versions.len());
+                versions.push(Version {
+                    element: element.clone(),
+                    from: time,
+                    until: None,
+                });
+            }
+        }
+    }
+
+    let elements: Vec<BoardElement> = versions.iter().map(|v| v.element.clone()).collect();
+    let mut svg = svg_start(view_box(&elements));
+    for version in &versions {
+        svg.push_str(r#"<g visibility="hidden">"#);
+        let _ = write!(
+            svg,
+            r#"<set attributeName="visibility" to="visible" begin="{:.2}s"/>"#,
+            version.from
+        );
+        if let Some(until) = version.until {
+            let _ = write!(
+                svg,
+                r#"<set attributeName="visibility" to="hidden" begin="{:.2}s"/>"#,
+                until
+            );
+        }
+        write_element(&mut svg, &version.element);
+        svg.push_str("</g>");
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+// The opening tag of an SVG document showing `view`, and a white background.
+fn svg_start(view: Bounds) -> String {
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        view.x, view.y, view.width, view.height, view.width, view.height
+    );
+    let _ = write!(
+        svg,
+        r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#ffffff"/>"##,
+        view.x, view.y, view.width, view.height
+    );
+    svg
+}
+
+/// Renders the elements of a whiteboard page as a PNG image.
+/// This is CPU heavy, so it should be run on a blocking thread.
+pub fn to_png(elements: &[BoardElement]) -> Result<Vec<u8>, String> {
+    let options = usvg::Options {
+        font_family: FONT_FAMILY.to_string(),
+        fontdb: FONTS.clone(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(&to_svg(elements), &options).map_err(|e| e.to_string())?;
+
+    let size = tree.size();
+    let scale = (MAX_PNG_SIZE / size.width().max(size.height()) as f64).min(1.0);
+    let width = (size.width() as f64 * scale).ceil().max(1.0) as u32;
+    let height = (size.height() as f64 * scale).ceil().max(1.0) as u32;
+    let mut pixmap =
+        tiny_skia::Pixmap::new(width, height).ok_or_else(|| "invalid image size".to_string())?;
+
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale as f32, scale as f32),
+        &mut pixmap.as_mut(),
+    );
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+// The area covered by all elements, with some padding around it.
+fn view_box(elements: &[BoardElement]) -> Bounds {
+    if elements.is_empty() {
+        return Bounds {
+            x: 0.0,
+            y: 0.0,
+            width: EMPTY_PAGE_SIZE.0,
+            height: EMPTY_PAGE_SIZE.1,
+        };
+    }
+    let content = Bounds::around(elements.iter().flat_map(|e| {
+        let b = e.bounds();
+        [(b.x, b.y), (b.x + b.width, b.y + b.height)]
+    }));
+    // Strokes stick out of the bounds by half their width.
+    let margin = PADDING + elements.iter().map(stroke_width).fold(0.0, f64::max) / 2.0;
+    Bounds {
+        x: content.x - margin,
+        y: content.y - margin,
+        width: content.width + 2.0 * margin,
+        height: content.height + 2.0 * margin,
+    }
+}
+
+fn stroke_width(element: &BoardElement) -> f64 {
+    match element {
+        BoardElement::Path(p) => p.stroke_width,
+        BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => s.stroke_width,
+        BoardElement::Line(l) | BoardElement::Arrow(l) => l.stroke_width,
+        BoardElement::Text(_) | BoardElement::StickyNote(_) => 0.0,
+    }
+}
+
+fn write_element(svg: &mut String, element: &BoardElement) {
+    let _ = match element {
+        BoardElement::Path(p) if p.points.iter().any(|pt| pt.pressure.is_some()) => write!(
+            svg,
+            r#"<path d="{}" fill="{color}" stroke="{color}" stroke-width="{}" stroke-linejoin="round" opacity="{}"/>"#,
+            pressure_outline(&p.points, p.stroke_width),
+            p.stroke_width * MIN_PRESSURE_WIDTH,
+            p.opacity,
+            color = escape(&p.color),
+        ),
+        BoardElement::Path(p) => write!(
+            svg,
+            r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round" opacity="{}" style="mix-blend-mode:{}"/>"#,
+            to_svg_path(&p.points),
+            escape(&p.color),
+            p.stroke_width,
+            p.opacity,
+            if p.highlighter { "multiply" } else { "normal" },
+        ),
+        BoardElement::Rectangle(s) => write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" stroke="{}" stroke-width="{}" fill="none"/>"#,
+            s.bounds.x,
+            s.bounds.y,
+            s.bounds.width,
+            s.bounds.height,
+            escape(&s.color),
+            s.stroke_width,
+        ),
+        BoardElement::Ellipse(s) => {
+            let (rx, ry) = (s.bounds.width / 2.0, s.bounds.height / 2.0);
+            write!(
+                svg,
+                r#"<ellipse cx="{}" cy="{}" rx="{}" ry="{}" stroke="{}" stroke-width="{}" fill="none"/>"#,
+                s.bounds.x + rx,
+                s.bounds.y + ry,
+                rx,
+                ry,
+                escape(&s.color),
+                s.stroke_width,
+            )
+        }
+        BoardElement::Line(l) => write_line(svg, l),
+        BoardElement::Arrow(l) => write_line(svg, l).and_then(|_| {
+            write!(
+                svg,
+                r#"<path d="{}" stroke="{}" stroke-width="{}" fill="none" stroke-linecap="round" stroke-linejoin="round"/>"#,
+                arrow_head_path(l),
+                escape(&l.color),
+                l.stroke_width,
+            )
+        }),
+        BoardElement::Text(t) => {
+            let lines: Vec<&str> = t.text.lines().collect();
+            write_text(
+                svg,
+                t.position,
+                &lines,
+                t.font_size,
+                &escape(&t.color),
+            )
+        }
+        BoardElement::StickyNote(n) => {
+            let line_height = STICKY_NOTE_FONT_SIZE * TEXT_LINE_HEIGHT;
+            let max_chars = ((n.bounds.width - 2.0 * STICKY_NOTE_PADDING)
+                / (STICKY_NOTE_FONT_SIZE * TEXT_CHAR_WIDTH)) as usize;
+            let max_lines = ((n.bounds.height - 2.0 * STICKY_NOTE_PADDING) / line_height) as usize;
+            let lines: Vec<String> = wrap_text(&n.text, max_chars)
+                .into_iter()
+                .take(max_lines)
+                .collect();
+            let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+            write!(
+                svg,
+                r##"<rect x="{}" y="{}" width="{}" height="{}" rx="4" fill="{}" stroke="#00000022"/>"##,
+                n.bounds.x,
+                n.bounds.y,
+                n.bounds.width,
+                n.bounds.height,
+                escape(&n.color),
+            )
+            .and_then(|_| {
+                write_text(
+                    svg,
+                    (
+                        n.bounds.x + STICKY_NOTE_PADDING,
+                        n.bounds.y + STICKY_NOTE_PADDING,
+                    ),
+                    &lines,
+                    STICKY_NOTE_FONT_SIZE,
+                    "#1e293b",
+                )
+            })
+        }
+    };
+}
+
+fn write_line(svg: &mut String, line: &LineData) -> std::fmt::Result {
+    write!(
+        svg,
+        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}" stroke-linecap="round"/>"#,
+        line.start.0,
+        line.start.1,
+        line.end.0,
+        line.end.1,
+        escape(&line.color),
+        line.stroke_width,
+    )
+}
+
+// Writes lines of text with their top-left corner at `(x, y)`.
+fn write_text(
+    svg: &mut String,
+    (x, y): (f64, f64),
+    lines: &[&str],
+    font_size: f64,
+    color: &str,
+) -> std::fmt::Result {
+    write!(
+        svg,
+        r#"<text fill="{}" font-size="{}" font-family="{}" dominant-baseline="hanging">"#,
+        color, font_size, FONT_FAMILY
+    )?;
+    for (i, line) in lines.iter().enumerate() {
+        write!(
+            svg,
+            r#"<tspan x="{}" y="{}">{}</tspan>"#,
+            x,
+            y + i as f64 * font_size * TEXT_LINE_HEIGHT,
+            escape(line)
+        )?;
+    }
+    svg.push_str("</text>");
+    Ok(())
+}
+
+// Escapes text for use in SVG content and attribute values.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_svg_path(points: &[StrokePoint]) -> String {
+    let mut d = String::new();
+    for (i, p) in points.iter().enumerate() {
+        let _ = write!(d, "{}{} {} ", if i == 0 { "M" } else { "L" }, p.x, p.y);
+    }
+    d
+}
+
+// The outline of a stroke whose width follows the pen pressure, like the
+// web client draws it.
+fn pressure_outline(points: &[StrokePoint], stroke_width: f64) -> String {
+    let half_width = |p: &StrokePoint| {
+        stroke_width
+            * (MIN_PRESSURE_WIDTH + (2.0 - 2.0 * MIN_PRESSURE_WIDTH) * p.pressure.unwrap_or(0.5))
+            / 2.0
+    };
+    let mut left = Vec::with_capacity(points.len());
+    let mut right = Vec::with_capacity(points.len());
+    for (i, p) in points.iter().enumerate() {
+        let prev = &points[i.saturating_sub(1)];
+        let next = &points[(i + 1).min(points.len() - 1)];
+        let (dx, dy) = (next.x - prev.x, next.y - prev.y);
+        let length = dx.hypot(dy);
+        let (nx, ny) = if length > 0.0 {
+            (-dy / length, dx / length)
+        } else {
+            (0.0, 1.0)
+        };
+        let w = half_width(p);
+        left.push((p.x + nx * w, p.y + ny * w));
+        right.push((p.x - nx * w, p.y - ny * w));
+    }
+    let mut d = String::new();
+    for (i, (x, y)) in left.iter().chain(right.iter().rev()).enumerate() {
+        let _ = write!(d, "{}{} {} ", if i == 0 { "M" } else { "L" }, x, y);
+    }
+    d.push('Z');
+    d
+}
+
+// The two short strokes forming the head of an arrow at `line.end`.
+fn arrow_head_path(line: &LineData) -> String {
+    let angle = (line.end.1 - line.start.1).atan2(line.end.0 - line.start.0);
+    let size = 10.0 + line.stroke_width * 2.0;
+    let spread = std::f64::consts::PI / 7.0;
+    format!(
+        "M {} {} L {} {} L {} {}",
+        line.end.0 - size * (angle - spread).cos(),
+        line.end.1 - size * (angle - spread).sin(),
+        line.end.0,
+        line.end.1,
+        line.end.0 - size * (angle + spread).cos(),
+        line.end.1 - size * (angle + spread).sin(),
+    )
+}
+
+// Splits text into lines of at most `max_chars` characters, breaking at spaces.
+fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
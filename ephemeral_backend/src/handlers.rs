@@ -1,142 +1,202 @@
-use crate::{AppState, shared_types::PathData};
-use aws_sdk_s3::{
-    Client as S3Client, error::SdkError, operation::head_bucket::HeadBucketError,
-    primitives::ByteStream,
+use crate::{
+    AppState,
+    error::{AppError, parse_stored},
+    export, language,
+    shared_types::{
+        BoardElement, LoggedOperation, PageInfo, PathData, RevisionSource, SnippetInfo,
+        TextRevision, TextRevisionInfo, WsMessage,
+    },
+    snippet::{self, snippet_key},
+    telemetry::{self, RedisConnection},
+    text,
+    whiteboard::{self, WhiteboardPage, page_key},
 };
+use aws_sdk_s3::primitives::ByteStream;
 
 use axum::{
     Json,
-    body::Body,
-    extract::{Path, State},
-    http::{StatusCode, header},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use axum_extra::extract::Multipart;
 use chrono::{DateTime, Duration, Utc};
+use metrics::counter;
 use nanoid::nanoid;
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::io::{self, Cursor, Write};
-use tracing::debug;
+use std::{
+    collections::HashSet,
+    io::{Cursor, Write},
+};
+use tracing::{debug, warn};
 use zip::write::{FileOptions, ZipWriter};
 
-#[derive(Debug)]
-pub enum AppError {
-    PoolError(deadpool_redis::PoolError),
-    RedisError(redis::RedisError),
-    S3PutError(aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>),
-    S3GetError(aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>),
-    UploadError(String),
-    ZipError(zip::result::ZipError),
-    IoError(io::Error),
-    NotFound,
-}
-
-// Converts our custom AppError into a user-friendly HTTP response.
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::PoolError(e) => {
-                tracing::error!("Pool error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-            AppError::RedisError(e) => {
-                tracing::error!("Redis error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-            AppError::NotFound => (StatusCode::NOT_FOUND, "Hub not found".to_string()),
-            AppError::IoError(e) => {
-                tracing::error!("IO error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-            AppError::S3PutError(e) => {
-                tracing::error!("S3 Put Object error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-            AppError::S3GetError(e) => {
-                tracing::error!("S3 Get Object error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-            AppError::UploadError(e) => {
-                tracing::error!("Upload error: {:?}", e);
-                (StatusCode::BAD_REQUEST, "File upload failed".to_string())
-            }
-            AppError::ZipError(e) => {
-                tracing::error!("Zip creation error: {:?}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                )
-            }
-        };
-        (status, error_message).into_response()
-    }
+// Data model for a Hub, stored as JSON in Redis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hub {
+    pub id: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub files: Vec<FileInfo>,
+    // The whiteboard pages of the hub. Their elements are stored separately,
+    // see `whiteboard::page_key`.
+    #[serde(default)]
+    pub pages: Vec<PageInfo>,
+    // How many edits have been made to `content`. The most recent ones are
+    // stored separately, see `text::history_key`.
+    #[serde(default)]
+    pub text_revision: u64,
+    // How many times files have been uploaded to the hub.
+    #[serde(default)]
+    pub files_revision: u64,
+    // The named snippets of the hub, see `snippet::snippet_key`.
+    #[serde(default)]
+    pub snippets: Vec<SnippetInfo>,
+    // How many times snippets have been written or deleted.
+    #[serde(default)]
+    pub snippets_revision: u64,
+    // The strokes of hubs stored before they had pages, which are moved to
+    // a first page when the hub is loaded, see `load_hub`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub whiteboard: Vec<PathData>,
+    // The language `content` is highlighted as, see `language::LANGUAGES`.
+    // Detected from the text if not chosen.
+    #[serde(default)]
+    pub language: Option<String>,
 }
 
-// `From` trait implementations to allow using the `?` operator on different error types.
-impl From<deadpool_redis::PoolError> for AppError {
-    fn from(err: deadpool_redis::PoolError) -> Self {
-        AppError::PoolError(err)
+impl Hub {
+    /// The entity tag of the hub, which changes whenever its text, its
+    /// files, its whiteboard pages or its snippets do.
+    pub fn etag(&self) -> String {
+        format!(
+            "\"{}-{}-{}-{}\"",
+            self.text_revision,
+            self.files_revision,
+            self.pages.len(),
+            self.snippets_revision
+        )
     }
 }
-impl From<redis::RedisError> for AppError {
-    fn from(err: redis::RedisError) -> Self {
-        AppError::RedisError(err)
-    }
+
+/// Whether the `If-Match` header of a request that changes a hub matches
+/// the hub's current entity tag. Requests without one always match.
+fn if_match(headers: &HeaderMap, hub: &Hub) -> bool {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return true;
+    };
+    // `If-Match` compares strongly, so weak tags never match.
+    let etag = hub.etag();
+    value.to_str().is_ok_and(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == etag)
+    })
 }
-impl From<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>>
-    for AppError
-{
-    fn from(
-        err: aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::put_object::PutObjectError>,
-    ) -> Self {
-        AppError::S3PutError(err)
-    }
+
+// Whether the client asked for JSON, like the web app and the CLI do.
+// Everyone else, e.g. `curl`, gets plain text.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"))
 }
-impl From<aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>>
-    for AppError
-{
-    fn from(
-        err: aws_sdk_s3::error::SdkError<aws_sdk_s3::operation::get_object::GetObjectError>,
-    ) -> Self {
-        AppError::S3GetError(err)
-    }
+
+// Escapes everything but unreserved characters, so any file name makes a
+// single path segment.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
 }
-impl From<zip::result::ZipError> for AppError {
-    fn from(err: zip::result::ZipError) -> Self {
-        AppError::ZipError(err)
-    }
+
+// The direct URL of an uploaded file.
+fn file_url(base_url: &str, hub_id: &str, filename: &str) -> String {
+    format!(
+        "{}/api/hubs/{}/files/{}",
+        base_url,
+        hub_id,
+        percent_encode(filename)
+    )
 }
-impl From<io::Error> for AppError {
-    fn from(err: io::Error) -> Self {
-        AppError::IoError(err)
+
+// A `Content-Disposition` header for a file, `attachment` for anything that
+// must be saved rather than shown. Any file name is kept in the `filename*`
+// parameter, with a plain ASCII fallback for old clients.
+fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        fallback,
+        percent_encode(filename)
+    )
+}
+
+// Loads a hub from Redis.
+async fn load_hub(conn: &mut RedisConnection, id: &str) -> Result<Hub, AppError> {
+    let hub_json: Option<String> = conn.get(format!("hub:{}", id)).await?;
+    let hub: Hub = parse_stored(&hub_json.ok_or(AppError::NotFound("Hub"))?)?;
+    if hub.pages.is_empty() {
+        return add_first_page(conn, hub).await;
     }
+    Ok(hub)
 }
 
-// Data model for a Hub, stored as JSON in Redis.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Hub {
-    pub id: String,
-    pub content: String,
-    pub created_at: DateTime<Utc>,
-    pub files: Vec<FileInfo>,
-    pub whiteboard: Vec<PathData>,
+// The page hubs stored before they had pages get. Its id is always the same,
+// so that loading such a hub twice at once adds the same page.
+const FIRST_PAGE_ID: &str = "first";
+
+// Moves the strokes of a hub stored before hubs had pages to a page of its
+// own, so that it can be drawn on again. A page stored by an earlier load
+// is kept, along with anything drawn on it since.
+async fn add_first_page(conn: &mut RedisConnection, mut hub: Hub) -> Result<Hub, AppError> {
+    let paths = std::mem::take(&mut hub.whiteboard);
+    let page = WhiteboardPage::from_paths(FIRST_PAGE_ID, paths, hub.created_at);
+    hub.pages.push(PageInfo {
+        id: FIRST_PAGE_ID.to_string(),
+        name: String::from("Page 1"),
+    });
+
+    // The page expires together with the hub.
+    let key = format!("hub:{}", hub.id);
+    let ttl: isize = conn.ttl(&key).await?;
+    if ttl > 0 {
+        redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(page_key(&hub.id, FIRST_PAGE_ID))
+            .arg(serde_json::to_string(&page)?)
+            .arg("EX")
+            .arg(ttl)
+            .arg("NX")
+            .ignore()
+            .cmd("SET")
+            .arg(&key)
+            .arg(serde_json::to_string(&hub)?)
+            .arg("EX")
+            .arg(ttl)
+            .ignore()
+            .query_async::<()>(conn)
+            .await?;
+    }
+    debug!("Added a first whiteboard page to hub id: {}", hub.id);
+    Ok(hub)
 }
 
 // Data model for file metadata.
@@ -155,149 +215,623 @@ pub struct CreateHubResponse {
     expires_at: String,
 }
 
+// The query parameters for the create_hub handler.
+#[derive(Deserialize)]
+pub struct CreateHubQuery {
+    // How long the hub lives, in seconds, within the configured bounds.
+    ttl: Option<u64>,
+}
+
 /// Handler to create a new hub.
+/// A request body becomes the hub's text, so that `curl -T notes.txt` or
+/// `curl --data-binary @notes.txt` share a file in one go. Clients that
+/// don't accept JSON get the hub's URL as plain text.
 pub async fn create_hub(
     State(state): State<AppState>,
-) -> Result<(StatusCode, Json<CreateHubResponse>), AppError> {
+    Query(query): Query<CreateHubQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, AppError> {
+    let ttl = &state.settings.ttl;
+    let ttl_seconds = query.ttl.unwrap_or(ttl.default_secs);
+    if !(ttl.min_secs..=ttl.max_secs).contains(&ttl_seconds) {
+        return Err(AppError::InvalidTtl {
+            min_secs: ttl.min_secs,
+            max_secs: ttl.max_secs,
+        });
+    }
+    let max_bytes = state.settings.limits.max_text_bytes;
+    if body.len() > max_bytes {
+        return Err(AppError::TextTooLarge(max_bytes));
+    }
     let mut conn = state.redis.get().await?;
 
     let id = nanoid!(10);
     let now = Utc::now();
 
+    let first_page = PageInfo {
+        id: nanoid!(8),
+        name: String::from("Page 1"),
+    };
+
+    let uploaded_text = !body.is_empty();
     let hub = Hub {
         id: id.clone(),
-        content: String::from("Welcome to your ephemeral hub!"),
+        content: if body.is_empty() {
+            String::from("Welcome to your ephemeral hub!")
+        } else {
+            body
+        },
         created_at: now,
         files: Vec::new(),
+        pages: vec![first_page.clone()],
+        text_revision: 0,
+        files_revision: 0,
+        snippets: Vec::new(),
+        snippets_revision: 0,
         whiteboard: Vec::new(),
+        language: None,
     };
 
-    let hub_json = serde_json::to_string(&hub).unwrap();
-    let ttl_seconds = Duration::hours(24).num_seconds() as usize;
+    let hub_json = serde_json::to_string(&hub)?;
+    let page_json = serde_json::to_string(&WhiteboardPage::default())?;
 
     redis::cmd("SET")
         .arg(format!("hub:{}", id))
         .arg(hub_json)
         .arg("EX")
         .arg(ttl_seconds)
-        .query_async::<()>(&mut *conn)
+        .query_async::<()>(&mut conn)
         .await?;
+    redis::cmd("SET")
+        .arg(page_key(&id, &first_page.id))
+        .arg(page_json)
+        .arg("EX")
+        .arg(ttl_seconds)
+        .query_async::<()>(&mut conn)
+        .await?;
+    // Uploaded text is kept as the first revision, so it can be restored
+    // if it gets overwritten.
+    if uploaded_text {
+        let revisions = vec![TextRevision {
+            revision: 0,
+            at: now,
+            source: RevisionSource::Replaced,
+            content: hub.content.clone(),
+        }];
+        redis::cmd("SET")
+            .arg(text::revisions_key(&id))
+            .arg(serde_json::to_string(&revisions)?)
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async::<()>(&mut conn)
+            .await?;
+    }
+
+    let expires_at = now + Duration::seconds(ttl_seconds as i64);
+    // Active hubs are only tracked for `/metrics`.
+    if state.settings.metrics.enabled {
+        telemetry::record_hub_created(&mut conn, &id, expires_at.timestamp()).await?;
+    }
 
     debug!("Created new hub with id: {}", id);
-    let base_url = "http://localhost:3000";
-    let expires_at = now + Duration::hours(24);
+    let url = format!("{}/api/hubs/{}", state.settings.public_base_url, id);
 
+    if !wants_json(&headers) {
+        return Ok((StatusCode::CREATED, format!("{}\n", url)).into_response());
+    }
     Ok((
         StatusCode::CREATED,
         Json(CreateHubResponse {
             id: id.clone(),
-            url: format!("{}/api/hubs/{}", base_url, id),
-            text_url: format!("{}/api/hubs/{}/text", base_url, id),
+            text_url: format!("{}/text", url),
+            url,
             expires_at: expires_at.to_rfc3339(),
         }),
-    ))
+    )
+        .into_response())
 }
 
 /// Handler to get the content of a hub.
+/// The `ETag` header can be sent back as `If-Match` to only change the hub
+/// if nobody else has changed it in the meantime.
 pub async fn get_hub(
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> Result<Json<Hub>, AppError> {
+) -> Result<Response, AppError> {
     let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    Ok(([(header::ETAG, hub.etag())], Json(hub)).into_response())
+}
 
-    let key = format!("hub:{}", id);
-    let hub_json: Option<String> = conn.get(key).await?;
-
-    match hub_json {
-        Some(json) => {
-            let hub: Hub = serde_json::from_str(&json).unwrap();
-            Ok(Json(hub))
-        }
-        None => Err(AppError::NotFound),
-    }
+/// Handler to get the text bin of a hub as plain text, e.g. for `curl`.
+pub async fn get_text(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::ETAG, hub.etag()),
+        ],
+        hub.content,
+    )
+        .into_response())
 }
 
 /// Handler to update the text bin for a hub.
+/// The body replaces the whole text, and is merged with any edits being
+/// made to it at the same time in the web app.
 pub async fn update_text_bin(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
     body: String,
-) -> Result<StatusCode, AppError> {
+) -> Result<Response, AppError> {
+    let max_bytes = state.settings.limits.max_text_bytes;
+    if body.len() > max_bytes {
+        return Err(AppError::TextTooLarge(max_bytes));
+    }
+    let mut conn = state.redis.get().await?;
+
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let hub = load_hub(&mut conn, &id).await?;
+    if !if_match(&headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+    let (hub, update) = text::replace(&mut conn, hub, body, RevisionSource::Replaced).await?;
+    // Everyone editing the text in the web app gets the new text.
+    state.ws_state.broadcast(&id, &update).await;
+
+    debug!("Updated text for hub id: {}", id);
+    Ok((StatusCode::OK, [(header::ETAG, hub.etag())]).into_response())
+}
+
+/// Handler to list the past versions of a hub's text bin, oldest first.
+pub async fn get_text_revisions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<TextRevisionInfo>>, AppError> {
+    let mut conn = state.redis.get().await?;
+    load_hub(&mut conn, &id).await?;
+    let revisions = text::load_revisions(&mut conn, &id).await?;
+    Ok(Json(revisions.iter().map(TextRevisionInfo::from).collect()))
+}
+
+/// Handler to get a past version of a hub's text bin.
+pub async fn get_text_revision(
+    State(state): State<AppState>,
+    Path((id, revision)): Path<(String, u64)>,
+) -> Result<Json<TextRevision>, AppError> {
+    let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    Ok(Json(load_text_revision(&mut conn, &hub, revision).await?))
+}
+
+// The query parameters for the get_text_diff handler.
+#[derive(Deserialize)]
+pub struct DiffQuery {
+    from: u64,
+    // The current text if not given.
+    to: Option<u64>,
+}
+
+/// Handler to get a unified diff between two versions of a hub's text bin.
+pub async fn get_text_diff(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    let to = query.to.unwrap_or(hub.text_revision);
+    let old = load_text_revision(&mut conn, &hub, query.from).await?;
+    let new = load_text_revision(&mut conn, &hub, to).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        text::unified_diff(&old, &new),
+    )
+        .into_response())
+}
+
+/// Handler to bring back a past version of a hub's text bin.
+/// Restoring adds a new revision, so it can be undone by restoring again.
+pub async fn restore_text_revision(
+    State(state): State<AppState>,
+    Path((id, revision)): Path<(String, u64)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let hub = load_hub(&mut conn, &id).await?;
+    if !if_match(&headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+    let restored = load_text_revision(&mut conn, &hub, revision).await?;
+    let (hub, update) =
+        text::replace(&mut conn, hub, restored.content, RevisionSource::Restored).await?;
+    state.ws_state.broadcast(&id, &update).await;
+
+    debug!(
+        "Restored revision {} of the text for hub id: {}",
+        revision, id
+    );
+    Ok((StatusCode::OK, [(header::ETAG, hub.etag())]).into_response())
+}
+
+// Loads the past version of a hub's text bin that `revision` belongs to.
+async fn load_text_revision(
+    conn: &mut RedisConnection,
+    hub: &Hub,
+    revision: u64,
+) -> Result<TextRevision, AppError> {
+    let revisions = text::load_revisions(conn, &hub.id).await?;
+    text::find_revision(revisions, revision, hub.text_revision)
+        .ok_or(AppError::NotFound("Revision"))
+}
+
+// The request body for the set_text_language handler.
+#[derive(Deserialize)]
+pub struct SetLanguageRequest {
+    // `None` to detect the language from the text.
+    language: Option<String>,
+}
+
+/// Handler to choose the language a hub's text bin is highlighted as.
+pub async fn set_text_language(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<SetLanguageRequest>,
+) -> Result<Response, AppError> {
+    if let Some(language) = &request.language
+        && !language::is_known(language)
+    {
+        return Err(AppError::UnknownLanguage(language.clone()));
+    }
     let mut conn = state.redis.get().await?;
 
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let hub = load_hub(&mut conn, &id).await?;
+    if !if_match(&headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+    let (hub, operation) = text::set_language(&mut conn, hub, request.language.clone()).await?;
+
+    // The web app has to know about the new revision to keep editing.
+    let edit = WsMessage::TextEdit {
+        revision: hub.text_revision,
+        operation,
+        user_id: String::new(),
+    };
+    state.ws_state.broadcast(&id, &edit).await;
+    let update = WsMessage::TextLanguageChanged {
+        language: request.language,
+    };
+    state.ws_state.broadcast(&id, &update).await;
+
+    debug!("Set the text language for hub id: {}", id);
+    Ok((StatusCode::OK, [(header::ETAG, hub.etag())]).into_response())
+}
+
+// The request body for the create_page handler.
+#[derive(Deserialize)]
+pub struct CreatePageRequest {
+    name: String,
+}
+
+/// Handler to add a new whiteboard page to a hub.
+pub async fn create_page(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<CreatePageRequest>,
+) -> Result<(StatusCode, Json<PageInfo>), AppError> {
+    let mut conn = state.redis.get().await?;
     let key = format!("hub:{}", id);
-    let hub_json: Option<String> = conn.get(&key).await?;
 
-    if let Some(json) = hub_json {
-        let mut hub: Hub = serde_json::from_str(&json).unwrap();
-        hub.content = body;
-
-        let updated_json = serde_json::to_string(&hub).unwrap();
-        let ttl: isize = conn.ttl(&key).await?;
-
-        if ttl > 0 {
-            redis::cmd("SET")
-                .arg(&key)
-                .arg(updated_json)
-                .arg("EX")
-                .arg(ttl)
-                .query_async::<()>(&mut *conn)
-                .await?;
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let mut hub = load_hub(&mut conn, &id).await?;
+    if hub.pages.len() >= whiteboard::MAX_PAGES {
+        return Err(AppError::Validation(format!(
+            "a hub can't hold more than {} whiteboard pages",
+            whiteboard::MAX_PAGES
+        )));
+    }
+
+    let page = PageInfo {
+        id: nanoid!(8),
+        name: request.name,
+    };
+    hub.pages.push(page.clone());
+
+    // The page expires together with the hub.
+    let ttl: isize = conn.ttl(&key).await?;
+    if ttl > 0 {
+        let updated_json = serde_json::to_string(&hub)?;
+        let page_json = serde_json::to_string(&WhiteboardPage::default())?;
+        redis::cmd("SET")
+            .arg(page_key(&id, &page.id))
+            .arg(page_json)
+            .arg("EX")
+            .arg(ttl)
+            .query_async::<()>(&mut conn)
+            .await?;
+        redis::cmd("SET")
+            .arg(&key)
+            .arg(updated_json)
+            .arg("EX")
+            .arg(ttl)
+            .query_async::<()>(&mut conn)
+            .await?;
+    }
+
+    state
+        .ws_state
+        .broadcast(&id, &WsMessage::PageCreated(page.clone()))
+        .await;
+
+    debug!("Created whiteboard page {} for hub id: {}", page.id, id);
+    Ok((StatusCode::CREATED, Json(page)))
+}
+
+/// Handler to get the elements placed on a single whiteboard page.
+pub async fn get_page(
+    State(state): State<AppState>,
+    Path((id, page_id)): Path<(String, String)>,
+) -> Result<Json<Vec<BoardElement>>, AppError> {
+    let mut conn = state.redis.get().await?;
+
+    let page_json: Option<String> = conn.get(page_key(&id, &page_id)).await?;
+    let page: WhiteboardPage = parse_stored(&page_json.ok_or(AppError::NotFound("Page"))?)?;
+
+    Ok(Json(page.elements))
+}
+
+/// Handler to get the content of a snippet, served with its content type.
+pub async fn get_snippet(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    let info = hub
+        .snippets
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or(AppError::NotFound("Snippet"))?;
+
+    let content: Option<String> = conn.get(snippet_key(&id, &name)).await?;
+    Ok((
+        [(header::CONTENT_TYPE, info.content_type)],
+        content.unwrap_or_default(),
+    )
+        .into_response())
+}
+
+/// Handler to create or replace a snippet with the request body.
+/// The content type is taken from the request, or guessed from the name.
+pub async fn put_snippet(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, AppError> {
+    snippet::validate_name(&name).map_err(AppError::InvalidSnippet)?;
+    let max_bytes = state.settings.limits.max_text_bytes;
+    if body.len() > max_bytes {
+        return Err(AppError::TextTooLarge(max_bytes));
+    }
+    let mut conn = state.redis.get().await?;
+    let key = format!("hub:{}", id);
+
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let mut hub = load_hub(&mut conn, &id).await?;
+    if !if_match(&headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+
+    let info = SnippetInfo {
+        name: name.clone(),
+        content_type: snippet::content_type(&headers, &name),
+        size: body.len() as u64,
+    };
+    let status = match hub.snippets.iter().position(|s| s.name == name) {
+        Some(index) => {
+            hub.snippets[index] = info.clone();
+            StatusCode::OK
         }
+        None if hub.snippets.len() >= snippet::MAX_SNIPPETS => {
+            return Err(AppError::InvalidSnippet(format!(
+                "a hub can't hold more than {} snippets",
+                snippet::MAX_SNIPPETS
+            )));
+        }
+        None => {
+            hub.snippets.push(info.clone());
+            StatusCode::CREATED
+        }
+    };
+    hub.snippets_revision += 1;
 
-        debug!("Updated text for hub id: {}", id);
-        Ok(StatusCode::OK)
-    } else {
-        Err(AppError::NotFound)
+    // The snippet expires together with the hub.
+    let ttl: isize = conn.ttl(&key).await?;
+    if ttl > 0 {
+        redis::pipe()
+            .atomic()
+            .set_ex(snippet_key(&id, &name), &body, ttl as u64)
+            .set_ex(&key, serde_json::to_string(&hub)?, ttl as u64)
+            .query_async::<()>(&mut conn)
+            .await?;
     }
+
+    let update = WsMessage::SnippetUpdated {
+        snippet: info,
+        content: body,
+    };
+    state.ws_state.broadcast(&id, &update).await;
+
+    debug!("Wrote snippet {} for hub id: {}", name, id);
+    Ok((status, [(header::ETAG, hub.etag())]).into_response())
+}
+
+/// Handler to delete a snippet.
+pub async fn delete_snippet(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+    let key = format!("hub:{}", id);
+
+    let _lock = state.ws_state.lock_hub(&id).await;
+    let mut hub = load_hub(&mut conn, &id).await?;
+    if !if_match(&headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+    let index = hub
+        .snippets
+        .iter()
+        .position(|s| s.name == name)
+        .ok_or(AppError::NotFound("Snippet"))?;
+    hub.snippets.remove(index);
+    hub.snippets_revision += 1;
+
+    let ttl: isize = conn.ttl(&key).await?;
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(snippet_key(&id, &name));
+    if ttl > 0 {
+        pipe.set_ex(&key, serde_json::to_string(&hub)?, ttl as u64);
+    }
+    pipe.query_async::<()>(&mut conn).await?;
+
+    state
+        .ws_state
+        .broadcast(&id, &WsMessage::SnippetDeleted { name: name.clone() })
+        .await;
+
+    debug!("Deleted snippet {} for hub id: {}", name, id);
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, hub.etag())]).into_response())
+}
+
+// A file as listed in the response of the upload_file handler.
+#[derive(Serialize)]
+pub struct UploadedFile {
+    filename: String,
+    size: u64,
+    url: String,
 }
 
 /// Handler to upload one or more files to a hub.
+/// Replies with the direct URL of every file, one per line unless the client
+/// accepts JSON, so that `curl -F file=@notes.pdf` prints links to share.
 pub async fn upload_file(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<StatusCode, AppError> {
+) -> Result<Response, AppError> {
     let mut conn = state.redis.get().await?;
-    let key = format!("hub:{}", id);
 
-    // Get the hub metadata from Redis first.
-    let hub_json: Option<String> = conn.get(&key).await?;
-    let mut hub: Hub = serde_json::from_str(&hub_json.ok_or(AppError::NotFound)?).unwrap();
+    // Check the hub exists and hasn't changed before uploading anything.
+    if !if_match(&headers, &load_hub(&mut conn, &id).await?) {
+        return Err(AppError::PreconditionFailed);
+    }
+    // The files are staged under keys of their own, and only copied next to
+    // the hub's other files once it is known not to have changed meanwhile.
+    // This way the hub isn't locked during the upload, and a rejected
+    // upload doesn't overwrite any files.
+    let mut staged = Vec::new();
+    let stored = store_upload(&state, &id, &headers, &mut multipart, &mut staged).await;
+    discard_staged(&state, &staged).await;
+    let (hub, uploaded) = stored?;
+
+    let files: Vec<UploadedFile> = uploaded
+        .into_iter()
+        .map(|file| UploadedFile {
+            url: file_url(&state.settings.public_base_url, &id, &file.filename),
+            filename: file.filename,
+            size: file.size,
+        })
+        .collect();
+    let etag = [(header::ETAG, hub.etag())];
+    if wants_json(&headers) {
+        return Ok((StatusCode::OK, etag, Json(files)).into_response());
+    }
+    let urls: String = files.iter().map(|file| format!("{}\n", file.url)).collect();
+    Ok((StatusCode::OK, etag, urls).into_response())
+}
+
+// Stages every file of an upload, adding their keys to `staged`, then moves
+// them to where they're downloaded from and adds them to the hub. Returns
+// the updated hub and the uploaded files.
+async fn store_upload(
+    state: &AppState,
+    id: &str,
+    headers: &HeaderMap,
+    multipart: &mut Multipart,
+    staged: &mut Vec<String>,
+) -> Result<(Hub, Vec<FileInfo>), AppError> {
+    let bucket = &state.settings.s3.bucket;
+    let staging = format!("uploads/{}/{}", id, nanoid!(10));
+    let mut uploaded = Vec::new();
 
     // Iterate over each part of the multipart upload.
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    while let Some(field) = multipart.next_field().await? {
         let filename = field.file_name().unwrap_or("unknown_file").to_string();
-        let s3_key = format!("{}/{}", id, filename);
+        let staged_key = format!("{}/{}", staging, filename);
 
-        let data = field
-            .bytes()
-            .await
-            .map_err(|e| AppError::UploadError(e.to_string()))?;
+        let data = field.bytes().await?;
         let file_size = data.len() as u64;
 
         // Stream the file content to the S3 bucket.
         let body = ByteStream::from(data);
-
-        let bucket = env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set");
-        state
+        let request = state
             .s3
             .put_object()
-            .bucket(&bucket)
-            .key(&s3_key)
+            .bucket(bucket)
+            .key(&staged_key)
             .body(body)
-            .send()
-            .await?;
+            .send();
+        staged.push(staged_key);
+        telemetry::time_s3("put_object", request).await?;
+        counter!("ephemeral_upload_bytes_total").increment(file_size);
 
-        // Update the hub metadata with the new file info.
-        hub.files.push(FileInfo {
+        uploaded.push(FileInfo {
             filename,
             size: file_size,
         });
     }
 
+    // The hub may have changed during the upload, so it is loaded and
+    // checked again.
+    let mut conn = state.redis.get().await?;
+    let _lock = state.ws_state.lock_hub(id).await;
+    let mut hub = load_hub(&mut conn, id).await?;
+    if !if_match(headers, &hub) {
+        return Err(AppError::PreconditionFailed);
+    }
+    for (file, staged_key) in uploaded.iter().zip(staged.iter()) {
+        let request = state
+            .s3
+            .copy_object()
+            .bucket(bucket)
+            .copy_source(format!("{}/{}", bucket, percent_encode(staged_key)))
+            .key(format!("{}/{}", id, file.filename))
+            .send();
+        telemetry::time_s3("copy_object", request).await?;
+    }
+    hub.files.extend(uploaded.iter().cloned());
+    hub.files_revision += 1;
+
     // Save the updated hub metadata back to Redis, preserving the TTL.
-    let updated_json = serde_json::to_string(&hub).unwrap();
+    let key = format!("hub:{}", id);
+    let updated_json = serde_json::to_string(&hub)?;
     let ttl: isize = conn.ttl(&key).await?;
     if ttl > 0 {
         redis::cmd("SET")
@@ -305,11 +839,65 @@ pub async fn upload_file(
             .arg(updated_json)
             .arg("EX")
             .arg(ttl)
-            .query_async::<()>(&mut *conn)
+            .query_async::<()>(&mut conn)
             .await?;
     }
+    Ok((hub, uploaded))
+}
 
-    Ok(StatusCode::OK)
+// Deletes the staged files of an upload, whether it succeeded or not.
+// Failing to is only logged, as the upload itself is done.
+async fn discard_staged(state: &AppState, staged: &[String]) {
+    for staged_key in staged {
+        let request = state
+            .s3
+            .delete_object()
+            .bucket(&state.settings.s3.bucket)
+            .key(staged_key)
+            .send();
+        if let Err(e) = telemetry::time_s3("delete_object", request).await {
+            warn!("Failed to delete staged upload {}: {:?}", staged_key, e);
+        }
+    }
+}
+
+/// Handler to download a single file of a hub.
+pub async fn download_file(
+    State(state): State<AppState>,
+    Path((id, filename)): Path<(String, String)>,
+) -> Result<Response, AppError> {
+    let mut conn = state.redis.get().await?;
+    let hub = load_hub(&mut conn, &id).await?;
+    if !hub.files.iter().any(|f| f.filename == filename) {
+        return Err(AppError::NotFound("File"));
+    }
+
+    let bucket = &state.settings.s3.bucket;
+    let request = state
+        .s3
+        .get_object()
+        .bucket(bucket)
+        .key(format!("{}/{}", id, filename))
+        .send();
+    let object = telemetry::time_s3("get_object", request).await?;
+    let content_type = object
+        .content_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let data = object.body.collect().await?.into_bytes();
+    counter!("ephemeral_download_bytes_total").increment(data.len() as u64);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition("attachment", &filename),
+            ),
+        ],
+        data,
+    )
+        .into_response())
 }
 
 /// Handler to download all content of a hub as a single zip archive.
@@ -322,57 +910,274 @@ pub async fn download_files(
 
     // Get the hub metadata from Redis.
     let hub_json: Option<String> = conn.get(&key).await?;
-    let hub: Hub = serde_json::from_str(&hub_json.ok_or(AppError::NotFound)?).unwrap();
+    let hub: Hub = parse_stored(&hub_json.ok_or(AppError::NotFound("Hub"))?)?;
 
     // Create a zip archive in an in-memory buffer.
     let mut buffer = Vec::new();
     let cursor = Cursor::new(&mut buffer);
     let mut zip: ZipWriter<Cursor<&mut Vec<u8>>> = ZipWriter::new(cursor);
+    // Uploaded files may be named like the entries made for the hub, e.g. a
+    // re-uploaded `whiteboard.png`, so they are renamed if they clash.
+    let mut taken = HashSet::new();
 
-    // Add the text bin content to the zip.
-    zip.start_file("ephemeral_text_bin.txt", FileOptions::<()>::default())?;
+    // Add the text bin content to the zip, named after its language.
+    let text_language = hub
+        .language
+        .as_deref()
+        .unwrap_or_else(|| language::detect(&hub.content));
+    let text_name = format!("ephemeral_text_bin.{}", language::extension(text_language));
+    zip.start_file(
+        unique_entry_name(&mut taken, &text_name),
+        FileOptions::<()>::default(),
+    )?;
     zip.write_all(hub.content.as_bytes())?;
 
-    // Fetch each file from S3 and add it to the zip.
+    // Add every snippet as a file of its own.
+    for info in &hub.snippets {
+        let content: Option<String> = conn.get(snippet_key(&id, &info.name)).await?;
+        zip.start_file(
+            unique_entry_name(&mut taken, &format!("snippets/{}", info.name)),
+            FileOptions::<()>::default(),
+        )?;
+        zip.write_all(content.unwrap_or_default().as_bytes())?;
+    }
+
+    // Add an SVG and a PNG image of every whiteboard page.
+    for (index, page_info) in hub.pages.iter().enumerate() {
+        let page_json: Option<String> = conn.get(page_key(&id, &page_info.id)).await?;
+        let Some(page_json) = page_json else {
+            continue;
+        };
+        let page: WhiteboardPage = parse_stored(&page_json)?;
+        let name = export_file_name(index);
+
+        zip.start_file(
+            unique_entry_name(&mut taken, &format!("{}.svg", name)),
+            FileOptions::<()>::default(),
+        )?;
+        zip.write_all(export::to_svg(&page.elements).as_bytes())?;
+
+        let png = render_png(page.elements).await?;
+        zip.start_file(
+            unique_entry_name(&mut taken, &format!("{}.png", name)),
+            FileOptions::<()>::default(),
+        )?;
+        zip.write_all(&png)?;
+    }
+
+    // Fetch each file from S3 and add it to the zip. Files uploaded again
+    // under the same name are only stored once.
+    let mut seen = HashSet::new();
     for file_info in hub.files {
+        if !seen.insert(file_info.filename.clone()) {
+            continue;
+        }
         let s3_key = format!("{}/{}", id, file_info.filename);
-        let bucket = env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set");
-        let object = state
-            .s3
-            .get_object()
-            .bucket(&bucket)
-            .key(&s3_key)
-            .send()
-            .await?;
-        let data = object.body.collect().await.unwrap().into_bytes();
+        let bucket = &state.settings.s3.bucket;
+        let request = state.s3.get_object().bucket(bucket).key(&s3_key).send();
+        let object = telemetry::time_s3("get_object", request).await?;
+        let data = object.body.collect().await?.into_bytes();
 
-        zip.start_file(&file_info.filename, FileOptions::<()>::default())?;
+        zip.start_file(
+            unique_entry_name(&mut taken, &file_info.filename),
+            FileOptions::<()>::default(),
+        )?;
         zip.write_all(&data)?;
     }
 
     zip.finish()?;
 
-    // Manually build the HTTP response with the correct headers and body.
-    let body = Body::from(buffer);
     let filename = format!("ephemeral_hub_{}.zip", id);
+    counter!("ephemeral_download_bytes_total").increment(buffer.len() as u64);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition("attachment", &filename),
+            ),
+        ],
+        buffer,
+    )
+        .into_response())
+}
 
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/zip")
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(body)
-        .unwrap();
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    // The page to export; the first page of the hub if not given.
+    page: Option<String>,
+}
+
+/// Handler to export a whiteboard page as an SVG image.
+pub async fn export_whiteboard_svg(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (index, page) = load_export_page(&state, &id, query.page).await?;
+    let svg = export::to_svg(&page.elements);
+    Ok(image_response(
+        svg.into_bytes(),
+        "image/svg+xml",
+        format!("{}_{}.svg", export_file_name(index), id),
+    ))
+}
+
+/// Handler to export a whiteboard page as a PNG image.
+pub async fn export_whiteboard_png(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (index, page) = load_export_page(&state, &id, query.page).await?;
+    let png = render_png(page.elements).await?;
+    Ok(image_response(
+        png,
+        "image/png",
+        format!("{}_{}.png", export_file_name(index), id),
+    ))
+}
 
-    Ok(response)
+/// Handler to get the log of every change made to a whiteboard page, oldest first.
+pub async fn get_whiteboard_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Json<Vec<LoggedOperation>>, AppError> {
+    let (_, page) = load_export_page(&state, &id, query.page).await?;
+    Ok(Json(page.log))
 }
 
-pub async fn verify_bucket_access(
-    s3_client: &S3Client,
-    bucket_name: &str,
-) -> Result<(), SdkError<HeadBucketError>> {
-    s3_client.head_bucket().bucket(bucket_name).send().await?;
-    Ok(())
+/// Handler to export the replay of a whiteboard page as an animated SVG image.
+pub async fn export_whiteboard_replay(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (index, page) = load_export_page(&state, &id, query.page).await?;
+    let svg = export::to_animated_svg(&page.log);
+    Ok(image_response(
+        svg.into_bytes(),
+        "image/svg+xml",
+        format!("{}_replay_{}.svg", export_file_name(index), id),
+    ))
+}
+
+// Loads the whiteboard page to export, along with its position in the hub.
+async fn load_export_page(
+    state: &AppState,
+    id: &str,
+    page_id: Option<String>,
+) -> Result<(usize, WhiteboardPage), AppError> {
+    let mut conn = state.redis.get().await?;
+
+    let hub_json: Option<String> = conn.get(format!("hub:{}", id)).await?;
+    let hub: Hub = parse_stored(&hub_json.ok_or(AppError::NotFound("Hub"))?)?;
+
+    let index = match page_id {
+        Some(page_id) => hub.pages.iter().position(|p| p.id == page_id),
+        None => (!hub.pages.is_empty()).then_some(0),
+    }
+    .ok_or(AppError::NotFound("Page"))?;
+
+    let page_json: Option<String> = conn.get(page_key(id, &hub.pages[index].id)).await?;
+    let page: WhiteboardPage = parse_stored(&page_json.ok_or(AppError::NotFound("Page"))?)?;
+    Ok((index, page))
+}
+
+// Rasterizing is CPU heavy, so it is kept off the async runtime.
+async fn render_png(elements: Vec<BoardElement>) -> Result<Vec<u8>, AppError> {
+    tokio::task::spawn_blocking(move || export::to_png(&elements))
+        .await
+        .map_err(|e| AppError::RenderError(e.to_string()))?
+        .map_err(AppError::RenderError)
+}
+
+// Names a zip entry `name`, or `name (2)`, `name (3)` and so on if an
+// earlier entry took that name already, as zip archives can't hold two
+// entries of the same name. The number goes before the extension.
+fn unique_entry_name(taken: &mut HashSet<String>, name: &str) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty() && !stem.ends_with('/') && !extension.contains('/') =>
+        {
+            (stem, format!(".{}", extension))
+        }
+        _ => (name, String::new()),
+    };
+    let mut entry = name.to_string();
+    let mut copy = 2;
+    while !taken.insert(entry.clone()) {
+        entry = format!("{} ({}){}", stem, copy, extension);
+        copy += 1;
+    }
+    entry
+}
+
+// Exported pages are named `whiteboard`, `whiteboard_2`, `whiteboard_3` and so on.
+fn export_file_name(index: usize) -> String {
+    if index == 0 {
+        "whiteboard".to_string()
+    } else {
+        format!("whiteboard_{}", index + 1)
+    }
+}
+
+fn image_response(data: Vec<u8>, content_type: &str, filename: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition("inline", &filename),
+            ),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clashing_zip_entries_are_renamed() {
+        let mut taken = HashSet::new();
+        let names = [
+            "whiteboard.png",
+            "snippets/notes",
+            "whiteboard.png",
+            "whiteboard.png",
+            "snippets/notes",
+            "archive.tar.gz",
+            "archive.tar.gz",
+            ".env",
+            ".env",
+        ];
+        let entries: Vec<String> = names
+            .iter()
+            .map(|name| unique_entry_name(&mut taken, name))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "whiteboard.png",
+                "snippets/notes",
+                "whiteboard (2).png",
+                "whiteboard (3).png",
+                "snippets/notes (2)",
+                "archive.tar.gz",
+                "archive.tar (2).gz",
+                ".env",
+                ".env (2)",
+            ]
+        );
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for entry in entries {
+            zip.start_file(entry, FileOptions::<()>::default()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
 }
@@ -0,0 +1,90 @@
+use crate::{AppState, telemetry};
+use aws_sdk_s3::error::DisplayErrorContext;
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// The outcome of checking a dependency of the backend.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Whether the backend can serve requests, with the checks that decide it.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub redis: Check,
+    pub s3: Check,
+}
+
+/// Handler telling that the process is alive. It doesn't check anything
+/// else, so that a failing dependency doesn't get the process restarted.
+pub async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Handler telling whether the backend can reach Redis and S3, with
+/// `503 Service Unavailable` if it can't.
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let readiness = check(&state).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// Checks Redis and S3 at the same time, each within `health.timeout_ms`.
+pub async fn check(state: &AppState) -> Readiness {
+    let limit = Duration::from_millis(state.settings.health.timeout_ms);
+    let (redis, s3) = tokio::join!(
+        timed(limit, ping_redis(state)),
+        timed(limit, head_bucket(state))
+    );
+    Readiness {
+        ready: redis.ok && s3.ok,
+        redis,
+        s3,
+    }
+}
+
+async fn ping_redis(state: &AppState) -> Result<(), String> {
+    let mut conn = state.redis.get().await.map_err(|e| e.to_string())?;
+    redis::cmd("PING")
+        .query_async::<()>(&mut conn)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn head_bucket(state: &AppState) -> Result<(), String> {
+    let bucket = &state.settings.s3.bucket;
+    let request = state.s3.head_bucket().bucket(bucket).send();
+    telemetry::time_s3("head_bucket", request)
+        .await
+        .map(|_| ())
+        .map_err(|e| DisplayErrorContext(e).to_string())
+}
+
+async fn timed(limit: Duration, check: impl Future<Output = Result<(), String>>) -> Check {
+    let start = Instant::now();
+    let result = match timeout(limit, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {} ms", limit.as_millis())),
+    };
+    Check {
+        ok: result.is_ok(),
+        latency_ms: start.elapsed().as_millis(),
+        error: result.err(),
+    }
+}
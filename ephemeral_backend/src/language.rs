@@ -0,0 +1,124 @@
+/// The languages the text bin can be highlighted as, with the extension
+/// its file gets in a hub's zip archive.
+pub const LANGUAGES: &[(&str, &str)] = &[
+    ("plaintext", "txt"),
+    ("markdown", "md"),
+    ("rust", "rs"),
+    ("python", "py"),
+    ("javascript", "js"),
+    ("typescript", "ts"),
+    ("json", "json"),
+    ("yaml", "yaml"),
+    ("toml", "toml"),
+    ("html", "html"),
+    ("css", "css"),
+    ("shell", "sh"),
+    ("sql", "sql"),
+    ("go", "go"),
+    ("java", "java"),
+    ("c", "c"),
+    ("cpp", "cpp"),
+];
+
+pub fn is_known(language: &str) -> bool {
+    LANGUAGES.iter().any(|(id, _)| *id == language)
+}
+
+/// The file extension for a language, `txt` for unknown ones.
+pub fn extension(language: &str) -> &'static str {
+    LANGUAGES
+        .iter()
+        .find(|(id, _)| *id == language)
+        .map_or("txt", |(_, extension)| extension)
+}
+
+/// Guesses the language of a text from tell-tale syntax. The web app
+/// guesses the same way, so the two agree on what "auto" means.
+pub fn detect(text: &str) -> &'static str {
+    let trimmed = text.trim_start();
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let has = |needle: &str| text.contains(needle);
+    let lines_starting = |prefix: &str| lines.iter().filter(|l| l.starts_with(prefix)).count();
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        return "json";
+    }
+    if let Some(shebang) = trimmed.strip_prefix("#!") {
+        let interpreter = shebang.lines().next().unwrap_or_default();
+        return if interpreter.contains("python") {
+            "python"
+        } else if interpreter.contains("node") {
+            "javascript"
+        } else {
+            "shell"
+        };
+    }
+    let lower = trimmed.to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return "html";
+    }
+    if has("fn ") && (has("let ") || has("->") || has("::") || has("pub ")) {
+        return "rust";
+    }
+    if has("package main") || (has("func ") && has(":=")) {
+        return "go";
+    }
+    if has("public class ") || has("public static void ") {
+        return "java";
+    }
+    if lines_starting("#include") > 0 {
+        return if has("std::") || has("class ") || has("template<") {
+            "cpp"
+        } else {
+            "c"
+        };
+    }
+    if lines_starting("def ") > 0 || (lines_starting("import ") > 0 && !has(";")) {
+        return "python";
+    }
+    if has("interface ") || has(": string") || has(": number") {
+        return "typescript";
+    }
+    if has("function ") || has("const ") || has("=>") || has("console.log") {
+        return "javascript";
+    }
+    if ["select ", "insert into ", "create table ", "update "]
+        .iter()
+        .any(|keyword| lower.starts_with(keyword))
+    {
+        return "sql";
+    }
+    if lines
+        .iter()
+        .any(|l| l.starts_with('[') && l.ends_with(']') && !l.contains(','))
+        && lines.iter().any(|l| l.contains(" = "))
+    {
+        return "toml";
+    }
+    if has("{") && has("}") && has(";") && lines.iter().any(|l| l.ends_with('{')) {
+        return "css";
+    }
+    let keys = lines
+        .iter()
+        .filter(|l| {
+            l.split_once(':').is_some_and(|(key, _)| {
+                !key.is_empty()
+                    && key
+                        .trim_start_matches("- ")
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            })
+        })
+        .count();
+    if keys >= 2 && keys * 2 >= lines.iter().filter(|l| !l.is_empty()).count() {
+        return "yaml";
+    }
+    if lines_starting("# ") + lines_starting("## ") + lines_starting("```") + lines_starting("- ")
+        > 0
+    {
+        return "markdown";
+    }
+    "plaintext"
+}
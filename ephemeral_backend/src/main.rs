@@ -3,69 +3,88 @@ use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::{Client as S3Client, config::Region};
 use axum::{
     Router,
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post, put},
 };
 use deadpool_redis::{Config, Runtime};
-use std::{env, sync::Arc};
-use tokio::net::TcpListener;
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use settings::Settings;
+use std::{net::SocketAddr, process, sync::Arc, time::Duration};
+use telemetry::MakeRequestNanoid;
+use telemetry::RedisPool;
+use tokio::{net::TcpListener, sync::Notify};
+use tower_http::{
+    request_id::{PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::{info, warn};
 use websocket::AppWsState;
 
+mod error;
+mod export;
 mod handlers;
+mod health;
+mod language;
+mod ratelimit;
+mod security;
+mod settings;
 pub mod shared_types;
+mod snippet;
+mod stroke;
+mod telemetry;
+mod text;
 mod websocket;
+mod whiteboard;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub redis: deadpool_redis::Pool,
+    pub redis: RedisPool,
     pub s3: S3Client,
     pub ws_state: Arc<AppWsState>,
+    pub settings: Arc<Settings>,
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ephemeral_backend=debug,aws_config=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    // S3 Configuration
-    let aws_region_str = env::var("AWS_REGION").expect("AWS_REGION must be set");
-    let s3_bucket_name =
-        env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set in the environment");
-    let region_provider = RegionProviderChain::first_try(Region::new(aws_region_str));
+    // --- Configuration ---
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Invalid configuration:\n{}", e);
+            process::exit(1);
+        }
+    };
+    let tracer_provider = match telemetry::init_tracing(&settings.tracing) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Failed to set up tracing: {}", e);
+            process::exit(1);
+        }
+    };
+    if settings.metrics.enabled
+        && let Err(e) = telemetry::install()
+    {
+        eprintln!("Failed to set up metrics: {}", e);
+        process::exit(1);
+    }
+    let region_provider = RegionProviderChain::first_try(Region::new(settings.s3.region.clone()));
 
     // --- AWS S3 ---
-    let s3_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+    let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
         .region(region_provider)
         .load()
         .await;
+    let mut s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+        .force_path_style(settings.s3.force_path_style);
+    if let Some(endpoint) = &settings.s3.endpoint {
+        s3_config = s3_config.endpoint_url(endpoint);
+    }
 
-    let s3_client = S3Client::new(&s3_config);
+    let s3_client = S3Client::from_conf(s3_config.build());
     info!("S3 client configured.");
 
-    // Verify the application can access the configured bucket.
-    if let Err(e) = handlers::verify_bucket_access(&s3_client, &s3_bucket_name).await {
-        tracing::error!(
-            "Could not verify access to bucket '{}': {:?}",
-            s3_bucket_name,
-            e
-        );
-    } else {
-        info!(
-            "Successfully verified access to S3 bucket '{}'.",
-            s3_bucket_name
-        );
-    }
-
     // --- SETUP REDIS POOL---
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
-    let cfg = Config::from_url(redis_url);
+    let cfg = Config::from_url(settings.redis_url.clone());
     let redis_pool = cfg
         .create_pool(Some(Runtime::Tokio1))
         .expect("Failed to create Redis pool.");
@@ -76,28 +95,210 @@ async fn main() {
 
     // --- AppState Setup ---
     let app_state = AppState {
-        redis: redis_pool,
+        redis: RedisPool::new(redis_pool),
         s3: s3_client,
         ws_state,
+        settings: Arc::new(settings.clone()),
     };
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    // --- Startup Checks ---
+    let readiness = health::check(&app_state).await;
+    if readiness.ready {
+        info!(
+            "Verified access to Redis and S3 bucket '{}'.",
+            settings.s3.bucket
+        );
+    } else {
+        for (name, check) in [("Redis", &readiness.redis), ("S3", &readiness.s3)] {
+            if let Some(error) = &check.error {
+                tracing::error!("Could not reach {}: {}", name, error);
+            }
+        }
+        if settings.health.fail_fast {
+            process::exit(1);
+        }
+    }
 
-    let app = Router::new()
-        .route("/api/hubs", post(handlers::create_hub))
+    let mut routes = Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route(
+            "/api/hubs",
+            post(handlers::create_hub).put(handlers::create_hub),
+        )
         .route("/api/hubs/{id}", get(handlers::get_hub))
-        .route("/api/hubs/{id}/text", put(handlers::update_text_bin))
-        .route("/api/hubs/{id}/files", post(handlers::upload_file))
+        .route(
+            "/api/hubs/{id}/text",
+            get(handlers::get_text).put(handlers::update_text_bin),
+        )
+        .route("/api/hubs/{id}/raw", get(handlers::get_text))
+        .route(
+            "/api/hubs/{id}/text/revisions",
+            get(handlers::get_text_revisions),
+        )
+        .route(
+            "/api/hubs/{id}/text/revisions/{revision}",
+            get(handlers::get_text_revision),
+        )
+        .route(
+            "/api/hubs/{id}/text/revisions/{revision}/restore",
+            post(handlers::restore_text_revision),
+        )
+        .route("/api/hubs/{id}/text/diff", get(handlers::get_text_diff))
+        .route(
+            "/api/hubs/{id}/text/language",
+            put(handlers::set_text_language),
+        )
+        .route("/api/hubs/{id}/pages", post(handlers::create_page))
+        .route("/api/hubs/{id}/pages/{page_id}", get(handlers::get_page))
+        .route(
+            "/api/hubs/{id}/snippets/{name}",
+            get(handlers::get_snippet)
+                .put(handlers::put_snippet)
+                .delete(handlers::delete_snippet),
+        )
+        // Only uploads may be as large as a file, see the limit below.
+        .route(
+            "/api/hubs/{id}/files",
+            post(handlers::upload_file)
+                .layer(DefaultBodyLimit::max(settings.limits.max_upload_bytes)),
+        )
+        .route(
+            "/api/hubs/{id}/files/{filename}",
+            get(handlers::download_file),
+        )
         .route("/api/hubs/{id}/download", get(handlers::download_files))
-        .route("/ws/hubs/{id}", get(websocket::websocket_handler))
-        .with_state(app_state)
-        .layer(cors);
+        .route(
+            "/api/hubs/{id}/whiteboard.svg",
+            get(handlers::export_whiteboard_svg),
+        )
+        .route(
+            "/api/hubs/{id}/whiteboard.png",
+            get(handlers::export_whiteboard_png),
+        )
+        .route(
+            "/api/hubs/{id}/whiteboard/history",
+            get(handlers::get_whiteboard_history),
+        )
+        .route(
+            "/api/hubs/{id}/whiteboard/replay.svg",
+            get(handlers::export_whiteboard_replay),
+        )
+        .route("/ws/hubs/{id}", get(websocket::websocket_handler));
+
+    // --- Metrics ---
+    if settings.metrics.enabled {
+        let metrics = Router::new().route("/metrics", get(telemetry::render_metrics));
+        match settings.metrics.bind_address {
+            Some(address) => {
+                let listener = match TcpListener::bind(address).await {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        tracing::error!("Could not serve metrics on {}: {}", address, e);
+                        process::exit(1);
+                    }
+                };
+                info!("Serving metrics on {}", address);
+                let metrics = metrics.with_state(app_state.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = axum::serve(listener, metrics).await {
+                        tracing::error!("Metrics server failed: {}", e);
+                    }
+                });
+            }
+            None => routes = routes.merge(metrics),
+        }
+    }
+
+    let app = routes
+        .with_state(app_state.clone())
+        .layer(DefaultBodyLimit::max(settings.limits.max_text_bytes))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            ratelimit::rate_limit,
+        ))
+        .layer(middleware::from_fn(error::json_errors))
+        .layer(middleware::map_response_with_state(
+            app_state.settings.clone(),
+            security::security_headers,
+        ))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span)
+                .on_response(telemetry::record_response),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestNanoid))
+        .layer(security::cors_layer(&settings));
 
     // --- Server Launch ---
-    let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = TcpListener::bind(settings.bind_address).await.unwrap();
     info!("🚀 Server listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    // The address of each client is needed to rate limit it.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let stopping = Arc::new(Notify::new());
+    let server = axum::serve(listener, app).with_graceful_shutdown({
+        let (stopping, ws_state) = (stopping.clone(), app_state.ws_state.clone());
+        async move {
+            shutdown_signal().await;
+            info!("Shutting down, waiting for requests in flight to finish.");
+            ws_state.close_all().await;
+            stopping.notify_one();
+        }
+    });
+    let drained = async {
+        if let Err(e) = server.await {
+            tracing::error!("Server error: {}", e);
+        }
+        // WebSocket connections outlive the server, so they are waited for
+        // until they have been closed.
+        while app_state.ws_state.stats().await.1 > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    // Once stopping, requests like uploads get a while to finish before
+    // they're cut off.
+    let timeout = Duration::from_secs(settings.shutdown.timeout_secs);
+    tokio::select! {
+        _ = drained => info!("Shut down."),
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(timeout).await;
+        } => warn!("Requests still in flight after {:?}, exiting anyway.", timeout),
+    }
+
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        eprintln!("Failed to export the last spans: {}", e);
+    }
+}
+
+/// Resolves once the process is asked to stop, with SIGTERM or Ctrl+C.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
@@ -0,0 +1,247 @@
+use crate::{AppState, error::AppError, settings::RateLimit};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Method},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use redis::Script;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::LazyLock,
+};
+use tracing::warn;
+
+// Refills a token bucket for the time since it was last used and takes a
+// token from it. Returns 0 if there was one, or else how many milliseconds
+// until there is. Buckets expire once they would be full again.
+static TAKE_TOKEN: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local capacity = tonumber(ARGV[1])
+        local per_ms = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = time[1] * 1000 + math.floor(time[2] / 1000)
+
+        local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+        local tokens = tonumber(bucket[1]) or capacity
+        local at = tonumber(bucket[2]) or now
+        tokens = math.min(capacity, tokens + math.max(0, now - at) * per_ms)
+
+        local wait = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+        else
+            wait = math.ceil((1 - tokens) / per_ms)
+        end
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+        redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) / per_ms) + 1)
+        return wait
+        ",
+    )
+});
+
+/// What a request does, which decides the limit it counts against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    Create,
+    Write,
+    Upload,
+    WebSocket,
+}
+
+impl RouteClass {
+    // Reading is never limited.
+    fn of(method: &Method, path: &str) -> Option<RouteClass> {
+        if path.starts_with("/ws/") {
+            Some(RouteClass::WebSocket)
+        } else if *method == Method::GET || *method == Method::HEAD || *method == Method::OPTIONS {
+            None
+        } else if path == "/api/hubs" {
+            Some(RouteClass::Create)
+        } else if path.ends_with("/files") {
+            Some(RouteClass::Upload)
+        } else {
+            Some(RouteClass::Write)
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RouteClass::Create => "create",
+            RouteClass::Write => "write",
+            RouteClass::Upload => "upload",
+            RouteClass::WebSocket => "websocket",
+        }
+    }
+}
+
+/// The address of the client that made a request. Proxies in
+/// `trusted_proxies` append the address they received a request from to
+/// `X-Forwarded-For`, so it is read from the right until an address that
+/// isn't one of them. Anything further left could have been made up.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev();
+
+    let mut client = peer;
+    while is_trusted(&client) {
+        match forwarded.next().map(str::parse) {
+            Some(Ok(ip)) => client = ip,
+            _ => break,
+        }
+    }
+    client
+}
+
+/// Takes a token from a client's bucket for a class of requests, failing
+/// with `AppError::RateLimited` if it is empty. Requests are let through
+/// if Redis can't be reached, so limits never take the service down.
+pub async fn check(state: &AppState, class: RouteClass, ip: IpAddr) -> Result<(), AppError> {
+    let limits = &state.settings.rate_limits;
+    if !limits.enabled {
+        return Ok(());
+    }
+    let limit: RateLimit = match class {
+        RouteClass::Create => limits.create,
+        RouteClass::Write => limits.write,
+        RouteClass::Upload => limits.upload,
+        RouteClass::WebSocket => limits.websocket,
+    };
+
+    let key = format!("ratelimit:{}:{}", class.name(), ip);
+    let per_ms = f64::from(limit.per_minute) / 60_000.0;
+    let wait_ms: Result<u64, String> = async {
+        let mut conn = state.redis.get().await.map_err(|e| e.to_string())?;
+        TAKE_TOKEN
+            .key(&key)
+            .arg(limit.burst)
+            .arg(per_ms)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| e.to_string())
+    }
+    .await;
+
+    match wait_ms {
+        Ok(0) => Ok(()),
+        Ok(wait_ms) => Err(AppError::RateLimited {
+            retry_after_secs: wait_ms.div_ceil(1000),
+        }),
+        Err(e) => {
+            warn!("Failed to check the rate limit for {}: {}", ip, e);
+            Ok(())
+        }
+    }
+}
+
+/// Middleware limiting how fast each client can create and change hubs.
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(class) = RouteClass::of(request.method(), request.uri().path()) {
+        let trusted_proxies = &state.settings.rate_limits.trusted_proxies;
+        let ip = client_ip(peer.ip(), request.headers(), trusted_proxies);
+        check(&state, class, ip).await?;
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn made_up_addresses_are_ignored() {
+        let headers = forwarded_for(&["6.6.6.6, 1.2.3.4", "10.0.0.2"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies()),
+            ip("1.2.3.4")
+        );
+    }
+
+    #[test]
+    fn the_header_is_only_read_from_trusted_proxies() {
+        let headers = forwarded_for(&["1.2.3.4"]);
+        assert_eq!(
+            client_ip(ip("5.6.7.8"), &headers, &proxies()),
+            ip("5.6.7.8")
+        );
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn the_first_address_is_used_if_every_hop_is_trusted() {
+        let headers = forwarded_for(&["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies()),
+            ip("10.0.0.3")
+        );
+    }
+
+    #[test]
+    fn proxies_are_the_client_without_the_header() {
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &proxies()),
+            ip("10.0.0.1")
+        );
+        let garbled = forwarded_for(&["not an address"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &garbled, &proxies()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn requests_are_classified_by_what_they_do() {
+        let cases = [
+            (Method::GET, "/ws/abc", Some(RouteClass::WebSocket)),
+            (Method::GET, "/api/hubs/abc", None),
+            (Method::HEAD, "/api/hubs/abc/files", None),
+            (Method::OPTIONS, "/api/hubs", None),
+            (Method::POST, "/api/hubs", Some(RouteClass::Create)),
+            (
+                Method::POST,
+                "/api/hubs/abc/files",
+                Some(RouteClass::Upload),
+            ),
+            (Method::PUT, "/api/hubs/abc/text", Some(RouteClass::Write)),
+            (
+                Method::DELETE,
+                "/api/hubs/abc/snippets/a",
+                Some(RouteClass::Write),
+            ),
+        ];
+        for (method, path, class) in cases {
+            assert_eq!(RouteClass::of(&method, path), class, "{} {}", method, path);
+        }
+    }
+}
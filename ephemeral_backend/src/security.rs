@@ -0,0 +1,67 @@
+use crate::settings::Settings;
+use crate::telemetry::REQUEST_ID_HEADER;
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue, Method, header},
+    response::Response,
+};
+use std::{sync::Arc, time::Duration};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// The CORS policy for the web app: only `cors_origins` may call the API,
+/// with the methods and headers it uses.
+pub fn cors_layer(settings: &Settings) -> CorsLayer {
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::ACCEPT,
+            header::CONTENT_TYPE,
+            header::IF_MATCH,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        // Lets scripts in the web app read the headers describing a response.
+        .expose_headers([
+            header::ETAG,
+            header::RETRY_AFTER,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        .max_age(Duration::from_secs(60 * 60));
+
+    if settings.cors_origins.iter().any(|origin| origin == "*") {
+        return cors.allow_origin(AllowOrigin::any());
+    }
+    // Origins were validated when the settings were loaded.
+    let origins: Vec<HeaderValue> = settings
+        .cors_origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect();
+    cors.allow_origin(origins)
+        .allow_credentials(settings.cors_allow_credentials)
+}
+
+/// Middleware adding security headers to every response that doesn't set
+/// them itself.
+pub async fn security_headers(
+    State(settings): State<Arc<Settings>>,
+    mut response: Response,
+) -> Response {
+    let security = &settings.security;
+    let headers = response.headers_mut();
+    let mut set = |name: HeaderName, value: &str| {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.entry(name).or_insert(value);
+        }
+    };
+    set(
+        header::CONTENT_SECURITY_POLICY,
+        &security.content_security_policy,
+    );
+    set(header::REFERRER_POLICY, &security.referrer_policy);
+    // Browsers must use the content type we send instead of guessing one,
+    // so that an uploaded file can't turn into a page or a script.
+    set(header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+    // For browsers that don't support `frame-ancestors`.
+    set(header::X_FRAME_OPTIONS, "DENY");
+    response
+}
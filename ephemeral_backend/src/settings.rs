@@ -0,0 +1,433 @@
+use axum::http::HeaderValue;
+use figment::{
+    Figment,
+    providers::{Env, Format, Serialized, Toml},
+};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{env, net::SocketAddr};
+
+/// The configuration of the backend. Defaults are layered with a TOML file,
+/// `ephemeral.toml` or the one `EPHEMERAL_CONFIG` points to, and then with
+/// environment variables like `EPHEMERAL_S3__BUCKET` (`__` separates
+/// sections). Secrets such as AWS credentials only come from the environment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    /// The address the server listens on.
+    pub bind_address: SocketAddr,
+    /// The URL the API is reachable under, used in the links it returns.
+    pub public_base_url: String,
+    pub redis_url: String,
+    /// Origins the web app may call the API from, or `*` for any.
+    pub cors_origins: Vec<String>,
+    /// Whether browsers may send cookies along with requests from
+    /// `cors_origins`, which then can't be `*`.
+    pub cors_allow_credentials: bool,
+    pub security: SecuritySettings,
+    pub ttl: TtlSettings,
+    pub limits: LimitSettings,
+    pub s3: S3Settings,
+    pub rate_limits: RateLimitSettings,
+    pub metrics: MetricsSettings,
+    pub health: HealthSettings,
+    pub tracing: TracingSettings,
+    pub shutdown: ShutdownSettings,
+    pub websocket: WebSocketSettings,
+}
+
+/// How long hubs live, in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtlSettings {
+    pub default_secs: u64,
+    /// The bounds of the lifetime a hub can be created with.
+    pub min_secs: u64,
+    pub max_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitSettings {
+    /// The largest upload, which bounds uploaded files.
+    pub max_upload_bytes: usize,
+    /// The largest text bin or snippet, and the largest body of any other
+    /// request.
+    pub max_text_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Settings {
+    pub bucket: String,
+    pub region: String,
+    /// A custom endpoint, e.g. `http://127.0.0.1:9000` for MinIO.
+    pub endpoint: Option<String>,
+    /// Addresses buckets as `{endpoint}/{bucket}`, which MinIO needs.
+    pub force_path_style: bool,
+}
+
+/// Headers sent with every response, which keep content served by the API,
+/// like uploaded HTML, from running in a browser.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecuritySettings {
+    pub content_security_policy: String,
+    pub referrer_policy: String,
+}
+
+/// How Redis and S3 are checked for `/readyz` and on startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthSettings {
+    /// How long each check may take before it fails.
+    pub timeout_ms: u64,
+    /// Exit on startup if a check fails, instead of serving anyway and
+    /// reporting not ready until it passes.
+    pub fail_fast: bool,
+}
+
+/// How WebSocket connections are kept alive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketSettings {
+    /// How often clients are pinged.
+    pub ping_interval_secs: u64,
+    /// How long a client may send nothing, not even a pong, before its
+    /// connection is closed.
+    pub idle_timeout_secs: u64,
+}
+
+/// How the server stops on SIGTERM or Ctrl+C.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownSettings {
+    /// How long requests in flight, like uploads, may take to finish before
+    /// the server exits anyway.
+    pub timeout_secs: u64,
+}
+
+/// Where traces go besides the log. Log levels are set with `RUST_LOG`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TracingSettings {
+    /// An OpenTelemetry collector to export spans to over OTLP/HTTP, e.g.
+    /// `http://localhost:4318/v1/traces`.
+    pub otlp_endpoint: Option<String>,
+    /// The name the backend's spans are reported under.
+    pub service_name: String,
+}
+
+/// Prometheus metrics, served at `/metrics`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    /// A separate address to serve metrics on, e.g. `127.0.0.1:9100`, to
+    /// keep them private. They are served with the API if not set.
+    pub bind_address: Option<SocketAddr>,
+}
+
+/// Per-IP limits on how fast clients may make requests, shared by every
+/// replica through Redis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// Proxies, like a load balancer, whose `X-Forwarded-For` header is
+    /// trusted to name the client, e.g. `10.0.0.0/8`.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Creating hubs.
+    pub create: RateLimit,
+    /// Changing hubs, e.g. their text or snippets.
+    pub write: RateLimit,
+    /// Uploading files.
+    pub upload: RateLimit,
+    /// Connecting to a hub's WebSocket and every message sent over it.
+    pub websocket: RateLimit,
+}
+
+/// A token bucket: clients can make `burst` requests at once, and then
+/// `per_minute` requests a minute.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            public_base_url: "http://localhost:3000".to_string(),
+            redis_url: "redis://127.0.0.1/".to_string(),
+            cors_origins: vec![
+                "https://ephemeral-hub.com".to_string(),
+                "http://localhost:8080".to_string(),
+            ],
+            cors_allow_credentials: false,
+            security: SecuritySettings {
+                // Nothing served by the API is a page, so it may not load
+                // anything or be framed, and is sandboxed if opened anyway.
+                content_security_policy: "default-src 'none'; frame-ancestors 'none'; sandbox"
+                    .to_string(),
+                referrer_policy: "no-referrer".to_string(),
+            },
+            ttl: TtlSettings {
+                default_secs: 24 * 60 * 60,
+                min_secs: 5 * 60,
+                max_secs: 7 * 24 * 60 * 60,
+            },
+            limits: LimitSettings {
+                max_upload_bytes: 100 * 1024 * 1024,
+                max_text_bytes: 1024 * 1024,
+            },
+            s3: S3Settings {
+                bucket: String::new(),
+                region: String::new(),
+                endpoint: None,
+                force_path_style: false,
+            },
+            rate_limits: RateLimitSettings {
+                enabled: true,
+                trusted_proxies: Vec::new(),
+                create: RateLimit {
+                    burst: 10,
+                    per_minute: 10,
+                },
+                write: RateLimit {
+                    burst: 60,
+                    per_minute: 120,
+                },
+                upload: RateLimit {
+                    burst: 10,
+                    per_minute: 20,
+                },
+                websocket: RateLimit {
+                    burst: 300,
+                    per_minute: 3000,
+                },
+            },
+            metrics: MetricsSettings {
+                enabled: false,
+                bind_address: None,
+            },
+            health: HealthSettings {
+                timeout_ms: 2000,
+                fail_fast: false,
+            },
+            tracing: TracingSettings {
+                otlp_endpoint: None,
+                service_name: "ephemeral_backend".to_string(),
+            },
+            shutdown: ShutdownSettings { timeout_secs: 30 },
+            websocket: WebSocketSettings {
+                ping_interval_secs: 30,
+                idle_timeout_secs: 75,
+            },
+        }
+    }
+}
+
+impl Settings {
+    /// Loads and validates the configuration, describing what is wrong if
+    /// it can't be used.
+    pub fn load() -> Result<Settings, String> {
+        let path = env::var("EPHEMERAL_CONFIG").unwrap_or_else(|_| "ephemeral.toml".to_string());
+        let mut settings: Settings = Figment::from(Serialized::defaults(Settings::default()))
+            .merge(Toml::file(&path))
+            // The variables the backend has always been configured with.
+            .merge(
+                Env::raw()
+                    .only(&["REDIS_URL", "S3_BUCKET_NAME", "AWS_REGION"])
+                    .map(|key| match key.as_str() {
+                        "REDIS_URL" => "redis_url".into(),
+                        "S3_BUCKET_NAME" => "s3.bucket".into(),
+                        _ => "s3.region".into(),
+                    }),
+            )
+            .merge(Env::prefixed("EPHEMERAL_").split("__"))
+            .extract()
+            .map_err(|e| e.to_string())?;
+
+        settings.public_base_url = settings.public_base_url.trim_end_matches('/').to_string();
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Whether a browser page from `origin` may use the API.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.cors_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if self.s3.bucket.is_empty() {
+            errors.push("s3.bucket must be set (or S3_BUCKET_NAME)".to_string());
+        }
+        if self.s3.region.is_empty() {
+            errors.push("s3.region must be set (or AWS_REGION)".to_string());
+        }
+        if !self.public_base_url.starts_with("http://")
+            && !self.public_base_url.starts_with("https://")
+        {
+            errors.push(format!(
+                "public_base_url must start with http:// or https://, not `{}`",
+                self.public_base_url
+            ));
+        }
+        let ttl = &self.ttl;
+        if ttl.min_secs == 0 || ttl.min_secs > ttl.default_secs || ttl.default_secs > ttl.max_secs {
+            errors.push("ttl must satisfy 0 < min_secs <= default_secs <= max_secs".to_string());
+        }
+        if self.limits.max_upload_bytes == 0 || self.limits.max_text_bytes == 0 {
+            errors.push("limits must be greater than zero".to_string());
+        }
+        if self.limits.max_text_bytes > self.limits.max_upload_bytes {
+            errors.push("limits.max_text_bytes can't exceed limits.max_upload_bytes".to_string());
+        }
+        let limits = &self.rate_limits;
+        for (name, limit) in [
+            ("create", limits.create),
+            ("write", limits.write),
+            ("upload", limits.upload),
+            ("websocket", limits.websocket),
+        ] {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                errors.push(format!(
+                    "rate_limits.{} must allow at least one request",
+                    name
+                ));
+            }
+        }
+        if let Some(endpoint) = &self.tracing.otlp_endpoint
+            && !endpoint.starts_with("http://")
+            && !endpoint.starts_with("https://")
+        {
+            errors.push(format!(
+                "tracing.otlp_endpoint must start with http:// or https://, not `{}`",
+                endpoint
+            ));
+        }
+        if self.websocket.ping_interval_secs == 0 {
+            errors.push("websocket.ping_interval_secs must be greater than zero".to_string());
+        }
+        if self.websocket.idle_timeout_secs <= self.websocket.ping_interval_secs {
+            errors.push(
+                "websocket.idle_timeout_secs must be longer than websocket.ping_interval_secs"
+                    .to_string(),
+            );
+        }
+        if self.health.timeout_ms == 0 {
+            errors.push("health.timeout_ms must be greater than zero".to_string());
+        }
+        if self.metrics.bind_address == Some(self.bind_address) {
+            errors.push("metrics.bind_address must differ from bind_address".to_string());
+        }
+        let any_origin = self.cors_origins.iter().any(|origin| origin == "*");
+        if any_origin && self.cors_allow_credentials {
+            errors.push("cors_allow_credentials needs explicit cors_origins, not `*`".to_string());
+        }
+        for (name, value) in [
+            (
+                "security.content_security_policy",
+                &self.security.content_security_policy,
+            ),
+            ("security.referrer_policy", &self.security.referrer_policy),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                errors.push(format!("{} isn't a valid header value", name));
+            }
+        }
+        for origin in &self.cors_origins {
+            let is_url = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && HeaderValue::from_str(origin).is_ok();
+            if origin != "*" && !is_url {
+                errors.push(format!("cors_origins has an invalid origin `{}`", origin));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Settings {
+        let mut settings = Settings::default();
+        settings.s3.bucket = "bucket".to_string();
+        settings.s3.region = "eu-west-1".to_string();
+        settings
+    }
+
+    #[test]
+    fn the_defaults_are_valid_once_s3_is_set() {
+        assert!(valid().validate().is_ok());
+        assert!(Settings::default().validate().is_err());
+    }
+
+    #[test]
+    fn ttls_must_be_in_order() {
+        let mut settings = valid();
+        settings.ttl.min_secs = settings.ttl.default_secs + 1;
+        assert!(settings.validate().unwrap_err().contains("ttl"));
+
+        let mut settings = valid();
+        settings.ttl.max_secs = settings.ttl.default_secs - 1;
+        assert!(settings.validate().is_err());
+
+        let mut settings = valid();
+        settings.ttl.min_secs = 0;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn credentials_need_explicit_origins() {
+        let mut settings = valid();
+        settings.cors_origins = vec!["*".to_string()];
+        assert!(settings.validate().is_ok());
+
+        settings.cors_allow_credentials = true;
+        let error = settings.validate().unwrap_err();
+        assert!(error.contains("cors_allow_credentials"));
+    }
+
+    #[test]
+    fn otlp_endpoints_must_be_urls() {
+        let mut settings = valid();
+        settings.tracing.otlp_endpoint = Some("localhost:4318".to_string());
+        let error = settings.validate().unwrap_err();
+        assert!(error.contains("tracing.otlp_endpoint"));
+
+        settings.tracing.otlp_endpoint = Some("http://localhost:4318/v1/traces".to_string());
+        assert!(settings.validate().is_ok());
+    }
+
+    // The only test that changes the environment, as every test shares it.
+    #[test]
+    fn the_environment_overrides_the_config_file() {
+        let path = env::temp_dir().join(format!("ephemeral-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[s3]\nbucket = \"from-file\"\nregion = \"eu-west-1\"\n",
+        )
+        .unwrap();
+        // SAFETY: no other test reads or writes the environment.
+        unsafe {
+            env::set_var("EPHEMERAL_CONFIG", &path);
+            env::remove_var("AWS_REGION");
+            env::set_var("S3_BUCKET_NAME", "legacy");
+            env::set_var("EPHEMERAL_S3__BUCKET", "prefixed");
+        }
+        let prefixed = Settings::load();
+        unsafe { env::remove_var("EPHEMERAL_S3__BUCKET") };
+        let legacy = Settings::load();
+        unsafe { env::remove_var("S3_BUCKET_NAME") };
+        let from_file = Settings::load();
+        unsafe { env::remove_var("EPHEMERAL_CONFIG") };
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(prefixed.unwrap().s3.bucket, "prefixed");
+        assert_eq!(legacy.unwrap().s3.bucket, "legacy");
+        let from_file = from_file.unwrap();
+        assert_eq!(from_file.s3.bucket, "from-file");
+        assert_eq!(from_file.s3.region, "eu-west-1");
+    }
+}
@@ -1,16 +1,535 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
 // Data structure for a single drawing path.
 // This will be stored in Redis and sent over WebSockets.
+// Like all element coordinates, `points` are in world coordinates, which are
+// shared by every client regardless of its window size, pan or zoom.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct PathData {
     pub id: String,
-    pub points: Vec<(f64, f64)>,
+    // Stored and sent in a compact form, see `stroke::serialize_points`.
+    #[serde(
+        serialize_with = "crate::stroke::serialize_points",
+        deserialize_with = "crate::stroke::deserialize_points"
+    )]
+    pub points: Vec<StrokePoint>,
     pub color: String,
     pub stroke_width: f64,
+    // The user who drew the path. Older hubs don't store it.
+    #[serde(default)]
+    pub author: String,
+    // From 0.0 (invisible) to 1.0 (opaque).
+    #[serde(default = "default_opacity")]
+    pub opacity: f64,
+    // Highlighter strokes are blended with what is underneath them
+    // instead of covering it.
+    #[serde(default)]
+    pub highlighter: bool,
 }
+
+fn default_opacity() -> f64 {
+    1.0
+}
+
+// A point along a path. Points drawn with a pen also carry its pressure,
+// from 0.0 to 1.0, which makes the stroke thicker or thinner at that point.
+// Serialized as `[x, y]` or `[x, y, pressure]`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(from = "StrokePointRepr", into = "StrokePointRepr")]
+pub struct StrokePoint {
+    pub x: f64,
+    pub y: f64,
+    pub pressure: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum StrokePointRepr {
+    Plain(f64, f64),
+    WithPressure(f64, f64, f64),
+}
+
+impl From<StrokePointRepr> for StrokePoint {
+    fn from(repr: StrokePointRepr) -> Self {
+        match repr {
+            StrokePointRepr::Plain(x, y) => StrokePoint {
+                x,
+                y,
+                pressure: None,
+            },
+            StrokePointRepr::WithPressure(x, y, pressure) => StrokePoint {
+                x,
+                y,
+                pressure: Some(pressure),
+            },
+        }
+    }
+}
+
+impl From<StrokePoint> for StrokePointRepr {
+    fn from(point: StrokePoint) -> Self {
+        match point.pressure {
+            Some(pressure) => StrokePointRepr::WithPressure(point.x, point.y, pressure),
+            None => StrokePointRepr::Plain(point.x, point.y),
+        }
+    }
+}
+
+// A rectangle or an ellipse, described by its bounding box.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ShapeData {
+    pub id: String,
+    pub author: String,
+    pub bounds: Bounds,
+    pub color: String,
+    pub stroke_width: f64,
+}
+
+// A straight line or an arrow pointing from `start` to `end`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LineData {
+    pub id: String,
+    pub author: String,
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+    pub color: String,
+    pub stroke_width: f64,
+}
+
+// A free-standing text label. `position` is its top-left corner.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TextData {
+    pub id: String,
+    pub author: String,
+    pub position: (f64, f64),
+    pub text: String,
+    pub color: String,
+    pub font_size: f64,
+}
+
+// A colored sticky note with some text on it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StickyNoteData {
+    pub id: String,
+    pub author: String,
+    pub bounds: Bounds,
+    pub text: String,
+    pub color: String,
+}
+
+// An axis-aligned bounding box.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Bounds {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+// Anything that can be placed on a whiteboard page.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BoardElement {
+    Path(PathData),
+    Rectangle(ShapeData),
+    Ellipse(ShapeData),
+    Line(LineData),
+    Arrow(LineData),
+    Text(TextData),
+    StickyNote(StickyNoteData),
+}
+
+// Text labels don't know their rendered size, so it is estimated from the font size.
+const TEXT_LINE_HEIGHT: f64 = 1.2;
+const TEXT_CHAR_WIDTH: f64 = 0.6;
+
+impl BoardElement {
+    pub fn id(&self) -> &str {
+        match self {
+            BoardElement::Path(p) => &p.id,
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => &s.id,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => &l.id,
+            BoardElement::Text(t) => &t.id,
+            BoardElement::StickyNote(n) => &n.id,
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        match self {
+            BoardElement::Path(p) => &p.author,
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => &s.author,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => &l.author,
+            BoardElement::Text(t) => &t.author,
+            BoardElement::StickyNote(n) => &n.author,
+        }
+    }
+
+    // The smallest box containing the element.
+    pub fn bounds(&self) -> Bounds {
+        match self {
+            BoardElement::Path(p) => Bounds::around(p.points.iter().map(|pt| (pt.x, pt.y))),
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => s.bounds,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => Bounds::around([l.start, l.end]),
+            BoardElement::Text(t) => {
+                let longest_line = t.text.lines().map(|l| l.chars().count()).max();
+                let line_count = t.text.lines().count().max(1);
+                Bounds {
+                    x: t.position.0,
+                    y: t.position.1,
+                    width: longest_line.unwrap_or(0) as f64 * t.font_size * TEXT_CHAR_WIDTH,
+                    height: line_count as f64 * t.font_size * TEXT_LINE_HEIGHT,
+                }
+            }
+            BoardElement::StickyNote(n) => n.bounds,
+        }
+    }
+
+    // Moves the element so that the top-left corner of its bounds is at `(x, y)`.
+    pub fn move_to(&mut self, x: f64, y: f64) {
+        let target = Bounds {
+            x,
+            y,
+            ..self.bounds()
+        };
+        self.set_bounds(target);
+    }
+
+    // Moves and scales the element so that it fills `target`.
+    pub fn set_bounds(&mut self, target: Bounds) {
+        let current = self.bounds();
+        let map = |(x, y): (f64, f64)| current.map_point_to(&target, (x, y));
+        match self {
+            BoardElement::Path(p) => p.points.iter_mut().for_each(|pt| {
+                (pt.x, pt.y) = map((pt.x, pt.y));
+            }),
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => s.bounds = target,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => {
+                l.start = map(l.start);
+                l.end = map(l.end);
+            }
+            BoardElement::Text(t) => {
+                if current.height > 0.0 {
+                    t.font_size *= target.height / current.height;
+                }
+                t.position = (target.x, target.y);
+            }
+            BoardElement::StickyNote(n) => n.bounds = target,
+        }
+    }
+}
+
+impl Bounds {
+    // The smallest box containing all the given points.
+    pub fn around(points: impl IntoIterator<Item = (f64, f64)>) -> Bounds {
+        let mut points = points.into_iter();
+        let Some((x, y)) = points.next() else {
+            return Bounds {
+                x: 0.0,
+                y: 0.0,
+                width: 0.0,
+                height: 0.0,
+            };
+        };
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+        for (x, y) in points {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        Bounds {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
+
+    // Maps a point inside this box to the same relative position inside `target`.
+    // Degenerate (zero-sized) axes are only translated.
+    fn map_point_to(&self, target: &Bounds, (x, y): (f64, f64)) -> (f64, f64) {
+        let scale_x = if self.width > 0.0 {
+            target.width / self.width
+        } else {
+            1.0
+        };
+        let scale_y = if self.height > 0.0 {
+            target.height / self.height
+        } else {
+            1.0
+        };
+        (
+            target.x + (x - self.x) * scale_x,
+            target.y + (y - self.y) * scale_y,
+        )
+    }
+}
+
+// Metadata for a single whiteboard page (tab) of a hub.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PageInfo {
+    pub id: String,
+    pub name: String,
+}
+
+// A named text snippet of a hub, e.g. `config.yaml`. Its content is stored
+// separately, see `snippet::snippet_key`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SnippetInfo {
+    pub name: String,
+    pub content_type: String,
+    // The size of the content in bytes.
+    pub size: u64,
+}
+
 // Message format for WebSocket communication.
+// Whiteboard messages carry the id of the page they apply to.
+// `Undo` and `Redo` are only sent by clients; the server answers them by
+// broadcasting the resulting edits. `PageCreated`, `TextSnapshot`,
+// `TextUpdated`, `TextLanguageChanged`, `SnippetUpdated`, `SnippetDeleted`
+// and `ServerRestarting` are only sent by the server.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum WsMessage {
-    PathCompleted(PathData),
+    ElementAdded {
+        page_id: String,
+        element: BoardElement,
+    },
+    ElementDeleted {
+        page_id: String,
+        id: String,
+        user_id: String,
+    },
+    // Moves the top-left corner of the element's bounds to `(x, y)`.
+    ElementMoved {
+        page_id: String,
+        id: String,
+        user_id: String,
+        x: f64,
+        y: f64,
+    },
+    ElementResized {
+        page_id: String,
+        id: String,
+        user_id: String,
+        bounds: Bounds,
+    },
+    BoardCleared {
+        page_id: String,
+        user_id: String,
+    },
+    Undo {
+        page_id: String,
+        user_id: String,
+    },
+    Redo {
+        page_id: String,
+        user_id: String,
+    },
+    PageCreated(PageInfo),
+    // An edit to the text bin. Clients send the revision the edit was made
+    // on; the server broadcasts it transformed to the latest text, with the
+    // revision it produced.
+    TextEdit {
+        revision: u64,
+        operation: TextOperation,
+        user_id: String,
+    },
+    // Where a user's caret is in the text bin. `anchor` and `head` are the
+    // two ends of the selection, in characters, and equal without one.
+    TextCursor {
+        user_id: String,
+        anchor: usize,
+        head: usize,
+    },
+    // The whole text bin, sent to a client when it connects and whenever
+    // one of its edits can't be applied.
+    TextSnapshot {
+        content: String,
+        revision: u64,
+    },
+    // The whole text bin was replaced through the REST API. `operation` is
+    // the change as an edit of the revision before, for clients to merge
+    // with edits they haven't sent yet.
+    TextUpdated {
+        content: String,
+        revision: u64,
+        operation: TextOperation,
+    },
+    // A snippet was created or changed through the REST API.
+    SnippetUpdated {
+        snippet: SnippetInfo,
+        content: String,
+    },
+    SnippetDeleted {
+        name: String,
+    },
+    // The language the text bin is highlighted as was chosen, or set back to
+    // being detected if `None`.
+    TextLanguageChanged {
+        language: Option<String>,
+    },
+    // The server is shutting down, e.g. for a deploy. Clients should
+    // reconnect once the connection is closed.
+    ServerRestarting,
+}
+
+impl WsMessage {
+    // The whiteboard page this message applies to, if any.
+    pub fn page_id(&self) -> Option<&str> {
+        match self {
+            WsMessage::ElementAdded { page_id, .. }
+            | WsMessage::ElementDeleted { page_id, .. }
+            | WsMessage::ElementMoved { page_id, .. }
+            | WsMessage::ElementResized { page_id, .. }
+            | WsMessage::BoardCleared { page_id, .. }
+            | WsMessage::Undo { page_id, .. }
+            | WsMessage::Redo { page_id, .. } => Some(page_id),
+            WsMessage::PageCreated(_)
+            | WsMessage::TextEdit { .. }
+            | WsMessage::TextCursor { .. }
+            | WsMessage::TextSnapshot { .. }
+            | WsMessage::TextUpdated { .. }
+            | WsMessage::SnippetUpdated { .. }
+            | WsMessage::SnippetDeleted { .. }
+            | WsMessage::TextLanguageChanged { .. }
+            | WsMessage::ServerRestarting => None,
+        }
+    }
+}
+
+// A change to the text bin. Its components walk over the whole text from
+// start to end, keeping, inserting or deleting characters as they go, so an
+// operation only applies to a text of exactly the length it walks over.
+// Serialized as a list where a positive number keeps that many characters,
+// a negative number deletes them and a string is inserted.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(transparent)]
+pub struct TextOperation(pub Vec<TextComponent>);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "TextComponentRepr", into = "TextComponentRepr")]
+pub enum TextComponent {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TextComponentRepr {
+    Count(i64),
+    Insert(String),
+}
+
+impl TryFrom<TextComponentRepr> for TextComponent {
+    type Error = String;
+
+    fn try_from(repr: TextComponentRepr) -> Result<Self, Self::Error> {
+        match repr {
+            TextComponentRepr::Count(0) => Err("empty text component".to_string()),
+            TextComponentRepr::Count(n) if n > 0 => Ok(TextComponent::Retain(n as usize)),
+            TextComponentRepr::Count(n) => Ok(TextComponent::Delete(n.unsigned_abs() as usize)),
+            TextComponentRepr::Insert(text) if text.is_empty() => {
+                Err("empty text component".to_string())
+            }
+            TextComponentRepr::Insert(text) => Ok(TextComponent::Insert(text)),
+        }
+    }
+}
+
+impl From<TextComponent> for TextComponentRepr {
+    fn from(component: TextComponent) -> Self {
+        match component {
+            TextComponent::Retain(n) => TextComponentRepr::Count(n as i64),
+            TextComponent::Insert(text) => TextComponentRepr::Insert(text),
+            TextComponent::Delete(n) => TextComponentRepr::Count(-(n as i64)),
+        }
+    }
+}
+
+// A reversible whiteboard edit, remembered per user for undo/redo.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum WhiteboardAction {
+    Added(BoardElement),
+    Deleted(BoardElement),
+    Moved {
+        id: String,
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    Resized {
+        id: String,
+        before: Bounds,
+        after: Bounds,
+    },
+    Cleared(Vec<BoardElement>),
+}
+
+// A change made to a whiteboard page, as it was broadcast, with who made it
+// and when. A page's log of these can be replayed to show how it evolved.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LoggedOperation {
+    pub at: DateTime<Utc>,
+    pub user_id: String,
+    pub message: WsMessage,
+}
+
+// The undo and redo stacks of a single user.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct UndoHistory {
+    // Oldest first. The oldest actions are forgotten once there are too
+    // many, see `whiteboard::MAX_UNDO_DEPTH`.
+    pub undo: VecDeque<WhiteboardAction>,
+    pub redo: Vec<WhiteboardAction>,
+    // When the user last changed the page or undid something, to forget the
+    // histories of users who are long gone first.
+    #[serde(default)]
+    pub last_used: DateTime<Utc>,
+}
+
+// A past version of a hub's text bin. Consecutive live edits are grouped
+// into one revision, which is kept up to date until typing pauses. It
+// covers every `text_revision` up to the next one, see `text::find_revision`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TextRevision {
+    // The hub's `text_revision` when the text first read like this.
+    pub revision: u64,
+    pub at: DateTime<Utc>,
+    pub source: RevisionSource,
+    pub content: String,
+}
+
+// How the text of a revision was written.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RevisionSource {
+    // Edited in the web app.
+    Edited,
+    // Replaced through the API, e.g. with `ephemeral pipe`.
+    Replaced,
+    // Restored from an earlier revision.
+    Restored,
+}
+
+// A revision as listed in the history of a text bin, without its text.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TextRevisionInfo {
+    pub revision: u64,
+    pub at: DateTime<Utc>,
+    pub source: RevisionSource,
+    // The length of the text in characters.
+    pub length: usize,
+}
+
+impl From<&TextRevision> for TextRevisionInfo {
+    fn from(revision: &TextRevision) -> Self {
+        TextRevisionInfo {
+            revision: revision.revision,
+            at: revision.at,
+            source: revision.source,
+            length: revision.content.chars().count(),
+        }
+    }
 }
@@ -0,0 +1,67 @@
+use axum::http::{HeaderMap, header};
+
+/// How many snippets a single hub may hold.
+pub const MAX_SNIPPETS: usize = 20;
+
+const MAX_NAME_LENGTH: usize = 128;
+
+/// The Redis key the content of a snippet is stored under.
+pub fn snippet_key(hub_id: &str, name: &str) -> String {
+    format!("hub:{}:snippet:{}", hub_id, name)
+}
+
+/// Checks that a snippet name can be used as a file name, since snippets
+/// end up as files in the hub's zip archive.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("snippet names can't be empty, `.` or `..`".to_string());
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "snippet names can't be longer than {} bytes",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if name
+        .chars()
+        .any(|c| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err("snippet names can't contain slashes or control characters".to_string());
+    }
+    Ok(())
+}
+
+/// The content type of a snippet written with a request. Clients that
+/// don't say, or send a form or binary type by default like curl does, get
+/// one guessed from the snippet's extension.
+pub fn content_type(headers: &HeaderMap, name: &str) -> String {
+    let declared = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.starts_with("application/x-www-form-urlencoded")
+                && !value.starts_with("application/octet-stream")
+        });
+    match declared {
+        Some(content_type) => content_type.to_string(),
+        None => guess_content_type(name).to_string(),
+    }
+}
+
+fn guess_content_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("md" | "markdown") => "text/markdown",
+        Some("json") => "application/json",
+        Some("yaml" | "yml") => "application/yaml",
+        Some("toml") => "application/toml",
+        Some("xml") => "application/xml",
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("csv") => "text/csv",
+        Some("js") => "text/javascript",
+        _ => "text/plain",
+    }
+}
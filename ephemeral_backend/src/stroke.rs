@@ -0,0 +1,324 @@
+use crate::shared_types::StrokePoint;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// How finely point coordinates are stored: 10 means a tenth of a world unit.
+const POINT_SCALE: f64 = 10.0;
+
+/// Pen pressure is stored in steps of 1/255.
+const PRESSURE_SCALE: f64 = 255.0;
+
+/// How much a simplified path may differ from the drawn one, as a fraction
+/// of its stroke width. Anything well below the stroke width is invisible.
+pub const SIMPLIFY_TOLERANCE: f64 = 0.25;
+
+/// How much the pen pressure may differ from the drawn one after simplification.
+const PRESSURE_TOLERANCE: f64 = 0.05;
+
+/// Removes the points of a path that don't change its shape by more than
+/// `tolerance`, using the Ramer–Douglas–Peucker algorithm. The first and the
+/// last point are always kept, as are points where the pressure changes.
+pub fn simplify(points: &[StrokePoint], tolerance: f64) -> Vec<StrokePoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // Ranges of points still to be simplified, by their first and last index.
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let mut worst = (0, 1.0);
+        for i in first + 1..last {
+            let error = deviation(&points[i], &points[first], &points[last], tolerance);
+            if error > worst.1 {
+                worst = (i, error);
+            }
+        }
+        // Keep the point furthest from the segment if it is out of tolerance,
+        // and look at the two halves on either side of it.
+        if worst.0 != 0 {
+            keep[worst.0] = true;
+            ranges.push((first, worst.0));
+            ranges.push((worst.0, last));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect()
+}
+
+// How far `point` is from the segment between `start` and `end`, relative to
+// the tolerance. Values above 1.0 mean the point can't be dropped.
+fn deviation(point: &StrokePoint, start: &StrokePoint, end: &StrokePoint, tolerance: f64) -> f64 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let distance = (point.x - (start.x + t * dx)).hypot(point.y - (start.y + t * dy));
+
+    let pressure_error = match (point.pressure, start.pressure, end.pressure) {
+        (Some(p), Some(a), Some(b)) => (p - (a + t * (b - a))).abs(),
+        _ => 0.0,
+    };
+
+    (distance / tolerance).max(pressure_error / PRESSURE_TOLERANCE)
+}
+
+// The compact form of a path's points. Coordinates are quantized to
+// integers; the first point is absolute and every following one is stored
+// relative to the one before it, which keeps the numbers small.
+#[derive(Serialize, Deserialize)]
+struct EncodedPoints {
+    xy: Vec<i64>,
+    // Quantized pen pressure of every point, or empty for strokes without any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pressure: Vec<u8>,
+}
+
+// Paths stored before the compact encoding have a plain list of points.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPoints {
+    Encoded(EncodedPoints),
+    Plain(Vec<StrokePoint>),
+}
+
+/// Serializes the points of a path in their compact form.
+pub fn serialize_points<S: Serializer>(
+    points: &[StrokePoint],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    encode(points)
+        .map_err(serde::ser::Error::custom)?
+        .serialize(serializer)
+}
+
+/// Deserializes the points of a path, in either their compact or plain form.
+pub fn deserialize_points<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<StrokePoint>, D::Error> {
+    match StoredPoints::deserialize(deserializer)? {
+        StoredPoints::Encoded(encoded) => decode(&encoded).map_err(serde::de::Error::custom),
+        StoredPoints::Plain(points) => Ok(points),
+    }
+}
+
+// Fails for coordinates too far out to be stored, which would otherwise be
+// saturated or make the page impossible to read back.
+fn encode(points: &[StrokePoint]) -> Result<EncodedPoints, String> {
+    let mut xy = Vec::with_capacity(points.len() * 2);
+    let mut previous = (0, 0);
+    for point in points {
+        let current = (quantize(point.x)?, quantize(point.y)?);
+        xy.push(delta(current.0, previous.0)?);
+        xy.push(delta(current.1, previous.1)?);
+        previous = current;
+    }
+
+    // Points drawn without pressure are stored at half pressure, which is
+    // how they are rendered next to points that have it.
+    let pressure = if points.iter().any(|p| p.pressure.is_some()) {
+        points
+            .iter()
+            .map(|p| (p.pressure.unwrap_or(0.5).clamp(0.0, 1.0) * PRESSURE_SCALE).round() as u8)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(EncodedPoints { xy, pressure })
+}
+
+fn quantize(coordinate: f64) -> Result<i64, String> {
+    let scaled = (coordinate * POINT_SCALE).round();
+    // `i64::MAX as f64` rounds up to 2^63, which is out of range itself.
+    if scaled.is_finite() && scaled.abs() < i64::MAX as f64 {
+        Ok(scaled as i64)
+    } else {
+        Err(format!("coordinate {} out of range", coordinate))
+    }
+}
+
+fn delta(current: i64, previous: i64) -> Result<i64, String> {
+    current
+        .checked_sub(previous)
+        .ok_or_else(|| "coordinate out of range".to_string())
+}
+
+fn decode(encoded: &EncodedPoints) -> Result<Vec<StrokePoint>, String> {
+    if !encoded.xy.len().is_multiple_of(2) {
+        return Err("odd number of coordinates".to_string());
+    }
+    let count = encoded.xy.len() / 2;
+    if !encoded.pressure.is_empty() && encoded.pressure.len() != count {
+        return Err("pressure doesn't match the number of points".to_string());
+    }
+
+    let mut points = Vec::with_capacity(count);
+    let (mut x, mut y) = (0i64, 0i64);
+    for (i, delta) in encoded.xy.chunks_exact(2).enumerate() {
+        x = x.checked_add(delta[0]).ok_or("coordinate out of range")?;
+        y = y.checked_add(delta[1]).ok_or("coordinate out of range")?;
+        points.push(StrokePoint {
+            x: x as f64 / POINT_SCALE,
+            y: y as f64 / POINT_SCALE,
+            pressure: encoded
+                .pressure
+                .get(i)
+                .map(|&p| f64::from(p) / PRESSURE_SCALE),
+        });
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> StrokePoint {
+        StrokePoint {
+            x,
+            y,
+            pressure: None,
+        }
+    }
+
+    // A hand-drawn looking wave with some jitter, sampled like mouse events.
+    fn wavy_stroke() -> Vec<StrokePoint> {
+        (0..2000)
+            .map(|i| {
+                let t = i as f64 * 0.25;
+                point(t, 40.0 * (t / 30.0).sin() + 0.05 * (i % 3) as f64)
+            })
+            .collect()
+    }
+
+    // The largest distance from any point of `original` to the polyline `simplified`.
+    fn max_distance(original: &[StrokePoint], simplified: &[StrokePoint]) -> f64 {
+        original
+            .iter()
+            .map(|p| {
+                simplified
+                    .windows(2)
+                    .map(|w| deviation(p, &w[0], &w[1], 1.0))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn simplify_stays_within_tolerance() {
+        let original = wavy_stroke();
+        let simplified = simplify(&original, 0.5);
+
+        assert!(simplified.len() < original.len() / 10);
+        assert!(max_distance(&original, &simplified) <= 0.5);
+        assert_eq!(simplified.first(), original.first());
+        assert_eq!(simplified.last(), original.last());
+    }
+
+    #[test]
+    fn simplify_reduces_straight_lines_to_their_ends() {
+        let line: Vec<_> = (0..100).map(|i| point(i as f64, 2.0 * i as f64)).collect();
+        assert_eq!(simplify(&line, 0.1), vec![line[0], line[99]]);
+    }
+
+    #[test]
+    fn simplify_keeps_pressure_changes() {
+        let stroke: Vec<_> = (0..100)
+            .map(|i| StrokePoint {
+                x: i as f64,
+                y: 0.0,
+                pressure: Some(if i < 50 { 0.2 } else { 0.9 }),
+            })
+            .collect();
+        let simplified = simplify(&stroke, 1.0);
+
+        assert!(simplified.len() > 2);
+        assert!(simplified.len() < 10);
+    }
+
+    #[test]
+    fn encoding_round_trips_within_quantization() {
+        let original: Vec<_> = wavy_stroke()
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| StrokePoint {
+                pressure: Some((i % 100) as f64 / 100.0),
+                ..p
+            })
+            .collect();
+
+        let decoded = decode(&encode(&original).unwrap()).unwrap();
+
+        assert_eq!(decoded.len(), original.len());
+        for (a, b) in original.iter().zip(&decoded) {
+            assert!((a.x - b.x).abs() <= 0.5 / POINT_SCALE + 1e-9);
+            assert!((a.y - b.y).abs() <= 0.5 / POINT_SCALE + 1e-9);
+            assert!((a.pressure.unwrap() - b.pressure.unwrap()).abs() <= 0.5 / PRESSURE_SCALE);
+        }
+    }
+
+    #[test]
+    fn encoding_is_smaller_than_plain_json() {
+        let points = simplify(&wavy_stroke(), 0.5);
+        let encoded = serde_json::to_string(&encode(&points).unwrap()).unwrap();
+        let plain = serde_json::to_string(&points).unwrap();
+        assert!(encoded.len() * 2 < plain.len());
+    }
+
+    #[test]
+    fn plain_points_are_still_accepted() {
+        let mut deserializer = serde_json::Deserializer::from_str("[[1.5,2.0],[3.0,4.0,0.5]]");
+        let points = deserialize_points(&mut deserializer).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                point(1.5, 2.0),
+                StrokePoint {
+                    x: 3.0,
+                    y: 4.0,
+                    pressure: Some(0.5),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_encodings_are_rejected() {
+        let odd = EncodedPoints {
+            xy: vec![1, 2, 3],
+            pressure: Vec::new(),
+        };
+        assert!(decode(&odd).is_err());
+
+        let overflow = EncodedPoints {
+            xy: vec![i64::MAX, 0, 1, 0],
+            pressure: Vec::new(),
+        };
+        assert!(decode(&overflow).is_err());
+    }
+
+    #[test]
+    fn extreme_coordinates_are_rejected() {
+        assert!(encode(&[point(1e300, 0.0)]).is_err());
+        assert!(encode(&[point(0.0, f64::NAN)]).is_err());
+        // Each is in range, but the step between them isn't.
+        assert!(encode(&[point(9e17, 0.0), point(-9e17, 0.0)]).is_err());
+        assert!(serde_json::to_string(&EncodedPath(vec![point(1e300, 1e300)])).is_err());
+
+        let far = [point(1e9, -1e9), point(-1e9, 1e9)];
+        assert_eq!(decode(&encode(&far).unwrap()).unwrap(), far);
+    }
+
+    #[derive(Serialize)]
+    struct EncodedPath(#[serde(serialize_with = "serialize_points")] Vec<StrokePoint>);
+}
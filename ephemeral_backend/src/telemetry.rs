@@ -0,0 +1,236 @@
+use crate::{AppState, error::AppError, settings::TracingSettings};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use nanoid::nanoid;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use redis::{AsyncCommands, Cmd, Pipeline, RedisFuture, Value, aio::ConnectionLike};
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use tracing::{Span, field};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// The header requests are identified by in logs, spans and error bodies.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// The hubs that haven't expired yet, scored by when they expire. Hubs are
+// spread over many keys, so this is cheaper than counting their keys.
+const HUB_EXPIRY_KEY: &str = "hubs:expiry";
+
+// Latencies of Redis and S3 requests, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Sets up logging and, if an OTLP endpoint is configured, exporting spans.
+/// The returned provider has to be shut down on exit to export the last
+/// spans.
+pub fn init_tracing(settings: &TracingSettings) -> Result<Option<SdkTracerProvider>, String> {
+    let provider = match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .map_err(|e| e.to_string())?;
+            let resource = Resource::builder()
+                .with_service_name(settings.service_name.clone())
+                .build();
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(resource)
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    let otlp = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("ephemeral")));
+
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "ephemeral_backend=debug,aws_config=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp)
+        .init();
+    Ok(provider)
+}
+
+/// Gives requests without an `X-Request-Id` a random one.
+#[derive(Clone, Copy, Default)]
+pub struct MakeRequestNanoid;
+
+impl MakeRequestId for MakeRequestNanoid {
+    fn make_request_id<B>(&mut self, _request: &axum::http::Request<B>) -> Option<RequestId> {
+        HeaderValue::from_str(&nanoid!(12)).ok().map(RequestId::new)
+    }
+}
+
+/// The ID of a request, see `REQUEST_ID_HEADER`.
+pub fn request_id<B>(request: &axum::http::Request<B>) -> &str {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The hub a request is about, if any, from paths like `/api/hubs/{id}/text`.
+pub fn hub_id(path: &str) -> Option<&str> {
+    let mut segments = path.split('/');
+    segments.find(|segment| *segment == "hubs")?;
+    segments.next().filter(|id| !id.is_empty())
+}
+
+/// The span each request is handled in. Its status and latency are
+/// recorded once it has been answered, see `record_response`.
+pub fn request_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(request.uri().path(), MatchedPath::as_str);
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        hub_id = hub_id(request.uri().path()).unwrap_or_default(),
+        request_id = request_id(request),
+        status = field::Empty,
+        latency_ms = field::Empty,
+    )
+}
+
+pub fn record_response(response: &Response, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    tracing::debug!("finished request");
+}
+
+/// Starts collecting metrics. Until this is called, recording them does
+/// nothing.
+pub fn install() -> Result<(), String> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("duration_seconds".to_string()),
+            LATENCY_BUCKETS,
+        )
+        .and_then(PrometheusBuilder::install_recorder)
+        .map_err(|e| e.to_string())?;
+    let _ = PROMETHEUS.set(handle);
+    Ok(())
+}
+
+/// Handler to render the metrics in the Prometheus text format.
+pub async fn render_metrics(State(state): State<AppState>) -> Result<Response, AppError> {
+    let Some(prometheus) = PROMETHEUS.get() else {
+        return Err(AppError::NotFound("Metrics"));
+    };
+
+    // Gauges describing the current state are updated when scraped.
+    let (rooms, connections) = state.ws_state.stats().await;
+    gauge!("ephemeral_websocket_rooms").set(rooms as f64);
+    gauge!("ephemeral_websocket_connections").set(connections as f64);
+
+    let mut conn = state.redis.get().await?;
+    let now = chrono::Utc::now().timestamp();
+    let _: () = conn.zrembyscore(HUB_EXPIRY_KEY, "-inf", now).await?;
+    let active_hubs: u64 = conn.zcard(HUB_EXPIRY_KEY).await?;
+    gauge!("ephemeral_active_hubs").set(active_hubs as f64);
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        prometheus.render(),
+    )
+        .into_response())
+}
+
+/// Counts a new hub as active until it expires. Hubs that have expired
+/// meanwhile are forgotten, so that the set doesn't grow between scrapes.
+pub async fn record_hub_created(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+    expires_at: i64,
+) -> Result<(), AppError> {
+    counter!("ephemeral_hubs_created_total").increment(1);
+    let now = chrono::Utc::now().timestamp();
+    let _: () = redis::pipe()
+        .zrembyscore(HUB_EXPIRY_KEY, "-inf", now)
+        .ignore()
+        .zadd(HUB_EXPIRY_KEY, hub_id, expires_at)
+        .ignore()
+        .query_async(conn)
+        .await?;
+    Ok(())
+}
+
+/// Times a request to S3, like `get_object`.
+pub async fn time_s3<T>(operation: &'static str, request: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = request.await;
+    histogram!("ephemeral_s3_request_duration_seconds", "operation" => operation)
+        .record(start.elapsed().as_secs_f64());
+    result
+}
+
+/// The Redis connection pool of the backend, which times every command.
+#[derive(Clone)]
+pub struct RedisPool(deadpool_redis::Pool);
+
+impl RedisPool {
+    pub fn new(pool: deadpool_redis::Pool) -> Self {
+        RedisPool(pool)
+    }
+
+    pub async fn get(&self) -> Result<RedisConnection, deadpool_redis::PoolError> {
+        self.0.get().await.map(RedisConnection)
+    }
+}
+
+/// A pooled Redis connection, see `RedisPool`.
+pub struct RedisConnection(deadpool_redis::Connection);
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = self.0.req_packed_command(cmd).await;
+            histogram!("ephemeral_redis_command_duration_seconds")
+                .record(start.elapsed().as_secs_f64());
+            result
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = self.0.req_packed_commands(cmd, offset, count).await;
+            histogram!("ephemeral_redis_command_duration_seconds")
+                .record(start.elapsed().as_secs_f64());
+            result
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.0.get_db()
+    }
+}
@@ -0,0 +1,623 @@
+use crate::{
+    error::{AppError, parse_stored},
+    handlers::Hub,
+    shared_types::{RevisionSource, TextComponent, TextOperation, TextRevision, WsMessage},
+    telemetry::RedisConnection,
+};
+use chrono::{Duration, Utc};
+use redis::AsyncCommands;
+use similar::TextDiff;
+use tracing::debug;
+
+/// How many past edits of a text bin are kept to transform late edits
+/// against. Clients that are further behind have to start over.
+const MAX_TEXT_HISTORY: usize = 200;
+
+/// The Redis key the recent edits of a hub's text bin are stored under,
+/// oldest first. The last one produced the hub's current `text_revision`.
+pub fn history_key(hub_id: &str) -> String {
+    format!("hub:{}:text_history", hub_id)
+}
+
+/// How many past versions of a text bin are kept to look back at.
+const MAX_TEXT_REVISIONS: usize = 50;
+
+/// How long typing has to pause before the next live edit starts a new
+/// revision, rather than updating the latest one.
+const REVISION_PAUSE_SECS: i64 = 30;
+
+/// The Redis key the past versions of a hub's text bin are stored under,
+/// oldest first. The last one is always the current text.
+pub fn revisions_key(hub_id: &str) -> String {
+    format!("hub:{}:text_revisions", hub_id)
+}
+
+impl TextOperation {
+    /// Keeps the next `n` characters.
+    pub fn retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        // Counts come from clients, so merging them could overflow.
+        if let Some(TextComponent::Retain(m)) = self.0.last_mut()
+            && let Some(sum) = m.checked_add(n)
+        {
+            *m = sum;
+        } else {
+            self.0.push(TextComponent::Retain(n));
+        }
+    }
+
+    /// Inserts `text` at the current position.
+    pub fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        // Inserts are kept in front of deletes at the same position, so that
+        // equivalent operations always look the same.
+        let len = self.0.len();
+        match self.0.as_mut_slice() {
+            [.., TextComponent::Insert(s)]
+            | [.., TextComponent::Insert(s), TextComponent::Delete(_)] => s.push_str(text),
+            [.., TextComponent::Delete(_)] => self
+                .0
+                .insert(len - 1, TextComponent::Insert(text.to_string())),
+            _ => self.0.push(TextComponent::Insert(text.to_string())),
+        }
+    }
+
+    /// Deletes the next `n` characters.
+    pub fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        if let Some(TextComponent::Delete(m)) = self.0.last_mut()
+            && let Some(sum) = m.checked_add(n)
+        {
+            *m = sum;
+        } else {
+            self.0.push(TextComponent::Delete(n));
+        }
+    }
+
+    /// The length in characters of the texts the operation applies to, or
+    /// `None` if it is too long to be the length of any text.
+    pub fn base_len(&self) -> Option<usize> {
+        self.0.iter().try_fold(0usize, |len, c| match c {
+            TextComponent::Retain(n) | TextComponent::Delete(n) => len.checked_add(*n),
+            TextComponent::Insert(_) => Some(len),
+        })
+    }
+}
+
+impl TextComponent {
+    // How many characters the component walks over or inserts.
+    fn len(&self) -> usize {
+        match self {
+            TextComponent::Retain(n) | TextComponent::Delete(n) => *n,
+            TextComponent::Insert(s) => s.chars().count(),
+        }
+    }
+
+    // What is left of a retain or delete after its first `n` characters.
+    fn skip(self, n: usize) -> Option<TextComponent> {
+        match self {
+            TextComponent::Retain(m) if m > n => Some(TextComponent::Retain(m - n)),
+            TextComponent::Delete(m) if m > n => Some(TextComponent::Delete(m - n)),
+            _ => None,
+        }
+    }
+}
+
+/// Applies an operation to a text.
+pub fn apply(text: &str, operation: &TextOperation) -> Result<String, String> {
+    if operation.base_len() != Some(text.chars().count()) {
+        return Err("the operation doesn't match the length of the text".to_string());
+    }
+    let mut chars = text.chars();
+    let mut result = String::with_capacity(text.len());
+    for component in &operation.0 {
+        match component {
+            TextComponent::Retain(n) => result.extend(chars.by_ref().take(*n)),
+            TextComponent::Insert(s) => result.push_str(s),
+            TextComponent::Delete(n) => chars.by_ref().take(*n).for_each(drop),
+        }
+    }
+    Ok(result)
+}
+
+/// Transforms two operations made concurrently on the same text into
+/// `(a', b')`, where `a'` has the effect of `a` once `b` has been applied
+/// and vice versa, so that applying `a` then `b'` gives the same text as
+/// `b` then `a'`. When both insert at the same position, `a` goes first.
+pub fn transform(
+    a: &TextOperation,
+    b: &TextOperation,
+) -> Result<(TextOperation, TextOperation), String> {
+    if a.base_len().is_none() || a.base_len() != b.base_len() {
+        return Err("the operations were made on different texts".to_string());
+    }
+
+    let (mut a_prime, mut b_prime) = (TextOperation::default(), TextOperation::default());
+    let (mut a_iter, mut b_iter) = (a.0.iter().cloned(), b.0.iter().cloned());
+    let (mut next_a, mut next_b) = (a_iter.next(), b_iter.next());
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (None, None) => break,
+            // Inserted text is kept by the other operation.
+            (Some(TextComponent::Insert(s)), b) => {
+                a_prime.insert(&s);
+                b_prime.retain(s.chars().count());
+                next_a = a_iter.next();
+                next_b = b;
+            }
+            (a, Some(TextComponent::Insert(s))) => {
+                a_prime.retain(s.chars().count());
+                b_prime.insert(&s);
+                next_a = a;
+                next_b = b_iter.next();
+            }
+            (Some(a), Some(b)) => {
+                let n = a.len().min(b.len());
+                match (&a, &b) {
+                    (TextComponent::Retain(_), TextComponent::Retain(_)) => {
+                        a_prime.retain(n);
+                        b_prime.retain(n);
+                    }
+                    (TextComponent::Delete(_), TextComponent::Retain(_)) => a_prime.delete(n),
+                    (TextComponent::Retain(_), TextComponent::Delete(_)) => b_prime.delete(n),
+                    // Both deleted the same characters.
+                    _ => {}
+                }
+                next_a = a.skip(n).or_else(|| a_iter.next());
+                next_b = b.skip(n).or_else(|| b_iter.next());
+            }
+            (None, Some(_)) | (Some(_), None) => {
+                return Err("the operations were made on different texts".to_string());
+            }
+        }
+    }
+    Ok((a_prime, b_prime))
+}
+
+/// The operation that turns `old` into `new`, replacing everything between
+/// their common start and end.
+pub fn diff(old: &str, new: &str) -> TextOperation {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut operation = TextOperation::default();
+    operation.retain(prefix);
+    operation.insert(&new[prefix..new.len() - suffix].iter().collect::<String>());
+    operation.delete(old.len() - prefix - suffix);
+    operation.retain(suffix);
+    operation
+}
+
+/// The outcome of `apply_edit`.
+pub enum EditResult {
+    /// The edit was applied as `operation`, which produced `revision`.
+    Applied {
+        revision: u64,
+        operation: TextOperation,
+    },
+    /// The edit couldn't be applied. The client has to start over from this.
+    Rejected { content: String, revision: u64 },
+}
+
+/// Applies an edit a client made on `revision` of a hub's text bin, after
+/// transforming it past the edits made since. Edits that would make the
+/// text longer than `max_bytes` are rejected. Returns `None` if the hub
+/// doesn't exist.
+///
+/// Edits of the same hub must not be applied concurrently, see
+/// `AppWsState::lock_hub`.
+pub async fn apply_edit(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+    revision: u64,
+    operation: TextOperation,
+    max_bytes: usize,
+) -> Result<Option<EditResult>, AppError> {
+    let Some((hub, history)) = load(conn, hub_id).await? else {
+        return Ok(None);
+    };
+
+    let edit = catch_up(&history, hub.text_revision, revision, operation)
+        .and_then(|operation| Ok((apply(&hub.content, &operation)?, operation)))
+        .and_then(|(content, operation)| {
+            if content.len() > max_bytes {
+                Err(format!("the text would be longer than {} bytes", max_bytes))
+            } else {
+                Ok((content, operation))
+            }
+        });
+    match edit {
+        Ok((content, operation)) => {
+            let (hub, operation) = commit(
+                conn,
+                hub,
+                history,
+                content,
+                operation,
+                RevisionSource::Edited,
+            )
+            .await?;
+            Ok(Some(EditResult::Applied {
+                revision: hub.text_revision,
+                operation,
+            }))
+        }
+        Err(e) => {
+            debug!("Rejected text edit for hub {}: {}", hub_id, e);
+            Ok(Some(EditResult::Rejected {
+                content: hub.content,
+                revision: hub.text_revision,
+            }))
+        }
+    }
+}
+
+/// Replaces the whole text bin of a hub. The change is stored as an edit of
+/// the latest revision, so that edits clients made concurrently are merged
+/// with it. Returns the updated hub and the message announcing the change.
+///
+/// Edits of the same hub must not be applied concurrently, see
+/// `AppWsState::lock_hub`.
+pub async fn replace(
+    conn: &mut RedisConnection,
+    hub: Hub,
+    content: String,
+    source: RevisionSource,
+) -> Result<(Hub, WsMessage), AppError> {
+    let history = load_history(conn, &hub.id).await?;
+    let operation = diff(&hub.content, &content);
+    let (hub, operation) = commit(conn, hub, history, content, operation, source).await?;
+    let update = WsMessage::TextUpdated {
+        content: hub.content.clone(),
+        revision: hub.text_revision,
+        operation,
+    };
+    Ok((hub, update))
+}
+
+/// Chooses the language a hub's text bin is highlighted as. This is stored
+/// as an edit that keeps the whole text, so that the hub's entity tag
+/// changes. Returns the updated hub and the edit.
+///
+/// Edits of the same hub must not be applied concurrently, see
+/// `AppWsState::lock_hub`.
+pub async fn set_language(
+    conn: &mut RedisConnection,
+    mut hub: Hub,
+    language: Option<String>,
+) -> Result<(Hub, TextOperation), AppError> {
+    hub.language = language;
+    let history = load_history(conn, &hub.id).await?;
+    let mut operation = TextOperation::default();
+    operation.retain(hub.content.chars().count());
+    let content = hub.content.clone();
+    commit(
+        conn,
+        hub,
+        history,
+        content,
+        operation,
+        RevisionSource::Edited,
+    )
+    .await
+}
+
+/// The current text bin of a hub, for a client that has just connected.
+pub async fn snapshot(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+) -> Result<Option<WsMessage>, AppError> {
+    let hub_json: Option<String> = conn.get(format!("hub:{}", hub_id)).await?;
+    let Some(hub_json) = hub_json else {
+        return Ok(None);
+    };
+    let hub: Hub = parse_stored(&hub_json)?;
+    Ok(Some(WsMessage::TextSnapshot {
+        content: hub.content,
+        revision: hub.text_revision,
+    }))
+}
+
+// Loads a hub and the recent edits of its text bin.
+async fn load(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+) -> Result<Option<(Hub, Vec<TextOperation>)>, AppError> {
+    let hub_json: Option<String> = conn.get(format!("hub:{}", hub_id)).await?;
+    let Some(hub_json) = hub_json else {
+        return Ok(None);
+    };
+    let hub: Hub = parse_stored(&hub_json)?;
+    let history = load_history(conn, hub_id).await?;
+    Ok(Some((hub, history)))
+}
+
+// Loads the recent edits of a hub's text bin. Hubs created before
+// collaborative editing don't have any.
+async fn load_history(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+) -> Result<Vec<TextOperation>, AppError> {
+    let history_json: Option<String> = conn.get(history_key(hub_id)).await?;
+    Ok(history_json
+        .map(|json| parse_stored(&json))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// The past versions of a hub's text bin, oldest first. Hubs whose text
+/// has never been written don't have any.
+pub async fn load_revisions(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+) -> Result<Vec<TextRevision>, AppError> {
+    let revisions_json: Option<String> = conn.get(revisions_key(hub_id)).await?;
+    Ok(revisions_json
+        .map(|json| parse_stored(&json))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// The past version of a text bin that `revision` belongs to, which is the
+/// last one made at or before it. A version keeps the number it was made
+/// with, so that clients can look it up again while it is being typed on.
+pub fn find_revision(
+    revisions: Vec<TextRevision>,
+    revision: u64,
+    latest: u64,
+) -> Option<TextRevision> {
+    if revision > latest {
+        return None;
+    }
+    revisions.into_iter().rev().find(|r| r.revision <= revision)
+}
+
+/// A unified diff of the lines that changed between two revisions.
+pub fn unified_diff(old: &TextRevision, new: &TextRevision) -> String {
+    TextDiff::from_lines(&old.content, &new.content)
+        .unified_diff()
+        .header(
+            &format!("revision {}", old.revision),
+            &format!("revision {}", new.revision),
+        )
+        .to_string()
+}
+
+// Adds the text produced by a write to the past versions of a text bin.
+fn record_revision(revisions: &mut Vec<TextRevision>, hub: &Hub, source: RevisionSource) {
+    let now = Utc::now();
+    match revisions.last_mut() {
+        // Live edits made without pausing make up a single revision.
+        Some(latest)
+            if source == RevisionSource::Edited
+                && latest.source == RevisionSource::Edited
+                && now - latest.at < Duration::seconds(REVISION_PAUSE_SECS) =>
+        {
+            latest.at = now;
+            latest.content = hub.content.clone();
+        }
+        // Writes that keep the text as it is don't make a new revision.
+        Some(latest) if latest.content == hub.content => {}
+        _ => revisions.push(TextRevision {
+            revision: hub.text_revision,
+            at: now,
+            source,
+            content: hub.content.clone(),
+        }),
+    }
+    if revisions.len() > MAX_TEXT_REVISIONS {
+        revisions.drain(..revisions.len() - MAX_TEXT_REVISIONS);
+    }
+}
+
+// Transforms an edit made on `revision` past the edits made since, which
+// are the last ones in `history`. The very last one produced `latest`.
+fn catch_up(
+    history: &[TextOperation],
+    latest: u64,
+    revision: u64,
+    mut operation: TextOperation,
+) -> Result<TextOperation, String> {
+    let missed = latest
+        .checked_sub(revision)
+        .ok_or("the edit was made on a revision that doesn't exist yet")?;
+    let start = usize::try_from(missed)
+        .ok()
+        .and_then(|missed| history.len().checked_sub(missed))
+        .ok_or("the edit was made on a revision that is too old")?;
+    for concurrent in &history[start..] {
+        operation = transform(&operation, concurrent)?.0;
+    }
+    Ok(operation)
+}
+
+// Stores the text produced by an edit along with the edit itself and the
+// new revision, keeping the hub's TTL. Returns the updated hub and the edit.
+async fn commit(
+    conn: &mut RedisConnection,
+    mut hub: Hub,
+    mut history: Vec<TextOperation>,
+    content: String,
+    operation: TextOperation,
+    source: RevisionSource,
+) -> Result<(Hub, TextOperation), AppError> {
+    hub.content = content;
+    hub.text_revision += 1;
+    history.push(operation.clone());
+    if history.len() > MAX_TEXT_HISTORY {
+        history.drain(..history.len() - MAX_TEXT_HISTORY);
+    }
+    let mut revisions = load_revisions(conn, &hub.id).await?;
+    record_revision(&mut revisions, &hub, source);
+
+    let key = format!("hub:{}", hub.id);
+    let ttl: isize = conn.ttl(&key).await?;
+    if ttl > 0 {
+        redis::pipe()
+            .atomic()
+            .set_ex(&key, serde_json::to_string(&hub)?, ttl as u64)
+            .set_ex(
+                history_key(&hub.id),
+                serde_json::to_string(&history)?,
+                ttl as u64,
+            )
+            .set_ex(
+                revisions_key(&hub.id),
+                serde_json::to_string(&revisions)?,
+                ttl as u64,
+            )
+            .query_async::<()>(conn)
+            .await?;
+    }
+    Ok((hub, operation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Operations written the way clients send them, e.g. `[2, "ab", -1]`.
+    fn op(json: &str) -> TextOperation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // Edits of `BASE` that insert and delete around the same places.
+    const BASE: &str = "hello wörld";
+    const EDITS: &[&str] = &[
+        "hello wörld",
+        "hello brave wörld",
+        "hello, wörld",
+        "hellö wörld!",
+        "héllo",
+        "wörld",
+        "",
+        "hello wörld wörld",
+        "he wö",
+        "say hello wörld",
+    ];
+
+    fn converges(base: &str, a: &TextOperation, b: &TextOperation) -> String {
+        let (a_prime, b_prime) = transform(a, b).unwrap();
+        let a_then_b = apply(&apply(base, a).unwrap(), &b_prime).unwrap();
+        let b_then_a = apply(&apply(base, b).unwrap(), &a_prime).unwrap();
+        assert_eq!(a_then_b, b_then_a, "{:?} and {:?} diverge", a, b);
+        a_then_b
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        for a in EDITS {
+            for b in EDITS {
+                converges(BASE, &diff(BASE, a), &diff(BASE, b));
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_inserts_at_the_same_position_keep_both() {
+        let a = op(r#"[5, "X", 6]"#);
+        let b = op(r#"[5, "Y", 6]"#);
+        assert_eq!(converges(BASE, &a, &b), "helloXY wörld");
+        assert_eq!(converges(BASE, &b, &a), "helloYX wörld");
+    }
+
+    #[test]
+    fn overlapping_deletes_delete_once() {
+        let a = op("[2, -4, 5]");
+        let b = op("[4, -4, 3]");
+        assert_eq!(converges(BASE, &a, &b), "herld");
+    }
+
+    #[test]
+    fn diff_round_trips() {
+        for old in EDITS {
+            for new in EDITS {
+                assert_eq!(apply(old, &diff(old, new)).unwrap(), *new);
+            }
+        }
+        assert_eq!(diff("same", "same"), op("[4]"));
+    }
+
+    #[test]
+    fn operations_on_other_texts_are_rejected() {
+        assert!(apply(BASE, &op("[3]")).is_err());
+        assert!(apply(BASE, &op(r#"[11, "!", 1]"#)).is_err());
+        assert!(transform(&op("[3]"), &op("[4]")).is_err());
+    }
+
+    #[test]
+    fn huge_counts_are_rejected_without_overflowing() {
+        let huge = TextOperation(vec![
+            TextComponent::Retain(usize::MAX),
+            TextComponent::Retain(usize::MAX),
+        ]);
+        assert_eq!(huge.base_len(), None);
+        assert!(apply(BASE, &huge).is_err());
+        assert!(transform(&huge, &huge).is_err());
+
+        let mut merged = TextOperation::default();
+        merged.retain(usize::MAX);
+        merged.retain(1);
+        merged.delete(usize::MAX);
+        merged.delete(1);
+        assert_eq!(merged.0.len(), 4);
+    }
+
+    #[test]
+    fn late_edits_catch_up_with_the_history() {
+        let history = [op(r#"["say ", 11]"#), op(r#"[15, "!"]"#)];
+        let late = catch_up(&history, 7, 5, op("[5, -6]")).unwrap();
+        assert_eq!(apply("say hello wörld!", &late).unwrap(), "say hello!");
+
+        assert!(catch_up(&history, 7, 8, op("[16]")).is_err());
+        assert!(catch_up(&history, 7, 4, op("[11]")).is_err());
+    }
+
+    #[test]
+    fn revisions_keep_their_numbers() {
+        let mut hub: Hub = serde_json::from_value(serde_json::json!({
+            "id": "hub",
+            "content": "hello",
+            "created_at": Utc::now(),
+            "files": [],
+            "text_revision": 1,
+        }))
+        .unwrap();
+        let mut revisions = Vec::new();
+        record_revision(&mut revisions, &hub, RevisionSource::Edited);
+
+        // Typing on, and then a write that keeps the text.
+        hub.text_revision = 2;
+        hub.content = "hello wörld".to_string();
+        record_revision(&mut revisions, &hub, RevisionSource::Edited);
+        hub.text_revision = 3;
+        record_revision(&mut revisions, &hub, RevisionSource::Replaced);
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].revision, 1);
+
+        hub.text_revision = 4;
+        hub.content = "bye".to_string();
+        record_revision(&mut revisions, &hub, RevisionSource::Replaced);
+        assert_eq!(revisions.len(), 2);
+
+        let found = |revision| find_revision(revisions.clone(), revision, 4);
+        assert_eq!(found(1).unwrap().content, "hello wörld");
+        assert_eq!(found(3).unwrap().revision, 1);
+        assert_eq!(found(4).unwrap().content, "bye");
+        assert!(found(0).is_none());
+        assert!(found(5).is_none());
+    }
+}
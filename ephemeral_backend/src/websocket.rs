@@ -1,16 +1,39 @@
-use crate::{AppState, handlers::Hub, shared_types::WsMessage};
+use crate::{
+    AppState,
+    error::AppError,
+    handlers::Hub,
+    ratelimit::{self, RouteClass},
+    shared_types::{TextOperation, WsMessage},
+    telemetry::{REQUEST_ID_HEADER, RedisConnection},
+    text::{self, EditResult},
+    whiteboard::{self, MAX_WHITEBOARD_BYTES, WhiteboardPage},
+};
 use axum::{
+    body::Bytes,
     extract::{
-        Path, State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode, header},
+    response::Response,
 };
 use futures_util::{sink::SinkExt, stream::StreamExt};
+use metrics::counter;
 use redis::AsyncCommands;
-use std::collections::HashMap;
-use tokio::sync::{Mutex, broadcast};
-use tracing::{info, warn};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard, broadcast, broadcast::error::RecvError, mpsc, watch},
+    time::{self, MissedTickBehavior},
+};
+use tracing::{Instrument, debug, info, warn};
+
+// How many messages a room buffers for clients that are slow to receive them.
+const ROOM_CAPACITY: usize = 100;
 
 /// The shared state for our WebSocket rooms.
 /// We use a Mutex to safely access the HashMap of rooms from multiple threads.
@@ -18,6 +41,74 @@ use tracing::{info, warn};
 #[derive(Debug, Default)]
 pub struct AppWsState {
     rooms: Mutex<HashMap<String, broadcast::Sender<String>>>,
+    // One lock per hub that is being changed. Hubs are stored as a single
+    // JSON value, so changes to them have to be made one at a time.
+    hub_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    // Set once the server shuts down, to close every connection.
+    closing: watch::Sender<bool>,
+}
+
+impl AppWsState {
+    /// Sends a message to every client connected to a hub, if there are any.
+    pub async fn broadcast(&self, hub_id: &str, msg: &WsMessage) {
+        let rooms = self.rooms.lock().await;
+        if let Some(tx) = rooms.get(hub_id) {
+            send_json(tx, msg);
+        }
+    }
+
+    /// Adds a client to the room of a hub, creating the room if needed.
+    /// Returns the sender of the room and the receiver of the client.
+    pub async fn join(
+        &self,
+        hub_id: &str,
+    ) -> (broadcast::Sender<String>, broadcast::Receiver<String>) {
+        let mut rooms = self.rooms.lock().await;
+        let tx = rooms
+            .entry(hub_id.to_string())
+            .or_insert_with(|| broadcast::channel(ROOM_CAPACITY).0);
+        // Subscribed while locked, so that `leave` can't remove the room
+        // in between.
+        (tx.clone(), tx.subscribe())
+    }
+
+    /// Removes the room of a hub if its last client has left, i.e. dropped
+    /// the receiver returned by `join`.
+    pub async fn leave(&self, hub_id: &str) {
+        let mut rooms = self.rooms.lock().await;
+        if rooms.get(hub_id).is_some_and(|tx| tx.receiver_count() == 0) {
+            rooms.remove(hub_id);
+        }
+    }
+
+    /// Tells every connected client that the server is restarting, then
+    /// closes their connections once the messages before it were sent.
+    pub async fn close_all(&self) {
+        let rooms = self.rooms.lock().await;
+        for tx in rooms.values() {
+            send_json(tx, &WsMessage::ServerRestarting);
+        }
+        self.closing.send_replace(true);
+    }
+
+    /// How many rooms there are, and how many clients are connected to them.
+    pub async fn stats(&self) -> (usize, usize) {
+        let rooms = self.rooms.lock().await;
+        let connections = rooms.values().map(|tx| tx.receiver_count()).sum();
+        (rooms.len(), connections)
+    }
+
+    /// Waits until no one else is changing a hub, and keeps others from
+    /// doing so until the returned guard is dropped.
+    pub async fn lock_hub(&self, hub_id: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.hub_locks.lock().await;
+            // Forget the locks nobody holds or waits for.
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(hub_id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
 }
 
 /// The entry point for WebSocket connections.
@@ -26,81 +117,390 @@ pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     Path(hub_id): Path<String>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, hub_id))
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // The connection outlives the request, so it gets a span of its own.
+    let span = tracing::info_span!(
+        "websocket",
+        hub_id,
+        request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default(),
+    );
+    // Browsers don't apply CORS to WebSockets, so pages from other origins
+    // are turned away here.
+    if let Some(origin) = headers.get(header::ORIGIN)
+        && !origin
+            .to_str()
+            .is_ok_and(|origin| state.settings.allows_origin(origin))
+    {
+        return Err(AppError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            message: "This origin may not connect to hubs".to_string(),
+        });
+    }
+    let trusted_proxies = &state.settings.rate_limits.trusted_proxies;
+    let client_ip = ratelimit::client_ip(peer.ip(), &headers, trusted_proxies);
+    Ok(ws
+        .on_upgrade(move |socket| handle_socket(socket, state, hub_id, client_ip).instrument(span)))
 }
 
 /// The main logic for a single WebSocket connection.
-async fn handle_socket(socket: WebSocket, state: AppState, hub_id: String) {
+async fn handle_socket(socket: WebSocket, state: AppState, hub_id: String, client_ip: IpAddr) {
     info!("New WebSocket connection for hub: {}", hub_id);
+    let connected_at = Instant::now();
 
-    // Get a sender for the room's broadcast channel, creating it if it doesn't exist.
-
-    let tx = {
-        let mut rooms = state.ws_state.rooms.lock().await;
-        rooms
-            .entry(hub_id.clone())
-            .or_insert_with(|| broadcast::channel(100).0)
-            .clone()
-    };
-
-    // Subscribe to the broadcast channel to receive messages.
-    let mut rx = tx.subscribe();
+    // Join the room of the hub, to receive everything sent to it.
+    let ws_state = state.ws_state.clone();
+    let (tx, mut rx) = ws_state.join(&hub_id).await;
 
     // Split the WebSocket into a sender and receiver.
     let (mut sender, mut receiver) = socket.split();
 
+    // Messages for this client only, like the state of the text bin.
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<WsMessage>();
+    match state.redis.get().await {
+        Ok(mut conn) => match text::snapshot(&mut conn, &hub_id).await {
+            Ok(Some(snapshot)) => {
+                let _ = direct_tx.send(snapshot);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to load text bin for hub {}: {}", hub_id, e),
+        },
+        Err(e) => warn!("Failed to load text bin for hub {}: {}", hub_id, e),
+    }
+
     // Task to forward messages from the broadcast channel to the client.
-    let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            // Convert the String from the broadcast channel into the type expected by Message::Text.
-            if sender.send(Message::Text(msg.into())).await.is_err() {
-                break;
+    // Direct messages go first, so the client knows the text bin before it
+    // receives edits to it.
+    // Clients are pinged regularly, so that dead connections are noticed.
+    let mut closing = state.ws_state.closing.subscribe();
+    let ping_period = Duration::from_secs(state.settings.websocket.ping_interval_secs);
+    let mut ping = time::interval_at(time::Instant::now() + ping_period, ping_period);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut send_task = tokio::spawn(
+        async move {
+            loop {
+                let msg = tokio::select! {
+                    biased;
+                    Some(msg) = direct_rx.recv() => match serde_json::to_string(&msg) {
+                        Ok(json) => Message::Text(json.into()),
+                        Err(e) => {
+                            warn!("Failed to serialize WebSocket message: {}", e);
+                            continue;
+                        }
+                    },
+                    msg = rx.recv() => match msg {
+                        Ok(msg) => Message::Text(msg.into()),
+                        // The client missed messages, so its hub is out of
+                        // date. It has to reconnect to load it again.
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("WebSocket client missed {} messages, closing", missed);
+                            counter!("ephemeral_websocket_lagged_total").increment(1);
+                            close_frame(close_code::AGAIN, "Missed messages, reconnect")
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = async { closing.wait_for(|closing| *closing).await.is_ok() } => {
+                        close_frame(close_code::RESTART, "Server restarting")
+                    }
+                    _ = ping.tick() => Message::Ping(Bytes::new()),
+                };
+                let is_close = matches!(msg, Message::Close(_));
+                if sender.send(msg).await.is_err() || is_close {
+                    break;
+                }
             }
         }
-    });
+        .in_current_span(),
+    );
 
     // Task to handle incoming messages from the client.
     let recv_task_hub_id = hub_id.clone();
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            if tx.send(text.to_string()).is_err() {
-                // No active subscribers, but that's okay.
-            }
-
-            if let Ok(WsMessage::PathCompleted(path)) = serde_json::from_str(&text) {
-                let mut conn = match state.redis.get().await {
-                    Ok(conn) => conn,
+    let idle_timeout = Duration::from_secs(state.settings.websocket.idle_timeout_secs);
+    let mut recv_task = tokio::spawn(
+        async move {
+            loop {
+                // Clients answer pings, so one that sends nothing, not even
+                // a pong, for this long is gone.
+                let text = match time::timeout(idle_timeout, receiver.next()).await {
+                    Ok(Some(Ok(Message::Text(text)))) => text,
+                    Ok(Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_)))) => {
+                        continue;
+                    }
+                    Ok(_) => break,
+                    Err(_) => {
+                        debug!("Closing WebSocket connection from {}: idle", client_ip);
+                        break;
+                    }
+                };
+                // Messages over the limit are dropped, so a flood can't reach
+                // everyone else in the room.
+                if ratelimit::check(&state, RouteClass::WebSocket, client_ip)
+                    .await
+                    .is_err()
+                {
+                    debug!(
+                        "Dropping WebSocket message from {}: rate limited",
+                        client_ip
+                    );
+                    continue;
+                }
+                let msg = match serde_json::from_str::<WsMessage>(&text) {
+                    Ok(msg) => msg,
                     Err(e) => {
-                        warn!("Failed to get Redis connection: {}", e);
+                        warn!("Ignoring malformed WebSocket message: {}", e);
                         continue;
                     }
                 };
+                if let Err(e) = whiteboard::validate_message(&msg) {
+                    warn!("Ignoring invalid WebSocket message: {}", e);
+                    continue;
+                }
 
-                let key = format!("hub:{}", recv_task_hub_id);
-
-                // Fetch the current hub data
-                if let Ok(Some(hub_json)) = conn.get::<_, Option<String>>(&key).await {
-                    if let Ok(mut hub) = serde_json::from_str::<Hub>(&hub_json) {
-                        // Add the new path and save it back
-                        hub.whiteboard.push(path);
-                        let updated_json = serde_json::to_string(&hub).unwrap();
-                        let ttl: isize = conn.ttl(&key).await.unwrap_or(-1);
+                match msg {
+                    WsMessage::TextEdit {
+                        revision,
+                        operation,
+                        user_id,
+                    } => {
+                        // Edits are broadcast before the next one is applied, so
+                        // that everyone receives them in order.
+                        let _lock = state.ws_state.lock_hub(&recv_task_hub_id).await;
+                        match apply_text_edit(&state, &recv_task_hub_id, revision, operation).await
+                        {
+                            Ok(Some(EditResult::Applied {
+                                revision,
+                                operation,
+                            })) => {
+                                let edit = WsMessage::TextEdit {
+                                    revision,
+                                    operation,
+                                    user_id,
+                                };
+                                send_json(&tx, &edit);
+                            }
+                            // The client is out of sync, so it gets the current text to start over from.
+                            Ok(Some(EditResult::Rejected { content, revision })) => {
+                                let _ =
+                                    direct_tx.send(WsMessage::TextSnapshot { content, revision });
+                            }
+                            Ok(None) => {}
+                            Err(e) => warn!(
+                                "Failed to update text bin for hub {}: {}",
+                                recv_task_hub_id, e
+                            ),
+                        }
+                        continue;
+                    }
+                    // Carets aren't stored, only shown to everyone else.
+                    WsMessage::TextCursor { .. } => {
+                        send_json(&tx, &msg);
+                        continue;
+                    }
+                    _ => {}
+                }
 
-                        if ttl > 0 {
-                            let _: () = conn.set_ex(&key, updated_json, ttl as u64).await.unwrap();
+                // Apply the message to the stored page first, so that late joiners
+                // see the same board as everyone who receives the broadcast.
+                // Pages are read and written back whole, and the size limit
+                // covers all pages of the hub, so changes are made one at a
+                // time and broadcast in the order they were stored.
+                let _lock = state.ws_state.lock_hub(&recv_task_hub_id).await;
+                match apply_to_page(&state, &recv_task_hub_id, msg).await {
+                    Ok(PageUpdate::Applied(effects)) => {
+                        for effect in effects {
+                            send_json(&tx, &effect);
                         }
                     }
+                    Ok(PageUpdate::Rejected(undo)) => {
+                        for msg in undo {
+                            let _ = direct_tx.send(msg);
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Failed to update whiteboard for hub {}: {}",
+                        recv_task_hub_id, e
+                    ),
                 }
             }
         }
-    });
+        .in_current_span(),
+    );
 
     // Wait for either task to finish. If one does, the other should be aborted.
-    tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
+    let other_task = tokio::select! {
+        _ = (&mut send_task) => recv_task,
+        _ = (&mut recv_task) => send_task,
+    };
+    other_task.abort();
+    // Wait until the aborted task has dropped its receiver, so that the room
+    // is removed with its last client.
+    let _ = other_task.await;
+    ws_state.leave(&hub_id).await;
+
+    info!(
+        duration_secs = connected_at.elapsed().as_secs(),
+        "WebSocket connection for hub {} closed", hub_id
+    );
+}
+
+fn close_frame(code: u16, reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
+
+/// What became of a message from a client that changes a whiteboard page.
+enum PageUpdate {
+    /// The page was changed. Holds the messages to broadcast to the room.
+    Applied(Vec<WsMessage>),
+    /// The whiteboard is full. Holds the messages that take the change back
+    /// for the client that made it.
+    Rejected(Vec<WsMessage>),
+}
+
+/// Loads the page a message is addressed to, applies the message to it and
+/// saves it back, preserving the TTL.
+///
+/// Pages of the same hub must not be changed concurrently, see
+/// `AppWsState::lock_hub`.
+async fn apply_to_page(
+    state: &AppState,
+    hub_id: &str,
+    msg: WsMessage,
+) -> Result<PageUpdate, String> {
+    let Some(page_id) = msg.page_id().map(str::to_string) else {
+        return Ok(PageUpdate::Applied(Vec::new()));
     };
+    let key = whiteboard::page_key(hub_id, &page_id);
+    let mut conn = state.redis.get().await.map_err(|e| e.to_string())?;
+
+    let page_json: Option<String> = conn.get(&key).await.map_err(|e| e.to_string())?;
+    let page_json = page_json.ok_or_else(|| "page not found".to_string())?;
+    let mut page: WhiteboardPage = serde_json::from_str(&page_json).map_err(|e| e.to_string())?;
+
+    let effects = whiteboard::apply_message(&mut page, msg);
+    if effects.is_empty() {
+        return Ok(PageUpdate::Applied(effects));
+    }
+
+    let mut updated_json = serde_json::to_string(&page).map_err(|e| e.to_string())?;
+    if updated_json.len() > page_json.len() {
+        let other_pages = other_pages_size(&mut conn, hub_id, &page_id).await?;
+        let limit = MAX_WHITEBOARD_BYTES.saturating_sub(other_pages);
+        if updated_json.len() > limit {
+            // The undo history and the log are given up before the board.
+            page.forget_history();
+            updated_json = serde_json::to_string(&page).map_err(|e| e.to_string())?;
+            if updated_json.len() > limit && !whiteboard::only_removes(&effects) {
+                warn!("Whiteboard of hub {} is full, dropping an edit", hub_id);
+                // The author has already applied the change locally, so it
+                // has to be taken back.
+                let original: WhiteboardPage =
+                    serde_json::from_str(&page_json).map_err(|e| e.to_string())?;
+                let undo = whiteboard::compensate(&original.elements, &effects);
+                return Ok(PageUpdate::Rejected(undo));
+            }
+        }
+    }
+    let ttl: isize = conn.ttl(&key).await.unwrap_or(-1);
+    if ttl > 0 {
+        let _: () = conn
+            .set_ex(&key, updated_json, ttl as u64)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(PageUpdate::Applied(effects))
+}
+
+// Sends a message to everyone in a room. Having no active subscribers is
+// okay.
+fn send_json(tx: &broadcast::Sender<String>, msg: &WsMessage) {
+    match serde_json::to_string(msg) {
+        Ok(json) => {
+            if tx.send(json).is_ok() {
+                counter!("ephemeral_websocket_messages_broadcast_total").increment(1);
+            }
+        }
+        Err(e) => warn!("Failed to serialize WebSocket message: {}", e),
+    }
+}
+
+/// Applies an edit to the text bin of a hub.
+async fn apply_text_edit(
+    state: &AppState,
+    hub_id: &str,
+    revision: u64,
+    operation: TextOperation,
+) -> Result<Option<EditResult>, String> {
+    let mut conn = state.redis.get().await.map_err(|e| e.to_string())?;
+    let max_bytes = state.settings.limits.max_text_bytes;
+    text::apply_edit(&mut conn, hub_id, revision, operation, max_bytes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// The size in bytes of every whiteboard page of a hub except `page_id`.
+async fn other_pages_size(
+    conn: &mut RedisConnection,
+    hub_id: &str,
+    page_id: &str,
+) -> Result<usize, String> {
+    let hub_json: Option<String> = conn
+        .get(format!("hub:{}", hub_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    let hub_json = hub_json.ok_or_else(|| "hub not found".to_string())?;
+    let hub: Hub = serde_json::from_str(&hub_json).map_err(|e| e.to_string())?;
+
+    let mut size = 0;
+    for page in hub.pages.iter().filter(|page| page.id != page_id) {
+        let len: usize = conn
+            .strlen(whiteboard::page_key(hub_id, &page.id))
+            .await
+            .map_err(|e| e.to_string())?;
+        size += len;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rooms_are_removed_when_the_last_client_leaves() {
+        let ws_state = AppWsState::default();
+        let (_, first) = ws_state.join("hub").await;
+        let (_, second) = ws_state.join("hub").await;
+        let (_, other) = ws_state.join("other").await;
+        assert_eq!(ws_state.stats().await, (2, 3));
+
+        drop(first);
+        ws_state.leave("hub").await;
+        assert_eq!(ws_state.stats().await, (2, 2));
+
+        drop(second);
+        ws_state.leave("hub").await;
+        drop(other);
+        ws_state.leave("other").await;
+        assert!(ws_state.rooms.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaving_a_room_keeps_clients_that_joined_meanwhile() {
+        let ws_state = AppWsState::default();
+        let (_, first) = ws_state.join("hub").await;
+        drop(first);
+        let (tx, _second) = ws_state.join("hub").await;
+        ws_state.leave("hub").await;
 
-    info!("WebSocket connection for hub {} closed", hub_id);
+        let rooms = ws_state.rooms.lock().await;
+        assert!(rooms["hub"].same_channel(&tx));
+    }
 }
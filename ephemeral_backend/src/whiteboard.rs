@@ -0,0 +1,1108 @@
+use crate::{
+    shared_types::{
+        BoardElement, Bounds, LoggedOperation, PathData, UndoHistory, WhiteboardAction, WsMessage,
+    },
+    stroke,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How many actions each user can undo.
+const MAX_UNDO_DEPTH: usize = 100;
+
+/// How many users' undo histories a page keeps.
+const MAX_UNDO_USERS: usize = 32;
+
+/// How many operations the log of a page keeps besides those that added
+/// the elements on it. Older ones are folded into a snapshot of the board.
+const MAX_LOG_LENGTH: usize = 1000;
+
+/// Resizing below this size would make elements impossible to grab again
+/// and lose information when scaling paths back up.
+const MIN_ELEMENT_SIZE: f64 = 1.0;
+
+// Limits on what clients may put on a board. Elements outside them are
+// rejected before they are stored, since every other client has to render them.
+const MAX_ID_LENGTH: usize = 128;
+const MAX_PATH_POINTS: usize = 10_000;
+const MAX_TEXT_LENGTH: usize = 10_000;
+const MAX_STROKE_WIDTH: f64 = 1000.0;
+const MAX_FONT_SIZE: f64 = 1000.0;
+// Coordinates and sizes further out than this couldn't be stored exactly.
+const MAX_COORDINATE: f64 = 1e9;
+
+/// How many whiteboard pages a single hub may hold.
+pub const MAX_PAGES: usize = 50;
+
+/// The most space the whiteboard pages of a single hub may take up in Redis,
+/// including their undo history.
+pub const MAX_WHITEBOARD_BYTES: usize = 5 * 1024 * 1024;
+
+/// The contents of a single whiteboard page, stored as JSON in Redis
+/// under its own key next to the hub.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WhiteboardPage {
+    pub elements: Vec<BoardElement>,
+    // Per-user undo/redo stacks, keyed by user id.
+    #[serde(default)]
+    pub history: HashMap<String, UndoHistory>,
+    // Every change made to the page, oldest first.
+    #[serde(default)]
+    pub log: Vec<LoggedOperation>,
+}
+
+impl WhiteboardPage {
+    /// A page with the strokes of a hub stored before hubs had pages, whose
+    /// log adds them as of `at`. Strokes that can't be stored are dropped.
+    pub fn from_paths(page_id: &str, paths: Vec<PathData>, at: DateTime<Utc>) -> WhiteboardPage {
+        let elements: Vec<_> = paths
+            .into_iter()
+            .map(BoardElement::Path)
+            .filter(|element| serde_json::to_string(element).is_ok())
+            .collect();
+        let log = elements
+            .iter()
+            .map(|element| LoggedOperation {
+                at,
+                user_id: element.author().to_string(),
+                message: WsMessage::ElementAdded {
+                    page_id: page_id.to_string(),
+                    element: element.clone(),
+                },
+            })
+            .collect();
+        WhiteboardPage {
+            elements,
+            history: HashMap::new(),
+            log,
+        }
+    }
+
+    /// Drops the undo history and folds the whole log into a snapshot of
+    /// the board, to make room on a full whiteboard.
+    pub fn forget_history(&mut self) {
+        self.history.clear();
+        self.compact_log(0);
+    }
+
+    // Replaces all but the last `keep` operations of the log with ones that
+    // add the elements they left on the board, so that replays still start
+    // from an empty board.
+    fn compact_log(&mut self, keep: usize) {
+        let fold = self.log.len().saturating_sub(keep);
+        let Some(last_folded) = fold.checked_sub(1).map(|i| &self.log[i]) else {
+            return;
+        };
+        let at = last_folded.at;
+        let page_id = last_folded
+            .message
+            .page_id()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut board = Vec::new();
+        for op in &self.log[..fold] {
+            apply_effect(&mut board, &op.message);
+        }
+        let snapshot: Vec<_> = board
+            .into_iter()
+            .map(|element| LoggedOperation {
+                at,
+                user_id: element.author().to_string(),
+                message: WsMessage::ElementAdded {
+                    page_id: page_id.clone(),
+                    element,
+                },
+            })
+            .collect();
+        self.log.splice(..fold, snapshot);
+    }
+}
+
+/// The Redis key a whiteboard page is stored under.
+pub fn page_key(hub_id: &str, page_id: &str) -> String {
+    format!("hub:{}:page:{}", hub_id, page_id)
+}
+
+/// Applies a message from a client to a whiteboard page and records its
+/// effects in the page's log.
+/// Returns the messages that should be broadcast to the room, which may be
+/// empty if the message had no effect (e.g. nothing left to undo).
+pub fn apply_message(page: &mut WhiteboardPage, msg: WsMessage) -> Vec<WsMessage> {
+    let user_id = match &msg {
+        WsMessage::ElementAdded { element, .. } => element.author().to_string(),
+        WsMessage::ElementDeleted { user_id, .. }
+        | WsMessage::ElementMoved { user_id, .. }
+        | WsMessage::ElementResized { user_id, .. }
+        | WsMessage::BoardCleared { user_id, .. }
+        | WsMessage::Undo { user_id, .. }
+        | WsMessage::Redo { user_id, .. } => user_id.clone(),
+        WsMessage::PageCreated(_)
+        | WsMessage::TextEdit { .. }
+        | WsMessage::TextCursor { .. }
+        | WsMessage::TextSnapshot { .. }
+        | WsMessage::TextUpdated { .. }
+        | WsMessage::SnippetUpdated { .. }
+        | WsMessage::SnippetDeleted { .. }
+        | WsMessage::TextLanguageChanged { .. }
+        | WsMessage::ServerRestarting => String::new(),
+    };
+
+    let effects = apply(page, msg);
+    let at = Utc::now();
+    page.log
+        .extend(effects.iter().map(|effect| LoggedOperation {
+            at,
+            user_id: user_id.clone(),
+            message: effect.clone(),
+        }));
+    // The snapshot is about as long as the board, so the log is only
+    // compacted once it has grown by half its length again.
+    if page.log.len() > MAX_LOG_LENGTH + page.elements.len() {
+        page.compact_log(MAX_LOG_LENGTH / 2);
+    }
+    effects
+}
+
+/// Whether the effects of a message only take elements off the board.
+/// These are always stored, so that a full whiteboard can be cleaned up.
+pub fn only_removes(effects: &[WsMessage]) -> bool {
+    effects.iter().all(|effect| {
+        matches!(
+            effect,
+            WsMessage::ElementDeleted { .. } | WsMessage::BoardCleared { .. }
+        )
+    })
+}
+
+/// The messages that take the effects of a message back for its author,
+/// who has already applied it locally, if it can't be stored. `original` is
+/// the board before the message was applied.
+pub fn compensate(original: &[BoardElement], effects: &[WsMessage]) -> Vec<WsMessage> {
+    let restore = |page_id: &str, id: &str| {
+        original
+            .iter()
+            .find(|e| e.id() == id)
+            .map(|element| WsMessage::ElementAdded {
+                page_id: page_id.to_string(),
+                element: element.clone(),
+            })
+    };
+    let mut messages = Vec::new();
+    for effect in effects.iter().rev() {
+        match effect {
+            WsMessage::ElementAdded { page_id, element } => {
+                messages.push(WsMessage::ElementDeleted {
+                    page_id: page_id.clone(),
+                    id: element.id().to_string(),
+                    user_id: element.author().to_string(),
+                });
+            }
+            WsMessage::ElementDeleted { page_id, id, .. } => {
+                messages.extend(restore(page_id, id));
+            }
+            WsMessage::ElementMoved {
+                page_id,
+                id,
+                user_id,
+                ..
+            } => {
+                if let Some(element) = original.iter().find(|e| e.id() == id) {
+                    let bounds = element.bounds();
+                    messages.push(WsMessage::ElementMoved {
+                        page_id: page_id.clone(),
+                        id: id.clone(),
+                        user_id: user_id.clone(),
+                        x: bounds.x,
+                        y: bounds.y,
+                    });
+                }
+            }
+            WsMessage::ElementResized {
+                page_id,
+                id,
+                user_id,
+                ..
+            } => {
+                if let Some(element) = original.iter().find(|e| e.id() == id) {
+                    messages.push(WsMessage::ElementResized {
+                        page_id: page_id.clone(),
+                        id: id.clone(),
+                        user_id: user_id.clone(),
+                        bounds: element.bounds(),
+                    });
+                }
+            }
+            WsMessage::BoardCleared { page_id, .. } => {
+                messages.extend(original.iter().map(|element| WsMessage::ElementAdded {
+                    page_id: page_id.clone(),
+                    element: element.clone(),
+                }));
+            }
+            _ => {}
+        }
+    }
+    messages
+}
+
+/// Applies a message broadcast by `apply_message` to a list of elements,
+/// e.g. to replay the log of a page.
+pub fn apply_effect(board: &mut Vec<BoardElement>, msg: &WsMessage) {
+    match msg {
+        WsMessage::ElementAdded { element, .. } => {
+            add_to(board, element, "");
+        }
+        WsMessage::ElementDeleted { id, .. } => {
+            delete_from(board, id, "", "");
+        }
+        WsMessage::ElementMoved { id, x, y, .. } => {
+            move_in(board, id, (*x, *y), "", "");
+        }
+        WsMessage::ElementResized { id, bounds, .. } => {
+            resize_in(board, id, *bounds, "", "");
+        }
+        WsMessage::BoardCleared { .. } => board.clear(),
+        WsMessage::Undo { .. }
+        | WsMessage::Redo { .. }
+        | WsMessage::PageCreated(_)
+        | WsMessage::TextEdit { .. }
+        | WsMessage::TextCursor { .. }
+        | WsMessage::TextSnapshot { .. }
+        | WsMessage::TextUpdated { .. }
+        | WsMessage::SnippetUpdated { .. }
+        | WsMessage::SnippetDeleted { .. }
+        | WsMessage::TextLanguageChanged { .. }
+        | WsMessage::ServerRestarting => {}
+    }
+}
+
+fn apply(page: &mut WhiteboardPage, msg: WsMessage) -> Vec<WsMessage> {
+    match msg {
+        WsMessage::ElementAdded {
+            page_id,
+            mut element,
+        } => {
+            simplify_element(&mut element);
+            let effects = add_to(&mut page.elements, &element, &page_id);
+            if !effects.is_empty() {
+                let author = element.author().to_string();
+                record(page, &author, WhiteboardAction::Added(element));
+            }
+            effects
+        }
+        WsMessage::ElementDeleted {
+            page_id,
+            id,
+            user_id,
+        } => {
+            let Some(index) = find(&page.elements, &id) else {
+                return Vec::new();
+            };
+            let element = page.elements.remove(index);
+            record(page, &user_id, WhiteboardAction::Deleted(element));
+            vec![WsMessage::ElementDeleted {
+                page_id,
+                id,
+                user_id,
+            }]
+        }
+        WsMessage::ElementMoved {
+            page_id,
+            id,
+            user_id,
+            x,
+            y,
+        } => {
+            if !(x.is_finite() && y.is_finite()) {
+                return Vec::new();
+            }
+            let Some(index) = find(&page.elements, &id) else {
+                return Vec::new();
+            };
+            let before = page.elements[index].bounds();
+            let effects = move_in(&mut page.elements, &id, (x, y), &page_id, &user_id);
+            record(
+                page,
+                &user_id,
+                WhiteboardAction::Moved {
+                    id,
+                    from: (before.x, before.y),
+                    to: (x, y),
+                },
+            );
+            effects
+        }
+        WsMessage::ElementResized {
+            page_id,
+            id,
+            user_id,
+            bounds,
+        } => {
+            if !is_valid_size(&bounds) {
+                return Vec::new();
+            }
+            let Some(index) = find(&page.elements, &id) else {
+                return Vec::new();
+            };
+            let before = page.elements[index].bounds();
+            let effects = resize_in(&mut page.elements, &id, bounds, &page_id, &user_id);
+            record(
+                page,
+                &user_id,
+                WhiteboardAction::Resized {
+                    id,
+                    before,
+                    after: bounds,
+                },
+            );
+            effects
+        }
+        WsMessage::BoardCleared { page_id, user_id } => {
+            if page.elements.is_empty() {
+                return Vec::new();
+            }
+            let cleared = std::mem::take(&mut page.elements);
+            record(page, &user_id, WhiteboardAction::Cleared(cleared));
+            vec![WsMessage::BoardCleared { page_id, user_id }]
+        }
+        WsMessage::Undo { page_id, user_id } => {
+            let history = history_of(&mut page.history, &user_id);
+            while let Some(action) = history.undo.pop_back() {
+                let effects = revert(&mut page.elements, &action, &page_id, &user_id);
+                if !effects.is_empty() {
+                    history.redo.push(action);
+                    return effects;
+                }
+            }
+            Vec::new()
+        }
+        WsMessage::Redo { page_id, user_id } => {
+            let history = history_of(&mut page.history, &user_id);
+            while let Some(action) = history.redo.pop() {
+                let effects = replay(&mut page.elements, &action, &page_id, &user_id);
+                if !effects.is_empty() {
+                    history.undo.push_back(action);
+                    return effects;
+                }
+            }
+            Vec::new()
+        }
+        // Page management goes through the REST API.
+        WsMessage::PageCreated(_)
+        | WsMessage::TextEdit { .. }
+        | WsMessage::TextCursor { .. }
+        | WsMessage::TextSnapshot { .. }
+        | WsMessage::TextUpdated { .. }
+        | WsMessage::SnippetUpdated { .. }
+        | WsMessage::SnippetDeleted { .. }
+        | WsMessage::TextLanguageChanged { .. }
+        | WsMessage::ServerRestarting => Vec::new(),
+    }
+}
+
+/// Checks that a message from a client only contains values that every
+/// client can render, e.g. well-formed colors and finite coordinates.
+pub fn validate_message(msg: &WsMessage) -> Result<(), String> {
+    match msg {
+        WsMessage::ElementAdded { page_id, element } => {
+            validate_id(page_id)?;
+            validate_element(element)
+        }
+        WsMessage::ElementDeleted {
+            page_id,
+            id,
+            user_id,
+        } => [page_id, id, user_id]
+            .into_iter()
+            .try_for_each(|s| validate_id(s)),
+        WsMessage::ElementMoved {
+            page_id,
+            id,
+            user_id,
+            x,
+            y,
+        } => {
+            [page_id, id, user_id]
+                .into_iter()
+                .try_for_each(|s| validate_id(s))?;
+            validate_point((*x, *y))
+        }
+        WsMessage::ElementResized {
+            page_id,
+            id,
+            user_id,
+            bounds,
+        } => {
+            [page_id, id, user_id]
+                .into_iter()
+                .try_for_each(|s| validate_id(s))?;
+            validate_bounds(bounds)
+        }
+        WsMessage::BoardCleared { page_id, user_id }
+        | WsMessage::Undo { page_id, user_id }
+        | WsMessage::Redo { page_id, user_id } => {
+            validate_id(page_id)?;
+            validate_id(user_id)
+        }
+        WsMessage::TextEdit { user_id, .. } | WsMessage::TextCursor { user_id, .. } => {
+            validate_id(user_id)
+        }
+        WsMessage::PageCreated(_) => Err("pages can't be created over WebSocket".to_string()),
+        WsMessage::TextSnapshot { .. } | WsMessage::TextUpdated { .. } => {
+            Err("text snapshots are only sent by the server".to_string())
+        }
+        WsMessage::SnippetUpdated { .. }
+        | WsMessage::SnippetDeleted { .. }
+        | WsMessage::TextLanguageChanged { .. } => {
+            Err("only sent by the server after a change through the REST API".to_string())
+        }
+        WsMessage::ServerRestarting => Err("only sent by the server".to_string()),
+    }
+}
+
+fn validate_element(element: &BoardElement) -> Result<(), String> {
+    validate_id(element.id())?;
+    validate_id(element.author())?;
+    match element {
+        BoardElement::Path(p) => {
+            validate_color(&p.color)?;
+            validate_stroke_width(p.stroke_width)?;
+            if !(0.0..=1.0).contains(&p.opacity) {
+                return Err(format!("invalid opacity {}", p.opacity));
+            }
+            if p.points.len() > MAX_PATH_POINTS {
+                return Err(format!("path has more than {} points", MAX_PATH_POINTS));
+            }
+            for point in &p.points {
+                validate_point((point.x, point.y))?;
+                if let Some(pressure) = point.pressure
+                    && !(0.0..=1.0).contains(&pressure)
+                {
+                    return Err(format!("invalid pressure {}", pressure));
+                }
+            }
+            Ok(())
+        }
+        BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => {
+            validate_color(&s.color)?;
+            validate_stroke_width(s.stroke_width)?;
+            validate_bounds(&s.bounds)
+        }
+        BoardElement::Line(l) | BoardElement::Arrow(l) => {
+            validate_color(&l.color)?;
+            validate_stroke_width(l.stroke_width)?;
+            validate_point(l.start)?;
+            validate_point(l.end)
+        }
+        BoardElement::Text(t) => {
+            validate_color(&t.color)?;
+            validate_text(&t.text)?;
+            validate_point(t.position)?;
+            if !(t.font_size.is_finite() && t.font_size > 0.0 && t.font_size <= MAX_FONT_SIZE) {
+                return Err(format!("invalid font size {}", t.font_size));
+            }
+            Ok(())
+        }
+        BoardElement::StickyNote(n) => {
+            validate_color(&n.color)?;
+            validate_text(&n.text)?;
+            validate_bounds(&n.bounds)
+        }
+    }
+}
+
+// Ids are made of the characters of nanoids and UUIDs, so they can be put
+// into keys and markup as they are.
+fn validate_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("empty id".to_string());
+    }
+    if id.len() > MAX_ID_LENGTH {
+        return Err(format!("id longer than {} bytes", MAX_ID_LENGTH));
+    }
+    if !id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(format!(
+            "id `{}` may only contain letters, digits, `-` and `_`",
+            id
+        ));
+    }
+    Ok(())
+}
+
+// Only hex colors like `#3b82f6` are accepted, so nothing else ends up in
+// the SVG attributes clients render them into.
+fn validate_color(color: &str) -> Result<(), String> {
+    let valid = color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid color {:?}", color))
+    }
+}
+
+fn validate_stroke_width(width: f64) -> Result<(), String> {
+    if width.is_finite() && width > 0.0 && width <= MAX_STROKE_WIDTH {
+        Ok(())
+    } else {
+        Err(format!("invalid stroke width {}", width))
+    }
+}
+
+fn validate_point((x, y): (f64, f64)) -> Result<(), String> {
+    if is_in_range(x) && is_in_range(y) {
+        Ok(())
+    } else {
+        Err(format!(
+            "coordinates must be finite and within ±{}",
+            MAX_COORDINATE
+        ))
+    }
+}
+
+fn is_in_range(value: f64) -> bool {
+    value.is_finite() && value.abs() <= MAX_COORDINATE
+}
+
+fn validate_bounds(bounds: &Bounds) -> Result<(), String> {
+    validate_point((bounds.x, bounds.y))?;
+    if is_in_range(bounds.width)
+        && is_in_range(bounds.height)
+        && bounds.width >= 0.0
+        && bounds.height >= 0.0
+    {
+        Ok(())
+    } else {
+        Err("invalid bounds".to_string())
+    }
+}
+
+fn validate_text(text: &str) -> Result<(), String> {
+    if text.chars().count() > MAX_TEXT_LENGTH {
+        return Err(format!("text longer than {} characters", MAX_TEXT_LENGTH));
+    }
+    Ok(())
+}
+
+// Drops the points of a path that don't change how it looks, in case the
+// client didn't simplify it already.
+fn simplify_element(element: &mut BoardElement) {
+    if let BoardElement::Path(p) = element {
+        p.points = stroke::simplify(&p.points, p.stroke_width * stroke::SIMPLIFY_TOLERANCE);
+    }
+}
+
+// Pushes a new action onto the user's undo stack and invalidates their redo stack.
+fn record(page: &mut WhiteboardPage, user_id: &str, action: WhiteboardAction) {
+    if user_id.is_empty() {
+        return;
+    }
+    let history = history_of(&mut page.history, user_id);
+    history.undo.push_back(action);
+    if history.undo.len() > MAX_UNDO_DEPTH {
+        history.undo.pop_front();
+    }
+    history.redo.clear();
+}
+
+// The undo history of a user, making room for it if needed by forgetting
+// the one that was used least recently.
+fn history_of<'a>(
+    histories: &'a mut HashMap<String, UndoHistory>,
+    user_id: &str,
+) -> &'a mut UndoHistory {
+    if !histories.contains_key(user_id) && histories.len() >= MAX_UNDO_USERS {
+        let oldest = histories
+            .iter()
+            .min_by_key(|(_, history)| history.last_used)
+            .map(|(user_id, _)| user_id.clone());
+        if let Some(oldest) = oldest {
+            histories.remove(&oldest);
+        }
+    }
+    let history = histories.entry(user_id.to_string()).or_default();
+    history.last_used = Utc::now();
+    history
+}
+
+fn is_valid_size(bounds: &Bounds) -> bool {
+    [bounds.x, bounds.y, bounds.width, bounds.height]
+        .iter()
+        .all(|v| v.is_finite())
+        && bounds.width >= MIN_ELEMENT_SIZE
+        && bounds.height >= MIN_ELEMENT_SIZE
+}
+
+// Undoes an action. Returns no messages if the board no longer matches the
+// action, e.g. because another user already deleted the element.
+fn revert(
+    board: &mut Vec<BoardElement>,
+    action: &WhiteboardAction,
+    page_id: &str,
+    user_id: &str,
+) -> Vec<WsMessage> {
+    match action {
+        WhiteboardAction::Added(element) => delete_from(board, element.id(), page_id, user_id),
+        WhiteboardAction::Deleted(element) => add_to(board, element, page_id),
+        WhiteboardAction::Moved { id, from, .. } => move_in(board, id, *from, page_id, user_id),
+        WhiteboardAction::Resized { id, before, .. } => {
+            resize_in(board, id, *before, page_id, user_id)
+        }
+        WhiteboardAction::Cleared(elements) => elements
+            .iter()
+            .flat_map(|element| add_to(board, element, page_id))
+            .collect(),
+    }
+}
+
+// Redoes a previously undone action.
+fn replay(
+    board: &mut Vec<BoardElement>,
+    action: &WhiteboardAction,
+    page_id: &str,
+    user_id: &str,
+) -> Vec<WsMessage> {
+    match action {
+        WhiteboardAction::Added(element) => add_to(board, element, page_id),
+        WhiteboardAction::Deleted(element) => delete_from(board, element.id(), page_id, user_id),
+        WhiteboardAction::Moved { id, to, .. } => move_in(board, id, *to, page_id, user_id),
+        WhiteboardAction::Resized { id, after, .. } => {
+            resize_in(board, id, *after, page_id, user_id)
+        }
+        // Only the elements that were cleared are removed again, not those
+        // others added since.
+        WhiteboardAction::Cleared(elements) => {
+            let cleared = |e: &BoardElement| elements.iter().any(|c| c.id() == e.id());
+            if !board.is_empty() && board.iter().all(cleared) {
+                board.clear();
+                return vec![WsMessage::BoardCleared {
+                    page_id: page_id.to_string(),
+                    user_id: user_id.to_string(),
+                }];
+            }
+            elements
+                .iter()
+                .flat_map(|element| delete_from(board, element.id(), page_id, user_id))
+                .collect()
+        }
+    }
+}
+
+fn find(board: &[BoardElement], id: &str) -> Option<usize> {
+    board.iter().position(|e| e.id() == id)
+}
+
+fn add_to(board: &mut Vec<BoardElement>, element: &BoardElement, page_id: &str) -> Vec<WsMessage> {
+    if find(board, element.id()).is_some() {
+        return Vec::new();
+    }
+    board.push(element.clone());
+    vec![WsMessage::ElementAdded {
+        page_id: page_id.to_string(),
+        element: element.clone(),
+    }]
+}
+
+fn delete_from(
+    board: &mut Vec<BoardElement>,
+    id: &str,
+    page_id: &str,
+    user_id: &str,
+) -> Vec<WsMessage> {
+    let Some(index) = find(board, id) else {
+        return Vec::new();
+    };
+    board.remove(index);
+    vec![WsMessage::ElementDeleted {
+        page_id: page_id.to_string(),
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+    }]
+}
+
+fn move_in(
+    board: &mut [BoardElement],
+    id: &str,
+    (x, y): (f64, f64),
+    page_id: &str,
+    user_id: &str,
+) -> Vec<WsMessage> {
+    let Some(index) = find(board, id) else {
+        return Vec::new();
+    };
+    board[index].move_to(x, y);
+    vec![WsMessage::ElementMoved {
+        page_id: page_id.to_string(),
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+        x,
+        y,
+    }]
+}
+
+fn resize_in(
+    board: &mut [BoardElement],
+    id: &str,
+    bounds: Bounds,
+    page_id: &str,
+    user_id: &str,
+) -> Vec<WsMessage> {
+    let Some(index) = find(board, id) else {
+        return Vec::new();
+    };
+    board[index].set_bounds(bounds);
+    vec![WsMessage::ElementResized {
+        page_id: page_id.to_string(),
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+        bounds,
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_types::{PathData, ShapeData, StrokePoint};
+
+    const PAGE: &str = "page";
+
+    fn path(id: &str, points: &[(f64, f64)]) -> BoardElement {
+        BoardElement::Path(PathData {
+            id: id.to_string(),
+            points: points
+                .iter()
+                .map(|&(x, y)| StrokePoint {
+                    x,
+                    y,
+                    pressure: None,
+                })
+                .collect(),
+            color: "#000000".to_string(),
+            stroke_width: 2.0,
+            author: "alice".to_string(),
+            opacity: 1.0,
+            highlighter: false,
+        })
+    }
+
+    fn bounds(x: f64, y: f64, width: f64, height: f64) -> Bounds {
+        Bounds {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn rectangle(id: &str, author: &str, x: f64, y: f64) -> BoardElement {
+        BoardElement::Rectangle(ShapeData {
+            id: id.to_string(),
+            author: author.to_string(),
+            bounds: bounds(x, y, 10.0, 10.0),
+            color: "#000000".to_string(),
+            stroke_width: 2.0,
+        })
+    }
+
+    fn added(element: BoardElement) -> WsMessage {
+        WsMessage::ElementAdded {
+            page_id: PAGE.to_string(),
+            element,
+        }
+    }
+
+    #[test]
+    fn extreme_coordinates_are_rejected() {
+        for far in [1e300, -1e10, f64::INFINITY] {
+            let stroke = path("a", &[(0.0, 0.0), (far, 0.0)]);
+            assert!(validate_message(&added(stroke)).is_err());
+
+            let moved = WsMessage::ElementMoved {
+                page_id: PAGE.to_string(),
+                id: "a".to_string(),
+                user_id: "alice".to_string(),
+                x: 0.0,
+                y: far,
+            };
+            assert!(validate_message(&moved).is_err());
+
+            let resized = WsMessage::ElementResized {
+                page_id: PAGE.to_string(),
+                id: "a".to_string(),
+                user_id: "alice".to_string(),
+                bounds: bounds(0.0, 0.0, far.abs(), 10.0),
+            };
+            assert!(validate_message(&resized).is_err());
+        }
+
+        let edge = path("a", &[(-MAX_COORDINATE, 0.0), (MAX_COORDINATE, 0.0)]);
+        assert!(validate_message(&added(edge)).is_ok());
+    }
+
+    #[test]
+    fn strokes_of_hubs_without_pages_get_a_page() {
+        let paths: Vec<PathData> =
+            serde_json::from_str(r##"[
+                {"id": "a", "points": [[1.0, 2.0], [3.0, 4.0]], "color": "#000000", "stroke_width": 2.0},
+                {"id": "b", "points": [[0.0, 0.0], [1e300, 0.0]], "color": "#000000", "stroke_width": 2.0}
+            ]"##)
+            .unwrap();
+
+        let page = WhiteboardPage::from_paths(PAGE, paths, Utc::now());
+
+        assert_eq!(page.elements.len(), 1);
+        assert_eq!(page.elements[0].id(), "a");
+        assert_eq!(replayed(&page), page.elements);
+        assert!(serde_json::to_string(&page).is_ok());
+    }
+
+    #[test]
+    fn ids_are_restricted_to_safe_characters() {
+        for id in [
+            "V1StGXR8_Z5jdHi6B-myT",
+            "3f2b8c1e-9d4a-4c7b-8e6f-0a1b2c3d4e5f",
+        ] {
+            assert!(validate_message(&added(rectangle(id, id, 0.0, 0.0))).is_ok());
+        }
+        let long = "a".repeat(MAX_ID_LENGTH + 1);
+        for id in ["", "a b", "<svg>", "a\"b", "page:1", "ä", long.as_str()] {
+            assert!(validate_message(&added(rectangle(id, "alice", 0.0, 0.0))).is_err());
+            assert!(validate_message(&moved("a", id, 0.0, 0.0)).is_err());
+        }
+    }
+
+    #[test]
+    fn pages_with_far_out_strokes_can_be_read_back() {
+        let mut page = WhiteboardPage::default();
+        let far = [
+            (-MAX_COORDINATE, MAX_COORDINATE),
+            (MAX_COORDINATE, -MAX_COORDINATE),
+        ];
+        apply_message(&mut page, added(path("a", &far)));
+        apply_message(&mut page, added(rectangle("b", "alice", 0.0, 0.0)));
+
+        let json = serde_json::to_string(&page).unwrap();
+        let stored: WhiteboardPage = serde_json::from_str(&json).unwrap();
+        assert_eq!(stored.elements, page.elements);
+    }
+
+    fn moved(id: &str, user_id: &str, x: f64, y: f64) -> WsMessage {
+        WsMessage::ElementMoved {
+            page_id: PAGE.to_string(),
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            x,
+            y,
+        }
+    }
+
+    fn replayed(page: &WhiteboardPage) -> Vec<BoardElement> {
+        let mut board = Vec::new();
+        for op in &page.log {
+            apply_effect(&mut board, &op.message);
+        }
+        board
+    }
+
+    #[test]
+    fn the_log_is_compacted_but_still_replays_the_board() {
+        let mut page = WhiteboardPage::default();
+        for i in 0..10 {
+            apply_message(
+                &mut page,
+                added(rectangle(&i.to_string(), "alice", 0.0, 0.0)),
+            );
+        }
+        for step in 0..3 * MAX_LOG_LENGTH {
+            let id = (step % 10).to_string();
+            apply_message(&mut page, moved(&id, "alice", step as f64, 0.0));
+        }
+
+        assert!(page.log.len() <= MAX_LOG_LENGTH + page.elements.len());
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn forgetting_history_keeps_the_board() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        apply_message(&mut page, added(rectangle("b", "bob", 0.0, 0.0)));
+        apply_message(&mut page, moved("a", "alice", 5.0, 5.0));
+
+        page.forget_history();
+
+        assert!(page.history.is_empty());
+        assert_eq!(page.log.len(), 2);
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn only_recent_users_keep_their_history() {
+        let mut page = WhiteboardPage::default();
+        for i in 0..MAX_UNDO_USERS {
+            let user = format!("user{}", i);
+            apply_message(&mut page, added(rectangle(&user, &user, 0.0, 0.0)));
+        }
+        // Everyone but `user0` used the page more recently.
+        let long_ago = Utc::now() - chrono::Duration::hours(1);
+        page.history.get_mut("user0").unwrap().last_used = long_ago;
+
+        apply_message(&mut page, added(rectangle("new", "newcomer", 0.0, 0.0)));
+
+        assert_eq!(page.history.len(), MAX_UNDO_USERS);
+        assert!(page.history.contains_key("newcomer"));
+        assert!(!page.history.contains_key("user0"));
+    }
+
+    #[test]
+    fn rejected_changes_are_taken_back() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        let original = page.elements.clone();
+
+        let mut effects = apply_message(&mut page, moved("a", "alice", 50.0, 50.0));
+        effects.extend(apply_message(
+            &mut page,
+            added(rectangle("b", "alice", 0.0, 0.0)),
+        ));
+        assert!(!only_removes(&effects));
+
+        let mut board = page.elements.clone();
+        for msg in compensate(&original, &effects) {
+            apply_effect(&mut board, &msg);
+        }
+        assert_eq!(board, original);
+    }
+
+    #[test]
+    fn removals_are_recognized() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        let cleared = apply_message(
+            &mut page,
+            WsMessage::BoardCleared {
+                page_id: PAGE.to_string(),
+                user_id: "bob".to_string(),
+            },
+        );
+        assert!(only_removes(&cleared));
+    }
+
+    fn undo(user_id: &str) -> WsMessage {
+        WsMessage::Undo {
+            page_id: PAGE.to_string(),
+            user_id: user_id.to_string(),
+        }
+    }
+
+    fn redo(user_id: &str) -> WsMessage {
+        WsMessage::Redo {
+            page_id: PAGE.to_string(),
+            user_id: user_id.to_string(),
+        }
+    }
+
+    fn cleared(user_id: &str) -> WsMessage {
+        WsMessage::BoardCleared {
+            page_id: PAGE.to_string(),
+            user_id: user_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn adding_can_be_undone_and_redone() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        let board = page.elements.clone();
+
+        assert!(!apply_message(&mut page, undo("alice")).is_empty());
+        assert!(page.elements.is_empty());
+        assert!(!apply_message(&mut page, redo("alice")).is_empty());
+        assert_eq!(page.elements, board);
+        assert!(apply_message(&mut page, redo("alice")).is_empty());
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn moving_and_resizing_can_be_undone_and_redone() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        let before = page.elements.clone();
+        apply_message(&mut page, moved("a", "alice", 20.0, 30.0));
+        apply_message(
+            &mut page,
+            WsMessage::ElementResized {
+                page_id: PAGE.to_string(),
+                id: "a".to_string(),
+                user_id: "alice".to_string(),
+                bounds: bounds(20.0, 30.0, 40.0, 50.0),
+            },
+        );
+        let after = page.elements.clone();
+
+        apply_message(&mut page, undo("alice"));
+        assert_eq!(page.elements[0].bounds(), bounds(20.0, 30.0, 10.0, 10.0));
+        apply_message(&mut page, undo("alice"));
+        assert_eq!(page.elements, before);
+
+        apply_message(&mut page, redo("alice"));
+        apply_message(&mut page, redo("alice"));
+        assert_eq!(page.elements, after);
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn clearing_can_be_undone_and_redone() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        apply_message(&mut page, added(rectangle("b", "bob", 0.0, 0.0)));
+        let board = page.elements.clone();
+        apply_message(&mut page, cleared("alice"));
+
+        apply_message(&mut page, undo("alice"));
+        assert_eq!(page.elements, board);
+        apply_message(&mut page, redo("alice"));
+        assert!(page.elements.is_empty());
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn redoing_a_clear_keeps_elements_added_since() {
+        let mut page = WhiteboardPage::default();
+        apply_message(&mut page, added(rectangle("a", "alice", 0.0, 0.0)));
+        apply_message(&mut page, cleared("alice"));
+        apply_message(&mut page, undo("alice"));
+        apply_message(&mut page, added(rectangle("b", "bob", 0.0, 0.0)));
+
+        let effects = apply_message(&mut page, redo("alice"));
+
+        assert!(only_removes(&effects));
+        assert_eq!(page.elements, vec![rectangle("b", "bob", 0.0, 0.0)]);
+        assert_eq!(replayed(&page), page.elements);
+    }
+
+    #[test]
+    fn only_the_most_recent_actions_can_be_undone() {
+        let mut page = WhiteboardPage::default();
+        for i in 0..MAX_UNDO_DEPTH + 5 {
+            apply_message(
+                &mut page,
+                added(rectangle(&i.to_string(), "alice", 0.0, 0.0)),
+            );
+        }
+
+        let mut undone = 0;
+        while !apply_message(&mut page, undo("alice")).is_empty() {
+            undone += 1;
+        }
+
+        assert_eq!(undone, MAX_UNDO_DEPTH);
+        assert_eq!(page.elements.len(), 5);
+        assert_eq!(page.elements[0].id(), "0");
+    }
+}
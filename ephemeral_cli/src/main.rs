@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use comfy_table::Table;
 use serde::Deserialize;
 use spinners::{Spinner, Spinners};
@@ -37,6 +37,13 @@ enum Commands {
     Pipe {
         /// The URL of the hub.
         url: String,
+        /// Pipe into the named snippet (e.g. `error.log`) instead of the text bin.
+        /// The snippet is created if it doesn't exist yet.
+        #[arg(long)]
+        name: Option<String>,
+        /// Only replace the text if the hub still has this ETag.
+        #[arg(long)]
+        if_match: Option<String>,
     },
     /// Upload a file to a hub.
     Upload {
@@ -44,12 +51,44 @@ enum Commands {
         file_path: PathBuf,
         /// The URL of the hub.
         url: String,
+        /// Only upload the file if the hub still has this ETag.
+        #[arg(long)]
+        if_match: Option<String>,
     },
     /// Download all content from a hub as a zip file.
     Get {
         /// The URL of the hub.
         url: String,
     },
+    /// Download an image of a hub's whiteboard.
+    ExportBoard {
+        /// The URL of the hub.
+        url: String,
+        /// The image format.
+        #[arg(long, value_enum, default_value_t = BoardFormat::Png)]
+        format: BoardFormat,
+        /// The ID of the whiteboard page to export. Defaults to the first page.
+        #[arg(long)]
+        page: Option<String>,
+        /// Where to save the image. Defaults to `whiteboard_{hub id}.{format}`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BoardFormat {
+    Svg,
+    Png,
+}
+
+impl BoardFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            BoardFormat::Svg => "svg",
+            BoardFormat::Png => "png",
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -61,6 +100,26 @@ struct CreateHubResponse {
     _text_url: String,
     expires_at: DateTime<Utc>,
 }
+// The body of the server's error responses.
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    message: String,
+    request_id: String,
+}
+
+// Describes a failed request with the server's message, falling back to the
+// status for servers that don't send one.
+async fn server_error(res: reqwest::Response) -> String {
+    let status = res.status();
+    match res.json::<ErrorResponse>().await {
+        Ok(error) => format!(
+            "{} (Status: {}, request ID: {})",
+            error.message, status, error.request_id
+        ),
+        Err(_) => format!("Status: {}", status),
+    }
+}
+
 // Gets the API base URL from an environment variable, with a production default.
 fn get_api_base_url() -> String {
     env::var("EPHEMERAL_API_URL").unwrap_or_else(|_| "https://api.ephemeral-hub.com".to_string())
@@ -70,14 +129,42 @@ fn get_api_base_url() -> String {
 fn extract_hub_id(url: &str) -> Option<String> {
     let parts: Vec<&str> = url.split('/').collect();
     // Handles URLs like .../hubs/{id}, .../hubs/{id}/text, .../hubs/{id}/files, etc.
-    if let Some(hubs_index) = parts.iter().position(|&p| p == "hubs") {
-        if hubs_index + 1 < parts.len() {
-            return Some(parts[hubs_index + 1].to_string());
-        }
+    if let Some(hubs_index) = parts.iter().position(|&p| p == "hubs")
+        && hubs_index + 1 < parts.len()
+    {
+        return Some(parts[hubs_index + 1].to_string());
     }
     None
 }
 
+// The API URL of a named snippet, with the name escaped as a path segment.
+fn snippet_url(api_base_url: &str, hub_id: &str, name: &str) -> String {
+    let mut url = reqwest::Url::parse(api_base_url).expect("invalid EPHEMERAL_API_URL");
+    url.path_segments_mut()
+        .expect("invalid EPHEMERAL_API_URL")
+        .pop_if_empty()
+        .extend(["api", "hubs", hub_id, "snippets", name]);
+    url.to_string()
+}
+
+// Adds an `If-Match` header to a request if an ETag was given.
+fn with_if_match(
+    request: reqwest::RequestBuilder,
+    if_match: Option<&str>,
+) -> reqwest::RequestBuilder {
+    match if_match {
+        Some(etag) => request.header(reqwest::header::IF_MATCH, etag),
+        None => request,
+    }
+}
+
+// The ETag the server returned for the updated hub.
+fn etag(res: &reqwest::Response) -> Option<&str> {
+    res.headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+}
+
 #[tokio::main]
 async fn main() {
     println!("{}", EPHEMERAL_BANNER);
@@ -95,7 +182,11 @@ async fn main() {
             let mut sp = Spinner::new(Spinners::Dots9, "Creating a new hub...".into());
             let api_url = format!("{}/api/hubs", api_base_url);
 
-            let response = client.post(&api_url).send().await;
+            let response = client
+                .post(&api_url)
+                .header(reqwest::header::ACCEPT, "application/json")
+                .send()
+                .await;
 
             sp.stop();
 
@@ -122,7 +213,7 @@ async fn main() {
                             }
                         }
                     } else {
-                        println!("Error: Failed to create hub (Status: {})", res.status());
+                        println!("Error: Failed to create hub: {}", server_error(res).await);
                     }
                 }
                 Err(e) => {
@@ -130,23 +221,47 @@ async fn main() {
                 }
             }
         }
-        Commands::Pipe { url } => {
+        Commands::Pipe {
+            url,
+            name,
+            if_match,
+        } => {
             if let Some(hub_id) = extract_hub_id(&url) {
                 let mut sp = Spinner::new(Spinners::Dots9, "Piping content...".into());
-                let api_url = format!("{}/api/hubs/{}/text", api_base_url, hub_id);
+                let api_url = match &name {
+                    Some(name) => snippet_url(&api_base_url, &hub_id, name),
+                    None => format!("{}/api/hubs/{}/text", api_base_url, hub_id),
+                };
 
                 let mut buffer = String::new();
                 io::stdin().read_to_string(&mut buffer).unwrap();
 
-                let response = client.put(&api_url).body(buffer).send().await;
+                let response = with_if_match(client.put(&api_url), if_match.as_deref())
+                    .body(buffer)
+                    .send()
+                    .await;
                 sp.stop();
 
                 match response {
                     Ok(res) if res.status().is_success() => {
-                        println!("\n✓ Content piped successfully!");
+                        match &name {
+                            Some(name) => println!("\n✓ Content piped into '{}'!", name),
+                            None => println!("\n✓ Content piped successfully!"),
+                        }
+                        if let Some(etag) = etag(&res) {
+                            println!("ETag: {}", etag);
+                        }
+                    }
+                    Ok(res) if res.status() == reqwest::StatusCode::PRECONDITION_FAILED => {
+                        println!(
+                            "\nError: The hub has changed since you fetched it. Nothing was piped."
+                        );
                     }
                     Ok(res) => {
-                        println!("\nError: Failed to pipe content (Status: {})", res.status());
+                        println!(
+                            "\nError: Failed to pipe content: {}",
+                            server_error(res).await
+                        );
                     }
                     Err(e) => {
                         println!("\nError: Could not connect to the server: {}", e);
@@ -156,7 +271,11 @@ async fn main() {
                 println!("Error: Invalid URL format provided.");
             }
         }
-        Commands::Upload { file_path, url } => {
+        Commands::Upload {
+            file_path,
+            url,
+            if_match,
+        } => {
             if !file_path.exists() {
                 println!("Error: File not found at '{}'", file_path.display());
                 return;
@@ -172,15 +291,33 @@ async fn main() {
                 let part = reqwest::multipart::Part::bytes(file_bytes).file_name(file_name);
                 let form = reqwest::multipart::Form::new().part("file", part);
 
-                let response = client.post(&api_url).multipart(form).send().await;
+                let response = with_if_match(client.post(&api_url), if_match.as_deref())
+                    .multipart(form)
+                    .send()
+                    .await;
                 sp.stop();
 
                 match response {
                     Ok(res) if res.status().is_success() => {
                         println!("\n✓ File uploaded successfully!");
+                        if let Some(etag) = etag(&res) {
+                            println!("ETag: {}", etag);
+                        }
+                        // The server replies with the direct URL of the file.
+                        for url in res.text().await.unwrap_or_default().lines() {
+                            println!("URL: {}", url);
+                        }
+                    }
+                    Ok(res) if res.status() == reqwest::StatusCode::PRECONDITION_FAILED => {
+                        println!(
+                            "\nError: The hub has changed since you fetched it. Nothing was uploaded."
+                        );
                     }
                     Ok(res) => {
-                        println!("\nError: Failed to upload file (Status: {})", res.status());
+                        println!(
+                            "\nError: Failed to upload file: {}",
+                            server_error(res).await
+                        );
                     }
                     Err(e) => {
                         println!("\nError: Could not connect to the server: {}", e);
@@ -206,7 +343,55 @@ async fn main() {
                         println!("\n✓ Hub content downloaded to '{}'", file_name);
                     }
                     Ok(res) => {
-                        println!("\nError: Failed to download hub (Status: {})", res.status());
+                        println!(
+                            "\nError: Failed to download hub: {}",
+                            server_error(res).await
+                        );
+                    }
+                    Err(e) => {
+                        println!("\nError: Could not connect to the server: {}", e);
+                    }
+                }
+            } else {
+                println!("Error: Invalid URL format provided.");
+            }
+        }
+        Commands::ExportBoard {
+            url,
+            format,
+            page,
+            output,
+        } => {
+            if let Some(hub_id) = extract_hub_id(&url) {
+                let mut sp = Spinner::new(Spinners::Dots9, "Exporting whiteboard...".into());
+                let api_url = format!(
+                    "{}/api/hubs/{}/whiteboard.{}",
+                    api_base_url,
+                    hub_id,
+                    format.extension()
+                );
+
+                let mut request = client.get(&api_url);
+                if let Some(page) = &page {
+                    request = request.query(&[("page", page)]);
+                }
+                let response = request.send().await;
+                sp.stop();
+
+                match response {
+                    Ok(res) if res.status().is_success() => {
+                        let file_name = output.unwrap_or_else(|| {
+                            PathBuf::from(format!("whiteboard_{}.{}", hub_id, format.extension()))
+                        });
+                        let bytes = res.bytes().await.unwrap();
+                        fs::write(&file_name, bytes).await.unwrap();
+                        println!("\n✓ Whiteboard exported to '{}'", file_name.display());
+                    }
+                    Ok(res) => {
+                        println!(
+                            "\nError: Failed to export whiteboard: {}",
+                            server_error(res).await
+                        );
                     }
                     Err(e) => {
                         println!("\nError: Could not connect to the server: {}", e);
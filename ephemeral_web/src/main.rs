@@ -1,4 +1,6 @@
 // The prelude brings all the essential Dioxus items into scope.
+use dioxus::html::geometry::{ClientPoint, WheelDelta};
+use dioxus::html::input_data::MouseButton;
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
 use futures::{SinkExt, StreamExt};
@@ -8,6 +10,7 @@ use js_sys;
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::HashMap;
 use std::time::Duration;
 use uuid::Uuid;
 use wasm_bindgen::JsCast;
@@ -91,9 +94,16 @@ fn Home() -> Element {
                 }
 
                 let client = reqwest::Client::new();
-                let response = client.post(api_url).send().await;
+                let response = client
+                    .post(api_url)
+                    .header("Accept", "application/json")
+                    .send()
+                    .await;
 
                 match response {
+                    Ok(resp) if !resp.status().is_success() => {
+                        log::error!("Failed to create hub: {}", error_message(resp).await);
+                    }
                     Ok(resp) => {
                         if let Ok(data) = resp.json::<CreateHubResponse>().await {
                             // Because this is run in a coroutine, the navigator
@@ -400,7 +410,14 @@ struct HubData {
     content: String,
     created_at: String,
     files: Vec<FileInfo>,
-    whiteboard: Vec<PathData>,
+    #[serde(default)]
+    pages: Vec<PageInfo>,
+    #[serde(default)]
+    text_revision: u64,
+    #[serde(default)]
+    snippets: Vec<SnippetInfo>,
+    #[serde(default)]
+    language: Option<String>,
 }
 
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
@@ -596,7 +613,7 @@ pub fn Hub(props: HubProps) -> Element {
                                         files: data.files.clone(),
                                         hub_resource: hub_resource.clone()
                                     }
-                                    Whiteboard { hub_id: props.id.clone(), initial_paths: data.whiteboard.clone() }
+                                    Whiteboard { hub_id: props.id.clone(), initial_pages: data.pages.clone() }
                                 }
                             },
                             None => rsx! {
@@ -659,388 +676,4038 @@ pub fn Hub(props: HubProps) -> Element {
     }
 }
 
-/// A sub-component specifically for the Text Bin UI.
-#[derive(PartialEq, Props, Clone)]
-struct TextBinProps {
-    data: HubData,
-    hub_id: String,
-}
+// A change to the text bin, mirroring the backend: its components walk over
+// the whole text, keeping, inserting or deleting characters as they go.
+// Serialized as a list where a positive number keeps that many characters,
+// a negative number deletes them and a string is inserted.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(transparent)]
+struct TextOperation(Vec<TextComponent>);
 
-#[allow(non_snake_case)]
-fn TextBin(props: TextBinProps) -> Element {
-    let mut text_content = use_signal(|| props.data.content.clone());
-    let mut save_button_state = use_signal(|| "Save".to_string());
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "TextComponentRepr", into = "TextComponentRepr")]
+enum TextComponent {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
 
-    let hub_id = props.hub_id.clone();
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TextComponentRepr {
+    Count(i64),
+    Insert(String),
+}
 
-    let save_coroutine = use_coroutine(move |mut rx: UnboundedReceiver<String>| {
-        let hub_id = hub_id.clone();
-        async move {
-            while let Some(content) = rx.next().await {
-                let api_url = format!("https://api.ephemeral-hub.com/api/hubs/{}/text", hub_id);
-                let client = reqwest::Client::new();
-                let res = client.put(api_url).body(content).send().await;
+impl TryFrom<TextComponentRepr> for TextComponent {
+    type Error = String;
 
-                if res.is_err() {
-                    log::error!("Failed to save content");
-                }
+    fn try_from(repr: TextComponentRepr) -> Result<Self, Self::Error> {
+        match repr {
+            TextComponentRepr::Count(0) => Err("empty text component".to_string()),
+            TextComponentRepr::Count(n) if n > 0 => Ok(TextComponent::Retain(n as usize)),
+            TextComponentRepr::Count(n) => Ok(TextComponent::Delete(n.unsigned_abs() as usize)),
+            TextComponentRepr::Insert(text) if text.is_empty() => {
+                Err("empty text component".to_string())
             }
+            TextComponentRepr::Insert(text) => Ok(TextComponent::Insert(text)),
         }
-    });
+    }
+}
 
-    rsx! {
-        div { class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300",
-            div { class: "flex items-center gap-3 mb-4",
-                div { class: "w-10 h-10 bg-blue-500/20 rounded-lg flex items-center justify-center",
-                   svg {
-                        class: "w-6 h-6 text-orange-400",
-                        fill: "none",
-                        stroke: "currentColor",
-                        view_box: "0 0 24 24",
-                        path {
-                            stroke_linecap: "round",
-                            stroke_linejoin: "round",
-                            stroke_width: "2",
-                            d: "M9 5H7a2 2 0 00-2 2v12a2 2 0 002 2h10a2 2 0 002-2V7a2 2 0 00-2-2h-2M9 5a2 2 0 002 2h2a2 2 0 002-2M9 5a2 2 0 012-2h2a2 2 0 012 2m-3 7h3m-3 4h3m-6-4h.01M9 16h.01"
-                        }
-                    }
-                }
-                h2 { class: "text-xl font-semibold text-white", "Text Content" }
-            }
+impl From<TextComponent> for TextComponentRepr {
+    fn from(component: TextComponent) -> Self {
+        match component {
+            TextComponent::Retain(n) => TextComponentRepr::Count(n as i64),
+            TextComponent::Insert(text) => TextComponentRepr::Insert(text),
+            TextComponent::Delete(n) => TextComponentRepr::Count(-(n as i64)),
+        }
+    }
+}
 
-            textarea {
-                class: "w-full min-h-[200px] bg-slate-900/50 border border-slate-600/50 rounded-lg p-4 text-slate-100 placeholder-slate-400 focus:border-blue-500/50 focus:ring-2 focus:ring-blue-500/20 focus:outline-none transition-all duration-300 font-mono text-sm resize-none",
-                placeholder: "Enter your text content here...",
-                value: "{text_content}",
-                oninput: move |event| text_content.set(event.value()),
-            }
+impl TextOperation {
+    fn retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(TextComponent::Retain(m)) => *m += n,
+            _ => self.0.push(TextComponent::Retain(n)),
+        }
+    }
 
-            button {
-                class: "inline-flex items-center gap-2 px-4 py-2 bg-indigo-600 text-white font-semibold rounded-lg shadow-sm hover:bg-indigo-700 focus:outline-none focus:ring-2 focus:ring-indigo-500 focus:ring-offset-2 transition-all duration-150",
-                onclick: move |_| {
-                    save_coroutine.send(text_content.read().clone());
-                    save_button_state.set("Saving...".to_string());
+    // Inserts are kept in front of deletes at the same position, like on the server.
+    fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let len = self.0.len();
+        match self.0.as_mut_slice() {
+            [.., TextComponent::Insert(s)]
+            | [.., TextComponent::Insert(s), TextComponent::Delete(_)] => s.push_str(text),
+            [.., TextComponent::Delete(_)] => self
+                .0
+                .insert(len - 1, TextComponent::Insert(text.to_string())),
+            _ => self.0.push(TextComponent::Insert(text.to_string())),
+        }
+    }
 
-                    let mut save_button_state = save_button_state.clone();
-                    spawn(async move {
-                        sleep(Duration::from_secs(2)).await;
-                        save_button_state.set("Save".to_string());
-                    });
-                },
-                if save_button_state.read().as_str() == "Saving..." {
-                    div { class: "animate-spin w-4 h-4 border-2 border-white/30 border-t-white rounded-full" }
-                } else {
-                    svg {
-                        class: "w-4 h-4",
-                        fill: "none",
-                        stroke: "currentColor",
-                        view_box: "0 0 24 24",
-                        path {
-                            stroke_linecap: "round",
-                            stroke_linejoin: "round",
-                            stroke_width: "2",
-                            d: "M5 13l4 4L19 7"
-                        }
-                    }
-                },
+    fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(TextComponent::Delete(m)) => *m += n,
+            _ => self.0.push(TextComponent::Delete(n)),
+        }
+    }
+
+    fn base_len(&self) -> usize {
+        self.0
+            .iter()
+            .map(|c| match c {
+                TextComponent::Retain(n) | TextComponent::Delete(n) => *n,
+                TextComponent::Insert(_) => 0,
+            })
+            .sum()
+    }
+}
 
+impl TextComponent {
+    fn len(&self) -> usize {
+        match self {
+            TextComponent::Retain(n) | TextComponent::Delete(n) => *n,
+            TextComponent::Insert(s) => s.chars().count(),
+        }
+    }
 
-                "{save_button_state}"
+    // What is left of the component after its first `n` characters.
+    fn skip(self, n: usize) -> Option<TextComponent> {
+        match self {
+            TextComponent::Retain(m) if m > n => Some(TextComponent::Retain(m - n)),
+            TextComponent::Delete(m) if m > n => Some(TextComponent::Delete(m - n)),
+            TextComponent::Insert(s) if s.chars().count() > n => {
+                Some(TextComponent::Insert(s.chars().skip(n).collect()))
             }
+            _ => None,
         }
     }
 }
 
-/// A sub-component for the File Drop UI.
-#[derive(PartialEq, Props, Clone)]
-struct FileDropProps {
-    hub_id: String,
-    files: Vec<FileInfo>,
-    hub_resource: Resource<Option<HubData>>,
+fn apply_text_operation(text: &str, operation: &TextOperation) -> Result<String, String> {
+    if operation.base_len() != text.chars().count() {
+        return Err("the operation doesn't match the length of the text".to_string());
+    }
+    let mut chars = text.chars();
+    let mut result = String::with_capacity(text.len());
+    for component in &operation.0 {
+        match component {
+            TextComponent::Retain(n) => result.extend(chars.by_ref().take(*n)),
+            TextComponent::Insert(s) => result.push_str(s),
+            TextComponent::Delete(n) => chars.by_ref().take(*n).for_each(drop),
+        }
+    }
+    Ok(result)
 }
 
-#[allow(non_snake_case)]
-fn FileDrop(props: FileDropProps) -> Element {
-    let is_uploading = use_signal(|| false);
-
-    // The coroutine now expects a Vec containing the filename and its bytes.
-    let upload_coroutine: Coroutine<Vec<(String, Vec<u8>)>> =
-        use_coroutine(move |mut rx: UnboundedReceiver<Vec<(String, Vec<u8>)>>| {
-            let hub_id = props.hub_id.clone();
-            let mut hub_resource = props.hub_resource.clone();
-            let mut is_uploading = is_uploading.clone();
-            async move {
-                while let Some(files_with_data) = rx.next().await {
-                    is_uploading.set(true);
-                    let mut form = multipart::Form::new();
-                    for (filename, file_bytes) in files_with_data {
-                        let part = multipart::Part::bytes(file_bytes).file_name(filename);
-                        form = form.part("file", part);
-                    }
-
-                    let client = reqwest::Client::new();
-                    let api_url =
-                        format!("https://api.ephemeral-hub.com/api/hubs/{}/files", hub_id);
-
-                    let res = client.post(api_url).multipart(form).send().await;
-
-                    if res.is_ok() {
-                        hub_resource.restart();
-                    } else {
-                        log::error!("Failed to upload files: {:?}", res.err());
-                    }
-                    is_uploading.set(false);
-                }
+// Transforms two concurrent operations into `(a', b')`, so that `a` then `b'`
+// gives the same text as `b` then `a'`. Same as `text::transform` on the server.
+fn transform_text_operations(
+    a: &TextOperation,
+    b: &TextOperation,
+) -> Result<(TextOperation, TextOperation), String> {
+    if a.base_len() != b.base_len() {
+        return Err("the operations were made on different texts".to_string());
+    }
+    let (mut a_prime, mut b_prime) = (TextOperation::default(), TextOperation::default());
+    let (mut a_iter, mut b_iter) = (a.0.iter().cloned(), b.0.iter().cloned());
+    let (mut next_a, mut next_b) = (a_iter.next(), b_iter.next());
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (None, None) => break,
+            (Some(TextComponent::Insert(s)), b) => {
+                a_prime.insert(&s);
+                b_prime.retain(s.chars().count());
+                next_a = a_iter.next();
+                next_b = b;
             }
-        });
-
-    rsx! {
-        div {
-            class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300",
-              div { class: "flex items-center gap-3 mb-4",
-                div { class: "w-10 h-10 bg-blue-500/20 rounded-lg flex items-center justify-center",
-                    svg {
-                        class: "w-5 h-5 text-blue-400",
-                        fill: "none",
-                        stroke: "currentColor",
-                        view_box: "0 0 24 24",
-                        path {
-                            stroke_linecap: "round",
-                            stroke_linejoin: "round",
-                            stroke_width: "2",
-                            d: "M9 12h6m-6 4h6m2 5H7a2 2 0 01-2-2V5a2 2 0 012-2h5.586a1 1 0 01.707.293l5.414 5.414a1 1 0 01.293.707V19a2 2 0 01-2 2z"
-                        }
+            (a, Some(TextComponent::Insert(s))) => {
+                a_prime.retain(s.chars().count());
+                b_prime.insert(&s);
+                next_a = a;
+                next_b = b_iter.next();
+            }
+            (Some(a), Some(b)) => {
+                let n = a.len().min(b.len());
+                match (&a, &b) {
+                    (TextComponent::Retain(_), TextComponent::Retain(_)) => {
+                        a_prime.retain(n);
+                        b_prime.retain(n);
                     }
+                    (TextComponent::Delete(_), TextComponent::Retain(_)) => a_prime.delete(n),
+                    (TextComponent::Retain(_), TextComponent::Delete(_)) => b_prime.delete(n),
+                    // Both deleted the same characters.
+                    _ => {}
                 }
-                h2 { class: "text-xl font-semibold text-white", "FILES" }
-            }
-            p {
-                class: "text-white py-4",
-                "Upload files to share them temporarily."
+                next_a = a.skip(n).or_else(|| a_iter.next());
+                next_b = b.skip(n).or_else(|| b_iter.next());
             }
-
-            input {
-                r#type: "file",
-                multiple: true,
-                id: "file-upload",
-                class: "hidden",
-                onchange: move |evt| {
-                    // Spawn a task to handle the async file reading.
-                    spawn({
-                        let upload_coroutine = upload_coroutine.clone();
-                        async move {
-                            // Use `.files()` to get the file engine.
-                            if let Some(file_engine) = evt.files() {
-                                // Get the list of file names.
-                                let files = file_engine.files();
-                                let mut files_with_data = Vec::new();
-                                // Iterate over the file names and read each one.
-                                for file_name in &files {
-                                    if let Some(file_bytes) = file_engine.read_file(file_name).await {
-                                        files_with_data.push((file_name.clone(), file_bytes));
-                                    }
-                                }
-                                if !files_with_data.is_empty() {
-                                    upload_coroutine.send(files_with_data);
-                                }
-                            }
-                        }
-                    });
-                }
+            (None, Some(_)) | (Some(_), None) => {
+                return Err("the operations were made on different texts".to_string());
             }
+        }
+    }
+    Ok((a_prime, b_prime))
+}
 
-            // The button that triggers the file input.
-            label {
-                r#for: "file-upload",
-                class: "cursor-pointer inline-flex items-center gap-2 px-4 py-2 bg-indigo-600 text-white font-semibold rounded-lg shadow-sm hover:bg-indigo-700 focus:outline-none focus:ring-2 focus:ring-indigo-500 focus:ring-offset-2 transition-all duration-150",
-                if is_uploading() {
-                    "Uploading..."
-                } else {
-                    "Upload Files"
-                }
+// Combines two consecutive operations into one with the same effect.
+fn compose_text_operations(a: &TextOperation, b: &TextOperation) -> Result<TextOperation, String> {
+    let mut composed = TextOperation::default();
+    let (mut a_iter, mut b_iter) = (a.0.iter().cloned(), b.0.iter().cloned());
+    let (mut next_a, mut next_b) = (a_iter.next(), b_iter.next());
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (None, None) => break,
+            // Deleted text isn't seen by `b`, and text `b` inserts isn't seen by `a`.
+            (Some(TextComponent::Delete(n)), b) => {
+                composed.delete(n);
+                next_a = a_iter.next();
+                next_b = b;
             }
-
-            p {
-                class: "text-white pt-20",
-                "Files uploaded:"
+            (a, Some(TextComponent::Insert(s))) => {
+                composed.insert(&s);
+                next_a = a;
+                next_b = b_iter.next();
             }
-            ul {
-                class: "list-disc pl-5 mt-4 text-white",
-                for file in props.files.iter() {
-                    li {
-                        class: "mb-2",
-                        "{file.filename} ({file.size} bytes)"
+            (Some(a), Some(b)) => {
+                let n = a.len().min(b.len());
+                match (&a, &b) {
+                    (TextComponent::Retain(_), TextComponent::Retain(_)) => composed.retain(n),
+                    (TextComponent::Retain(_), TextComponent::Delete(_)) => composed.delete(n),
+                    (TextComponent::Insert(s), TextComponent::Retain(_)) => {
+                        composed.insert(&s.chars().take(n).collect::<String>())
                     }
+                    // Text inserted by `a` and deleted again by `b`.
+                    _ => {}
                 }
+                next_a = a.skip(n).or_else(|| a_iter.next());
+                next_b = b.skip(n).or_else(|| b_iter.next());
+            }
+            (None, Some(_)) | (Some(_), None) => {
+                return Err("the operations don't follow each other".to_string());
             }
         }
     }
+    Ok(composed)
 }
 
-// Data structure for a single drawing path
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
-struct PathData {
-    id: String,
-    points: Vec<(f64, f64)>,
-    color: String,
-    stroke_width: f64,
+// The operation that turns `old` into `new`, replacing everything between
+// their common start and end.
+fn diff_text(old: &str, new: &str) -> TextOperation {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut operation = TextOperation::default();
+    operation.retain(prefix);
+    operation.insert(&new[prefix..new.len() - suffix].iter().collect::<String>());
+    operation.delete(old.len() - prefix - suffix);
+    operation.retain(suffix);
+    operation
 }
 
-// Message format for WebSocket communication
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
-enum WsMessage {
-    PathCompleted(PathData),
+// Where a position in a text ends up after an operation. Text inserted right
+// at the position pushes it forward.
+fn transform_text_position(operation: &TextOperation, position: usize) -> usize {
+    let mut old = 0;
+    let mut new = position;
+    for component in &operation.0 {
+        if old > position {
+            break;
+        }
+        match component {
+            TextComponent::Retain(n) => old += n,
+            TextComponent::Insert(s) => new += s.chars().count(),
+            TextComponent::Delete(n) => {
+                new -= (*n).min(position - old);
+                old += n;
+            }
+        }
+    }
+    new
 }
 
-#[derive(PartialEq, Props, Clone)]
-struct WhiteboardProps {
-    hub_id: String,
-    initial_paths: Vec<PathData>,
+// The client side of editing the text bin together. Local edits are
+// collected until they are sent, and only one batch is sent at a time;
+// the next one waits until the server has confirmed it.
+#[derive(Default)]
+struct TextSync {
+    // The last revision of the text received from the server.
+    revision: u64,
+    // The edit sent to the server and not confirmed yet, and when it was sent.
+    sent: Option<(TextOperation, f64)>,
+    // Edits that haven't been sent yet.
+    pending: Option<TextOperation>,
 }
-#[allow(non_snake_case)]
-fn Whiteboard(props: WhiteboardProps) -> Element {
-    let mut paths = use_signal(|| props.initial_paths.clone());
-    // A signal to track the path currently being drawn by the user
-    let mut current_path = use_signal::<Option<PathData>>(|| None);
-    // Generate a unique ID for this user
-    let user_id = use_memo(|| Uuid::new_v4().to_string());
 
-    // A helper: derive color from user_id (stable hash → pick from palette)
-    fn color_for_user(user_id: &str) -> String {
-        let palette = vec![
-            "#e6194b", "#3cb44b", "#ffe119", "#4363d8", "#f58231", "#911eb4", "#46f0f0", "#f032e6",
-            "#bcf60c", "#fabebe", "#008080", "#e6beff", "#9a6324", "#fffac8", "#800000", "#aaffc3",
-            "#808000", "#ffd8b1", "#000075", "#808080",
-        ];
-        let mut hash = 0u64;
-        for b in user_id.as_bytes() {
-            hash = hash.wrapping_mul(31).wrapping_add(*b as u64);
+impl TextSync {
+    // Records a local edit, to be sent by `flush`.
+    fn edit(&mut self, operation: TextOperation) {
+        let pending = match self.pending.take() {
+            Some(pending) => compose_text_operations(&pending, &operation),
+            None => Ok(operation),
+        };
+        match pending {
+            Ok(pending) => self.pending = Some(pending),
+            Err(e) => log::error!("Failed to combine text edits: {}", e),
         }
-        let idx = (hash % palette.len() as u64) as usize;
-        palette[idx].to_string()
     }
 
-    let my_color = color_for_user(&user_id());
+    // Returns the edits to send now, if there are any and the server isn't
+    // still busy with the last ones.
+    fn flush(&mut self, now: f64) -> Option<TextOperation> {
+        if self.sent.is_some() {
+            return None;
+        }
+        let operation = self.pending.take()?;
+        self.sent = Some((operation.clone(), now));
+        Some(operation)
+    }
 
-    let ws_coroutine = use_coroutine(move |mut rx: UnboundedReceiver<WsMessage>| {
-        let paths = paths.clone();
-        let ws_url = format!("wss://api.ephemeral-hub.com/ws/hubs/{}", props.hub_id);
+    // The server applied the edit that was sent.
+    fn confirmed(&mut self, revision: u64) {
+        self.revision = revision;
+        self.sent = None;
+    }
 
-        async move {
-            let ws = match WebSocket::open(&ws_url) {
-                Ok(ws) => ws,
-                Err(e) => {
-                    log::error!("Failed to connect to WebSocket: {:?}", e);
-                    return;
-                }
-            };
+    // Whether there are local edits the server hasn't applied yet.
+    fn is_unsaved(&self) -> bool {
+        self.sent.is_some() || self.pending.is_some()
+    }
 
-            let (mut write, mut read) = ws.split();
+    // Transforms an edit someone else made past the local edits the server
+    // hasn't applied yet, so that it can be applied to the local text.
+    fn received(
+        &mut self,
+        revision: u64,
+        operation: TextOperation,
+    ) -> Result<TextOperation, String> {
+        self.revision = revision;
+        let mut operation = operation;
+        let sent = self.sent.as_mut().map(|(sent, _)| sent);
+        for local in [sent, self.pending.as_mut()].into_iter().flatten() {
+            let (local_prime, operation_prime) = transform_text_operations(local, &operation)?;
+            *local = local_prime;
+            operation = operation_prime;
+        }
+        Ok(operation)
+    }
+}
 
-            // Incoming messages
-            spawn({
-                let mut paths = paths.clone();
-                async move {
-                    while let Some(Ok(GlooWsMessage::Text(text))) = read.next().await {
-                        if let Ok(client_msg) = serde_json::from_str::<WsMessage>(&text) {
-                            match client_msg {
-                                WsMessage::PathCompleted(new_path) => {
-                                    paths.write().push(new_path);
-                                }
-                            }
-                        }
-                    }
-                }
-            });
+// Where someone else's caret is in the text bin, and when it last moved.
+#[derive(Clone, Copy, PartialEq)]
+struct RemoteCaret {
+    position: usize,
+    seen_at: f64,
+}
 
-            // Outgoing messages
-            while let Some(msg_to_send) = rx.next().await {
-                let json_msg = serde_json::to_string(&msg_to_send).unwrap();
-                if write.send(GlooWsMessage::Text(json_msg)).await.is_err() {
-                    log::error!("WebSocket connection closed. Cannot send message.");
-                    break;
-                }
-            }
-        }
-    });
+// A helper: derive color from user_id (stable hash → pick from palette)
+fn color_for_user(user_id: &str) -> String {
+    let palette = vec![
+        "#e6194b", "#3cb44b", "#ffe119", "#4363d8", "#f58231", "#911eb4", "#46f0f0", "#f032e6",
+        "#bcf60c", "#fabebe", "#008080", "#e6beff", "#9a6324", "#fffac8", "#800000", "#aaffc3",
+        "#808000", "#ffd8b1", "#000075", "#808080",
+    ];
+    let mut hash = 0u64;
+    for b in user_id.as_bytes() {
+        hash = hash.wrapping_mul(31).wrapping_add(*b as u64);
+    }
+    let idx = (hash % palette.len() as u64) as usize;
+    palette[idx].to_string()
+}
 
-    let to_svg_path = |points: &Vec<(f64, f64)>| -> String {
-        if points.is_empty() {
-            return String::new();
-        }
-        let mut d = format!("M {} {}", points[0].0, points[0].1);
-        for p in points.iter().skip(1) {
-            d.push_str(&format!(" L {} {}", p.0, p.1));
-        }
-        d
-    };
+// A past version of the text bin, as listed in its history.
+// `at` is an RFC 3339 timestamp.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct TextRevisionInfo {
+    revision: u64,
+    at: String,
+    source: RevisionSource,
+    length: usize,
+}
 
-    rsx! {
-        div {
-            class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300 col-span-1 lg:col-span-2",
-            h2 { class: "text-xl font-bold text-white mb-4", "Collaborative Whiteboard" }
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum RevisionSource {
+    Edited,
+    Replaced,
+    Restored,
+}
 
-            svg {
-                class: "w-full h-[400px] border border-gray-300 rounded-md bg-gray-50",
-                prevent_default: "onmousedown onmousemove",
-
-                onmousedown: move |evt| {
-                    let path_id = format!("{}-{}", user_id, Uuid::new_v4());
-                    let new_path = PathData {
-                        id: path_id.clone(),
-                        points: vec![(evt.element_coordinates().x, evt.element_coordinates().y)],
-                        color: my_color.clone(),
-                        stroke_width: 2.0,
-                    };
-                    current_path.set(Some(new_path));
-                },
+impl RevisionSource {
+    fn label(self) -> &'static str {
+        match self {
+            RevisionSource::Edited => "Edited",
+            RevisionSource::Replaced => "Replaced",
+            RevisionSource::Restored => "Restored",
+        }
+    }
+}
 
-                onmousemove: move |evt| {
-                    if let Some(path) = current_path.write().as_mut() {
-                        let point = (evt.element_coordinates().x, evt.element_coordinates().y);
-                        path.points.push(point);
-                    }
-                },
+// How the text bin is highlighted in a language.
+struct Syntax {
+    id: &'static str,
+    label: &'static str,
+    keywords: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    // Whether `'` starts a string, rather than only a character or a lifetime.
+    single_quoted_strings: bool,
+}
 
-                onmouseup: move |_| {
-                    if let Some(path) = current_path.take() {
-                        // Add the completed path to our local state immediately for responsiveness.
-                        paths.write().push(path.clone());
-                        // Send the completed path to the server.
-                        ws_coroutine.send(WsMessage::PathCompleted(path));
-                    }
-                },
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "int", "long", "return", "short", "signed",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void", "while",
+];
 
-                onmouseleave: move |_| {
-                    if let Some(path) = current_path.take() {
-                        // Add the completed path to our local state immediately.
-                        paths.write().push(path.clone());
-                        // Send the completed path to the server.
-                        ws_coroutine.send(WsMessage::PathCompleted(path));
-                    }
-                },
+// The same languages as the server's `language::LANGUAGES`, in the same order.
+const LANGUAGES: &[Syntax] = &[
+    Syntax {
+        id: "plaintext",
+        label: "Plain text",
+        keywords: &[],
+        line_comment: None,
+        block_comment: None,
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "markdown",
+        label: "Markdown",
+        keywords: &[],
+        line_comment: None,
+        block_comment: None,
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "rust",
+        label: "Rust",
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "python",
+        label: "Python",
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        line_comment: Some("#"),
+        block_comment: None,
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "javascript",
+        label: "JavaScript",
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "let",
+            "new",
+            "null",
+            "of",
+            "return",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "typescript",
+        label: "TypeScript",
+        keywords: &[
+            "any",
+            "as",
+            "async",
+            "await",
+            "boolean",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "else",
+            "enum",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "implements",
+            "import",
+            "in",
+            "interface",
+            "let",
+            "new",
+            "null",
+            "number",
+            "private",
+            "public",
+            "readonly",
+            "return",
+            "string",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "type",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "json",
+        label: "JSON",
+        keywords: &["true", "false", "null"],
+        line_comment: None,
+        block_comment: None,
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "yaml",
+        label: "YAML",
+        keywords: &["true", "false", "null", "yes", "no"],
+        line_comment: Some("#"),
+        block_comment: None,
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "toml",
+        label: "TOML",
+        keywords: &["true", "false"],
+        line_comment: Some("#"),
+        block_comment: None,
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "html",
+        label: "HTML",
+        keywords: &[],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "css",
+        label: "CSS",
+        keywords: &["important", "inherit", "initial", "none", "auto"],
+        line_comment: None,
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "shell",
+        label: "Shell",
+        keywords: &[
+            "case", "do", "done", "echo", "elif", "else", "esac", "export", "fi", "for",
+            "function", "if", "in", "local", "return", "then", "while",
+        ],
+        line_comment: Some("#"),
+        block_comment: None,
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "sql",
+        label: "SQL",
+        keywords: &[
+            "SELECT", "FROM", "WHERE", "INSERT", "INTO", "VALUES", "UPDATE", "SET", "DELETE",
+            "CREATE", "TABLE", "DROP", "ALTER", "JOIN", "LEFT", "RIGHT", "INNER", "ON", "AND",
+            "OR", "NOT", "NULL", "GROUP", "BY", "ORDER", "LIMIT", "AS", "DISTINCT", "select",
+            "from", "where", "insert", "into", "values", "update", "set", "delete", "create",
+            "table", "join", "on", "and", "or", "not", "null", "group", "by", "order", "limit",
+            "as",
+        ],
+        line_comment: Some("--"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: true,
+    },
+    Syntax {
+        id: "go",
+        label: "Go",
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "false",
+            "for",
+            "func",
+            "go",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "java",
+        label: "Java",
+        keywords: &[
+            "abstract",
+            "boolean",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "else",
+            "extends",
+            "false",
+            "final",
+            "finally",
+            "for",
+            "if",
+            "implements",
+            "import",
+            "int",
+            "interface",
+            "new",
+            "null",
+            "package",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "static",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "throws",
+            "true",
+            "try",
+            "void",
+            "while",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "c",
+        label: "C",
+        keywords: C_KEYWORDS,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: false,
+    },
+    Syntax {
+        id: "cpp",
+        label: "C++",
+        keywords: &[
+            "auto",
+            "bool",
+            "break",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "continue",
+            "delete",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "false",
+            "float",
+            "for",
+            "if",
+            "int",
+            "namespace",
+            "new",
+            "nullptr",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typename",
+            "using",
+            "virtual",
+            "void",
+            "while",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        single_quoted_strings: false,
+    },
+];
 
+fn syntax_for(language: &str) -> &'static Syntax {
+    LANGUAGES
+        .iter()
+        .find(|syntax| syntax.id == language)
+        .unwrap_or(&LANGUAGES[0])
+}
 
-                // Render all paths
-                for path in paths.read().iter() {
-                    path {
-                        d: "{to_svg_path(&path.points)}",
-                        stroke: "{path.color}",
-                        stroke_width: "{path.stroke_width}",
-                        fill: "none",
-                        stroke_linecap: "round",
-                        stroke_linejoin: "round"
-                    }
-                }
-                // Render the path currently being drawn by this user
-                if let Some(path) = current_path.read().as_ref() {
-                    path {
-                        d: "{to_svg_path(&path.points)}",
-                        stroke: "{path.color}",
-                        stroke_width: "{path.stroke_width}",
-                        fill: "none",
-                        stroke_linecap: "round",
-                        stroke_linejoin: "round"
+// Guesses the language of a text the same way the server does, see
+// `language::detect` in the backend.
+fn detect_language(text: &str) -> &'static str {
+    let trimmed = text.trim_start();
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let has = |needle: &str| text.contains(needle);
+    let lines_starting = |prefix: &str| lines.iter().filter(|l| l.starts_with(prefix)).count();
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        return "json";
+    }
+    if let Some(shebang) = trimmed.strip_prefix("#!") {
+        let interpreter = shebang.lines().next().unwrap_or_default();
+        return if interpreter.contains("python") {
+            "python"
+        } else if interpreter.contains("node") {
+            "javascript"
+        } else {
+            "shell"
+        };
+    }
+    let lower = trimmed.to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return "html";
+    }
+    if has("fn ") && (has("let ") || has("->") || has("::") || has("pub ")) {
+        return "rust";
+    }
+    if has("package main") || (has("func ") && has(":=")) {
+        return "go";
+    }
+    if has("public class ") || has("public static void ") {
+        return "java";
+    }
+    if lines_starting("#include") > 0 {
+        return if has("std::") || has("class ") || has("template<") {
+            "cpp"
+        } else {
+            "c"
+        };
+    }
+    if lines_starting("def ") > 0 || (lines_starting("import ") > 0 && !has(";")) {
+        return "python";
+    }
+    if has("interface ") || has(": string") || has(": number") {
+        return "typescript";
+    }
+    if has("function ") || has("const ") || has("=>") || has("console.log") {
+        return "javascript";
+    }
+    if ["select ", "insert into ", "create table ", "update "]
+        .iter()
+        .any(|keyword| lower.starts_with(keyword))
+    {
+        return "sql";
+    }
+    if lines
+        .iter()
+        .any(|l| l.starts_with('[') && l.ends_with(']') && !l.contains(','))
+        && lines.iter().any(|l| l.contains(" = "))
+    {
+        return "toml";
+    }
+    if has("{") && has("}") && has(";") && lines.iter().any(|l| l.ends_with('{')) {
+        return "css";
+    }
+    let keys = lines
+        .iter()
+        .filter(|l| {
+            l.split_once(':').is_some_and(|(key, _)| {
+                !key.is_empty()
+                    && key
+                        .trim_start_matches("- ")
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            })
+        })
+        .count();
+    if keys >= 2 && keys * 2 >= lines.iter().filter(|l| !l.is_empty()).count() {
+        return "yaml";
+    }
+    if lines_starting("# ") + lines_starting("## ") + lines_starting("```") + lines_starting("- ")
+        > 0
+    {
+        return "markdown";
+    }
+    "plaintext"
+}
+
+// Splits a text into runs of characters, each with the classes to color it with.
+fn highlight(text: &str, syntax: &Syntax) -> Vec<(String, &'static str)> {
+    const PLAIN: &str = "";
+    const KEYWORD: &str = "text-purple-300";
+    const STRING: &str = "text-green-300";
+    const NUMBER: &str = "text-amber-300";
+    const COMMENT: &str = "text-slate-500 italic";
+
+    let chars: Vec<char> = text.chars().collect();
+    let starts_with = |i: usize, s: &str| {
+        s.chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+    let mut tokens: Vec<(String, &'static str)> = Vec::new();
+    let mut push = |token: &[char], class: &'static str| match tokens.last_mut() {
+        Some((last, last_class)) if *last_class == class => last.extend(token),
+        _ => tokens.push((token.iter().collect(), class)),
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if let Some((open, close)) = syntax
+            .block_comment
+            .filter(|(open, _)| starts_with(i, open))
+        {
+            i += open.chars().count();
+            while i < chars.len() && !starts_with(i, close) {
+                i += 1;
+            }
+            i = (i + close.chars().count()).min(chars.len());
+            push(&chars[start..i], COMMENT);
+        } else if syntax
+            .line_comment
+            .is_some_and(|prefix| starts_with(i, prefix))
+        {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            push(&chars[start..i], COMMENT);
+        } else if c == '"'
+            || (c == '\''
+                && (syntax.single_quoted_strings
+                    || chars.get(i + 2) == Some(&'\'')
+                    || chars.get(i + 1) == Some(&'\\')))
+        {
+            i += 1;
+            while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(chars.len());
+            push(&chars[start..i], STRING);
+        } else if c.is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '.' || chars[i] == '_')
+            {
+                i += 1;
+            }
+            push(&chars[start..i], NUMBER);
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let class = if syntax.keywords.contains(&word.as_str()) {
+                KEYWORD
+            } else {
+                PLAIN
+            };
+            push(&chars[start..i], class);
+        } else {
+            i += 1;
+            push(&chars[start..i], PLAIN);
+        }
+    }
+    tokens
+}
+
+// Renders Markdown as HTML. Raw HTML is shown as text and links can't run
+// scripts, so rendering someone else's notes is safe.
+fn render_markdown(text: &str) -> String {
+    use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+    let is_safe_url = |url: &str| {
+        let url = url.trim().to_ascii_lowercase();
+        !url.contains(':')
+            || ["http://", "https://", "mailto:"]
+                .iter()
+                .any(|s| url.starts_with(s))
+    };
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed("#"),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+// What the snippet tabs of the text bin ask the server for.
+enum SnippetRequest {
+    Load(String),
+    // Saves a snippet, with how many times it had been edited locally.
+    Save {
+        name: String,
+        content: String,
+        edits: u64,
+    },
+    Delete(String),
+}
+
+// The API URL of a named snippet, with the name escaped as a path segment.
+fn snippet_url(hub_id: &str, name: &str) -> String {
+    format!(
+        "https://api.ephemeral-hub.com/api/hubs/{}/snippets/{}",
+        hub_id,
+        String::from(js_sys::encode_uri_component(name))
+    )
+}
+
+// The direct URL of an uploaded file.
+fn file_url(hub_id: &str, filename: &str) -> String {
+    format!(
+        "https://api.ephemeral-hub.com/api/hubs/{}/files/{}",
+        hub_id,
+        String::from(js_sys::encode_uri_component(filename))
+    )
+}
+
+// What the history panel of the text bin asks the server for.
+enum HistoryRequest {
+    // The list of past versions.
+    Load,
+    // How the text changed since a past version.
+    Pick(u64),
+    // To bring back a past version.
+    Restore(u64),
+}
+
+// The local date and time of an RFC 3339 timestamp, for the text bin history.
+fn revision_time(at: &str) -> String {
+    let date = js_sys::Date::new(&JsValue::from_str(at));
+    date.to_locale_string("default", &JsValue::UNDEFINED).into()
+}
+
+// Colors the lines of a unified diff.
+fn diff_line_class(line: &str) -> &'static str {
+    if line.starts_with("+++") || line.starts_with("---") {
+        "text-slate-400"
+    } else if line.starts_with('+') {
+        "text-green-300 bg-green-500/10"
+    } else if line.starts_with('-') {
+        "text-red-300 bg-red-500/10"
+    } else if line.starts_with("@@") {
+        "text-blue-300"
+    } else {
+        "text-slate-300"
+    }
+}
+
+// The body of the server's error responses.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+// The server's explanation of a failed request, or its status if there is none.
+async fn error_message(res: reqwest::Response) -> String {
+    let status = res.status();
+    match res.json::<ErrorResponse>().await {
+        Ok(error) => error.message,
+        Err(_) => status.to_string(),
+    }
+}
+
+const TEXT_BIN_ID: &str = "text-bin";
+
+// How long typing has to pause before the text is saved.
+const AUTOSAVE_DELAY_MS: u64 = 500;
+
+// How long the server may take to confirm an edit before saving is
+// reported as failed.
+const SAVE_TIMEOUT_MS: f64 = 10_000.0;
+
+// Carets that haven't moved for this long are assumed to belong to someone
+// who has left.
+const CARET_TIMEOUT_MS: f64 = 60_000.0;
+
+fn text_bin_element() -> Option<web_sys::HtmlTextAreaElement> {
+    window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.get_element_by_id(TEXT_BIN_ID))
+        .and_then(|element| element.dyn_into().ok())
+}
+
+// The selection in the text bin as character positions, in the order
+// `(anchor, head)`. The browser counts in UTF-16 code units.
+fn text_bin_selection(text: &str) -> Option<(usize, usize)> {
+    let textarea = text_bin_element()?;
+    let start = textarea.selection_start().ok()??;
+    let end = textarea.selection_end().ok()??;
+    let (start, end) = (
+        utf16_to_char_index(text, start),
+        utf16_to_char_index(text, end),
+    );
+    if textarea.selection_direction().ok()?.as_deref() == Some("backward") {
+        Some((end, start))
+    } else {
+        Some((start, end))
+    }
+}
+
+fn utf16_to_char_index(text: &str, offset: u32) -> usize {
+    let mut units = 0;
+    for (i, c) in text.chars().enumerate() {
+        if units >= offset {
+            return i;
+        }
+        units += c.len_utf16() as u32;
+    }
+    text.chars().count()
+}
+
+fn char_to_utf16_index(text: &str, index: usize) -> u32 {
+    text.chars().take(index).map(|c| c.len_utf16() as u32).sum()
+}
+
+/// A sub-component specifically for the Text Bin UI.
+#[derive(PartialEq, Props, Clone)]
+struct TextBinProps {
+    data: HubData,
+    hub_id: String,
+}
+
+#[allow(non_snake_case)]
+fn TextBin(props: TextBinProps) -> Element {
+    let mut text_content = use_signal(|| props.data.content.clone());
+    let text_revision = props.data.text_revision;
+    let mut sync = use_signal(move || TextSync {
+        revision: text_revision,
+        ..TextSync::default()
+    });
+    let mut carets = use_signal(HashMap::<String, RemoteCaret>::new);
+    // The last caret position sent, so that it is only sent when it moves.
+    let mut sent_caret = use_hook(|| CopyValue::new(None::<(usize, usize)>));
+    let mut scroll_top = use_signal(|| 0);
+    // Why the latest edits couldn't be saved, if they couldn't.
+    let mut save_error = use_signal::<Option<String>>(|| None);
+    // Shown when the text changed underneath unsaved local edits.
+    let mut conflict = use_signal::<Option<String>>(|| None);
+    // Counts the edits, so that autosave only runs once typing pauses.
+    let mut edit_count = use_hook(|| CopyValue::new(0u64));
+    let user_id = use_memo(|| Uuid::new_v4().to_string());
+    // The past versions of the text, while the history panel is open.
+    let mut revisions = use_signal::<Option<Vec<TextRevisionInfo>>>(|| None);
+    // The version picked in the history panel, with a diff of the changes since.
+    let mut picked_revision = use_signal::<Option<(u64, String)>>(|| None);
+    // The language chosen for the text, or `None` to detect it.
+    let mut language = use_signal(|| props.data.language.clone());
+    // Whether the text is shown highlighted, or rendered if it is Markdown,
+    // rather than in the editor.
+    let mut preview = use_signal(|| false);
+    let mut snippets = use_signal(|| props.data.snippets.clone());
+    // The content of the snippets opened so far.
+    let mut snippet_contents = use_signal(HashMap::<String, String>::new);
+    // The tab on screen: a snippet, or the text bin if `None`.
+    let mut active_snippet = use_signal::<Option<String>>(|| None);
+    // How many times each snippet has been edited locally since it was last
+    // saved. Changes from the server don't overwrite unsaved edits.
+    let mut unsaved_snippets = use_signal(HashMap::<String, u64>::new);
+    let mut snippet_error = use_signal::<Option<String>>(|| None);
+
+    let snippet_coroutine = use_coroutine({
+        let hub_id = props.hub_id.clone();
+        move |mut rx: UnboundedReceiver<SnippetRequest>| {
+            let hub_id = hub_id.clone();
+            async move {
+                let client = reqwest::Client::new();
+                while let Some(request) = rx.next().await {
+                    match request {
+                        SnippetRequest::Load(name) => {
+                            match client.get(snippet_url(&hub_id, &name)).send().await {
+                                Ok(res) if res.status().is_success() => {
+                                    let content = res.text().await.unwrap_or_default();
+                                    snippet_contents.write().insert(name, content);
+                                }
+                                Ok(res) => {
+                                    log::error!(
+                                        "Failed to load snippet: {}",
+                                        error_message(res).await
+                                    )
+                                }
+                                Err(e) => log::error!("Failed to load snippet: {}", e),
+                            }
+                        }
+                        SnippetRequest::Save {
+                            name,
+                            content,
+                            edits,
+                        } => {
+                            let res = client
+                                .put(snippet_url(&hub_id, &name))
+                                .body(content)
+                                .send()
+                                .await;
+                            match res {
+                                Ok(res) if res.status().is_success() => {
+                                    snippet_error.set(None);
+                                    // Unless it was edited again in the meantime.
+                                    if unsaved_snippets.peek().get(&name) == Some(&edits) {
+                                        unsaved_snippets.write().remove(&name);
+                                    }
+                                }
+                                Ok(res) => {
+                                    let message = error_message(res).await;
+                                    snippet_error
+                                        .set(Some(format!("Couldn't save {}: {}", name, message)));
+                                }
+                                Err(e) => snippet_error
+                                    .set(Some(format!("Couldn't save {}: {}", name, e))),
+                            }
+                        }
+                        SnippetRequest::Delete(name) => {
+                            match client.delete(snippet_url(&hub_id, &name)).send().await {
+                                Ok(res) if res.status().is_success() => {
+                                    snippets.write().retain(|s| s.name != name);
+                                    if active_snippet.peek().as_deref() == Some(name.as_str()) {
+                                        active_snippet.set(None);
+                                    }
+                                }
+                                Ok(res) => {
+                                    log::error!(
+                                        "Failed to delete snippet: {}",
+                                        error_message(res).await
+                                    )
+                                }
+                                Err(e) => log::error!("Failed to delete snippet: {}", e),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    let history_coroutine = use_coroutine({
+        let api_url = format!(
+            "https://api.ephemeral-hub.com/api/hubs/{}/text",
+            props.hub_id
+        );
+        move |mut rx: UnboundedReceiver<HistoryRequest>| {
+            let api_url = api_url.clone();
+            async move {
+                let client = reqwest::Client::new();
+                while let Some(request) = rx.next().await {
+                    let revision = match request {
+                        HistoryRequest::Load => None,
+                        HistoryRequest::Pick(revision) => {
+                            let diff_url = format!("{}/diff?from={}", api_url, revision);
+                            match client.get(&diff_url).send().await {
+                                Ok(res) if res.status().is_success() => {
+                                    let diff = res.text().await.unwrap_or_default();
+                                    picked_revision.set(Some((revision, diff)));
+                                }
+                                Ok(res) => {
+                                    log::error!("Failed to load diff: {}", error_message(res).await)
+                                }
+                                Err(e) => log::error!("Failed to load diff: {}", e),
+                            }
+                            continue;
+                        }
+                        HistoryRequest::Restore(revision) => Some(revision),
+                    };
+                    // The text itself comes back over the WebSocket.
+                    if let Some(revision) = revision {
+                        let restore_url = format!("{}/revisions/{}/restore", api_url, revision);
+                        match client.post(&restore_url).send().await {
+                            Ok(res) if res.status().is_success() => picked_revision.set(None),
+                            Ok(res) => {
+                                log::error!("Failed to restore text: {}", error_message(res).await)
+                            }
+                            Err(e) => log::error!("Failed to restore text: {}", e),
+                        }
+                    }
+                    let revisions_url = format!("{}/revisions", api_url);
+                    match client.get(&revisions_url).send().await {
+                        Ok(res) => match res.json::<Vec<TextRevisionInfo>>().await {
+                            Ok(list) => revisions.set(Some(list)),
+                            Err(e) => log::error!("Failed to parse text history: {}", e),
+                        },
+                        Err(e) => log::error!("Failed to load text history: {}", e),
+                    }
+                }
+            }
+        }
+    });
+
+    let ws_coroutine = use_coroutine({
+        let hub_id = props.hub_id.clone();
+        move |rx: UnboundedReceiver<WsMessage>| {
+            let ws_url = format!("wss://api.ephemeral-hub.com/ws/hubs/{}", hub_id);
+
+            async move {
+                let ws = match WebSocket::open(&ws_url) {
+                    Ok(ws) => ws,
+                    Err(e) => {
+                        log::error!("Failed to connect to WebSocket: {:?}", e);
+                        return;
+                    }
+                };
+
+                let (mut write, mut read) = ws.split();
+                // Edits that were waiting for the previous one to be confirmed.
+                let (next_tx, next_rx) = futures::channel::mpsc::unbounded::<WsMessage>();
+
+                // Replaces the local text with the server's, dropping unsaved edits.
+                let mut reset_text = move |content: String, revision: u64| {
+                    *sync.write() = TextSync {
+                        revision,
+                        ..TextSync::default()
+                    };
+                    if let Some(textarea) = text_bin_element() {
+                        textarea.set_value(&content);
+                    }
+                    text_content.set(content);
+                };
+
+                // Merges an edit someone else made into the local text.
+                let mut receive_edit = move |revision: u64, operation: TextOperation| {
+                    let (operation, text) =
+                        sync.write()
+                            .received(revision, operation)
+                            .and_then(|operation| {
+                                let text = apply_text_operation(&text_content.peek(), &operation)?;
+                                Ok((operation, text))
+                            })?;
+                    // Keep the local selection on the same characters.
+                    let selection = text_bin_selection(&text_content.peek());
+                    if let Some(textarea) = text_bin_element() {
+                        textarea.set_value(&text);
+                        if let Some((anchor, head)) = selection {
+                            let (anchor, head) = (
+                                transform_text_position(&operation, anchor),
+                                transform_text_position(&operation, head),
+                            );
+                            let _ = textarea.set_selection_range(
+                                char_to_utf16_index(&text, anchor.min(head)),
+                                char_to_utf16_index(&text, anchor.max(head)),
+                            );
+                        }
+                    }
+                    for caret in carets.write().values_mut() {
+                        caret.position = transform_text_position(&operation, caret.position);
+                    }
+                    text_content.set(text);
+                    Ok::<_, String>(())
+                };
+
+                // Incoming messages
+                spawn(async move {
+                    while let Some(Ok(GlooWsMessage::Text(text))) = read.next().await {
+                        let Ok(server_msg) = serde_json::from_str::<WsMessage>(&text) else {
+                            continue;
+                        };
+                        match server_msg {
+                            WsMessage::TextSnapshot { content, revision } => {
+                                // Sent on connecting, or when our edits couldn't be applied.
+                                if sync.peek().is_unsaved() {
+                                    conflict.set(Some(
+                                    "The text changed before your latest edits could be saved, so they were lost. The current text has been loaded.".to_string(),
+                                ));
+                                }
+                                reset_text(content, revision);
+                            }
+                            // Edits from before the last snapshot are already part of the text.
+                            WsMessage::TextEdit { revision, .. }
+                            | WsMessage::TextUpdated { revision, .. }
+                                if revision <= sync.peek().revision => {}
+                            WsMessage::TextEdit {
+                                revision,
+                                user_id: author,
+                                ..
+                            } if author == user_id() => {
+                                sync.write().confirmed(revision);
+                                save_error.set(None);
+                                if let Some(operation) = sync.write().flush(js_sys::Date::now()) {
+                                    let _ = next_tx.unbounded_send(WsMessage::TextEdit {
+                                        revision,
+                                        operation,
+                                        user_id: user_id(),
+                                    });
+                                }
+                            }
+                            WsMessage::TextEdit {
+                                revision,
+                                operation,
+                                ..
+                            } => {
+                                if let Err(e) = receive_edit(revision, operation) {
+                                    log::error!("Failed to apply text edit: {}", e);
+                                }
+                            }
+                            // The text was replaced through the API, e.g. with `ephemeral pipe`.
+                            WsMessage::TextUpdated {
+                                content,
+                                revision,
+                                operation,
+                            } => {
+                                if sync.peek().is_unsaved() {
+                                    conflict.set(Some(
+                                    "The text was replaced while you were editing. Your unsaved edits were merged into the new text; check that it still reads as intended.".to_string(),
+                                ));
+                                }
+                                if revision != sync.peek().revision + 1
+                                    || receive_edit(revision, operation).is_err()
+                                {
+                                    reset_text(content, revision);
+                                }
+                            }
+                            WsMessage::TextLanguageChanged { language: chosen } => {
+                                language.set(chosen);
+                            }
+                            WsMessage::SnippetUpdated { snippet, content } => {
+                                if !unsaved_snippets.peek().contains_key(&snippet.name) {
+                                    snippet_contents
+                                        .write()
+                                        .insert(snippet.name.clone(), content);
+                                }
+                                let mut list = snippets.write();
+                                match list.iter_mut().find(|s| s.name == snippet.name) {
+                                    Some(existing) => *existing = snippet,
+                                    None => list.push(snippet),
+                                }
+                            }
+                            WsMessage::SnippetDeleted { name } => {
+                                snippets.write().retain(|s| s.name != name);
+                                snippet_contents.write().remove(&name);
+                                if active_snippet.peek().as_deref() == Some(name.as_str()) {
+                                    active_snippet.set(None);
+                                }
+                            }
+                            WsMessage::TextCursor {
+                                user_id: other,
+                                head,
+                                ..
+                            } if other != user_id() => {
+                                carets.write().insert(
+                                    other,
+                                    RemoteCaret {
+                                        position: head,
+                                        seen_at: js_sys::Date::now(),
+                                    },
+                                );
+                            }
+                            // Whiteboard messages are handled by the whiteboard.
+                            _ => {}
+                        }
+                    }
+                    save_error.set(Some(
+                        "Disconnected from the server. Reload the page to keep editing."
+                            .to_string(),
+                    ));
+                });
+
+                // Outgoing messages
+                let mut outgoing = futures::stream::select(rx, next_rx);
+                while let Some(msg_to_send) = outgoing.next().await {
+                    let json_msg = serde_json::to_string(&msg_to_send).unwrap();
+                    if write.send(GlooWsMessage::Text(json_msg)).await.is_err() {
+                        log::error!("WebSocket connection closed. Cannot send message.");
+                        save_error.set(Some(
+                            "Disconnected from the server. Reload the page to keep editing."
+                                .to_string(),
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    // Sends the edits collected so far, unless earlier ones are still on their way.
+    let mut save_edits = move || {
+        let revision = sync.peek().revision;
+        if let Some(operation) = sync.write().flush(js_sys::Date::now()) {
+            ws_coroutine.send(WsMessage::TextEdit {
+                revision,
+                operation,
+                user_id: user_id(),
+            });
+        }
+    };
+
+    // Reports saving as failed when the server takes too long to confirm an edit.
+    use_future(move || async move {
+        loop {
+            sleep(Duration::from_secs(1)).await;
+            let overdue = sync
+                .peek()
+                .sent
+                .as_ref()
+                .is_some_and(|(_, sent_at)| js_sys::Date::now() - sent_at > SAVE_TIMEOUT_MS);
+            if overdue && save_error.peek().is_none() {
+                save_error.set(Some(
+                    "Your latest edits haven't been saved yet. Check your connection.".to_string(),
+                ));
+            }
+        }
+    });
+
+    // Tells everyone else where the local caret is, if it moved.
+    let mut send_caret = move || {
+        let Some((anchor, head)) = text_bin_selection(&text_content.peek()) else {
+            return;
+        };
+        if sent_caret() == Some((anchor, head)) {
+            return;
+        }
+        sent_caret.set(Some((anchor, head)));
+        ws_coroutine.send(WsMessage::TextCursor {
+            user_id: user_id(),
+            anchor,
+            head,
+        });
+    };
+
+    // Tells everyone else which language the text is in.
+    let language_url = format!(
+        "https://api.ephemeral-hub.com/api/hubs/{}/text/language",
+        props.hub_id
+    );
+    let mut choose_language = move |chosen: Option<String>| {
+        language.set(chosen.clone());
+        let language_url = language_url.clone();
+        spawn(async move {
+            let res = reqwest::Client::new()
+                .put(&language_url)
+                .json(&serde_json::json!({ "language": chosen }))
+                .send()
+                .await;
+            match res {
+                Ok(res) if res.status().is_success() => {}
+                Ok(res) => {
+                    log::error!(
+                        "Failed to set the text language: {}",
+                        error_message(res).await
+                    )
+                }
+                Err(e) => log::error!("Failed to set the text language: {}", e),
+            }
+        });
+    };
+    let detected_language = syntax_for(detect_language(&text_content.read())).label;
+    let shown_language =
+        language().unwrap_or_else(|| detect_language(&text_content.read()).to_string());
+
+    // The text split at the carets of everyone else, to draw them over the textarea.
+    let now = js_sys::Date::now();
+    let mut visible_carets: Vec<(usize, String)> = carets
+        .read()
+        .iter()
+        .filter(|(_, caret)| now - caret.seen_at < CARET_TIMEOUT_MS)
+        .map(|(id, caret)| (caret.position, color_for_user(id)))
+        .collect();
+    visible_carets.sort();
+    let text_chars: Vec<char> = text_content.read().chars().collect();
+    let mut caret_segments = Vec::new();
+    let mut segment_start = 0;
+    for (position, color) in visible_carets {
+        let position = position.min(text_chars.len());
+        caret_segments.push((
+            text_chars[segment_start..position]
+                .iter()
+                .collect::<String>(),
+            Some(color),
+        ));
+        segment_start = position;
+    }
+    caret_segments.push((text_chars[segment_start..].iter().collect(), None));
+
+    rsx! {
+        div { class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300",
+            div { class: "flex items-center gap-3 mb-4",
+                div { class: "w-10 h-10 bg-blue-500/20 rounded-lg flex items-center justify-center",
+                   svg {
+                        class: "w-6 h-6 text-orange-400",
+                        fill: "none",
+                        stroke: "currentColor",
+                        view_box: "0 0 24 24",
+                        path {
+                            stroke_linecap: "round",
+                            stroke_linejoin: "round",
+                            stroke_width: "2",
+                            d: "M9 5H7a2 2 0 00-2 2v12a2 2 0 002 2h10a2 2 0 002-2V7a2 2 0 00-2-2h-2M9 5a2 2 0 002 2h2a2 2 0 002-2M9 5a2 2 0 012-2h2a2 2 0 012 2m-3 7h3m-3 4h3m-6-4h.01M9 16h.01"
+                        }
+                    }
+                }
+                h2 { class: "text-xl font-semibold text-white", "Text Content" }
+                if let Some(name) = active_snippet() {
+                    if let Some(error) = snippet_error() {
+                        span { class: "ml-auto text-xs text-red-400", "{error}" }
+                    } else if unsaved_snippets.read().contains_key(&name) {
+                        span { class: "ml-auto text-xs text-slate-400", "Saving..." }
+                    } else {
+                        span { class: "ml-auto text-xs text-slate-400", "All changes saved" }
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-md bg-slate-700/50 text-xs text-slate-300 hover:bg-red-500/30 hover:text-white transition-colors",
+                        onclick: move |_| {
+                            let confirmed = window()
+                                .and_then(|w| {
+                                    w.confirm_with_message(&format!("Delete the snippet {}?", name)).ok()
+                                })
+                                .unwrap_or(false);
+                            if confirmed {
+                                snippet_coroutine.send(SnippetRequest::Delete(name.clone()));
+                            }
+                        },
+                        "Delete"
+                    }
+                } else {
+                    if let Some(error) = save_error() {
+                        span { class: "ml-auto text-xs text-red-400", "{error}" }
+                    } else if sync.read().is_unsaved() {
+                        span { class: "ml-auto text-xs text-slate-400", "Saving..." }
+                    } else {
+                        span { class: "ml-auto text-xs text-slate-400", "All changes saved" }
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-md bg-slate-700/50 text-xs text-slate-300 hover:bg-slate-600/50 hover:text-white transition-colors",
+                        onclick: move |_| {
+                            if revisions.peek().is_some() {
+                                revisions.set(None);
+                                picked_revision.set(None);
+                            } else {
+                                history_coroutine.send(HistoryRequest::Load);
+                            }
+                        },
+                        if revisions.read().is_some() { "Hide history" } else { "History" }
+                    }
+                }
+            }
+
+            // The text bin and the named snippets of the hub.
+            div { class: "flex flex-wrap items-center gap-1 mb-3",
+                button {
+                    class: if active_snippet.read().is_none() { "px-3 py-1 rounded-md bg-blue-500/20 text-sm text-blue-200" } else { "px-3 py-1 rounded-md text-sm text-slate-400 hover:bg-slate-700/40 hover:text-slate-200" },
+                    onclick: move |_| active_snippet.set(None),
+                    "Text"
+                }
+                for (snippet, name) in snippets().into_iter().map(|s| (s.clone(), s.name)) {
+                    button {
+                        key: "{snippet.name}",
+                        class: if active_snippet.read().as_deref() == Some(snippet.name.as_str()) { "px-3 py-1 rounded-md bg-blue-500/20 text-sm text-blue-200" } else { "px-3 py-1 rounded-md text-sm text-slate-400 hover:bg-slate-700/40 hover:text-slate-200" },
+                        title: "{snippet.content_type}",
+                        onclick: move |_| {
+                            if !snippet_contents.peek().contains_key(&name) {
+                                snippet_coroutine.send(SnippetRequest::Load(name.clone()));
+                            }
+                            active_snippet.set(Some(name.clone()));
+                        },
+                        "{snippet.name}"
+                    }
+                }
+                button {
+                    class: "px-2 py-1 rounded-md text-sm text-slate-400 hover:bg-slate-700/40 hover:text-slate-200",
+                    title: "New snippet",
+                    onclick: move |_| {
+                        let Some(name) = prompt_for_text("Name of the new snippet, e.g. notes.md") else {
+                            return;
+                        };
+                        let name = name.trim().to_string();
+                        if !snippets.peek().iter().any(|s| s.name == name) {
+                            snippet_coroutine.send(SnippetRequest::Save {
+                                name: name.clone(),
+                                content: String::new(),
+                                edits: 0,
+                            });
+                        }
+                        snippet_contents.write().entry(name.clone()).or_default();
+                        active_snippet.set(Some(name));
+                    },
+                    "+"
+                }
+            }
+
+            if let Some(message) = conflict() {
+                div { class: "flex items-start gap-3 mb-3 p-3 rounded-lg bg-amber-500/10 border border-amber-500/30 text-amber-200 text-sm",
+                    span { class: "flex-1", "{message}" }
+                    button {
+                        class: "text-amber-200/70 hover:text-amber-100",
+                        title: "Dismiss",
+                        onclick: move |_| conflict.set(None),
+                        "✕"
+                    }
+                }
+            }
+
+            if let Some(name) = active_snippet() {
+                if let Some(content) = snippet_contents.read().get(&name).cloned() {
+                    textarea {
+                        key: "{name}",
+                        class: "w-full min-h-[200px] bg-slate-900/50 border border-slate-600/50 rounded-lg p-4 text-slate-100 placeholder-slate-400 focus:border-blue-500/50 focus:ring-2 focus:ring-blue-500/20 focus:outline-none transition-all duration-300 font-mono text-sm resize-none",
+                        placeholder: "Enter the content of {name}...",
+                        value: "{content}",
+                        oninput: move |event| {
+                            let content = event.value();
+                            snippet_contents.write().insert(name.clone(), content);
+                            let edits = {
+                                let mut unsaved = unsaved_snippets.write();
+                                let edits = unsaved.entry(name.clone()).or_default();
+                                *edits += 1;
+                                *edits
+                            };
+
+                            let name = name.clone();
+                            spawn(async move {
+                                sleep(Duration::from_millis(AUTOSAVE_DELAY_MS)).await;
+                                if unsaved_snippets.peek().get(&name) == Some(&edits) {
+                                    let content = snippet_contents.peek().get(&name).cloned().unwrap_or_default();
+                                    snippet_coroutine.send(SnippetRequest::Save { name, content, edits });
+                                }
+                            });
+                        },
+                    }
+                } else {
+                    p { class: "text-sm text-slate-400", "Loading {name}..." }
+                }
+            }
+
+            if active_snippet.read().is_none() {
+                div { class: "flex items-center gap-2 mb-3",
+                    select {
+                        class: "bg-slate-900/50 border border-slate-600/50 rounded-md px-2 py-1 text-xs text-slate-200 focus:outline-none focus:border-blue-500/50",
+                        onchange: move |event| {
+                            let value = event.value();
+                            choose_language((!value.is_empty()).then_some(value));
+                        },
+                        option { value: "", selected: language.read().is_none(), "Auto ({detected_language})" }
+                        for syntax in LANGUAGES {
+                            option {
+                                value: syntax.id,
+                                selected: language.read().as_deref() == Some(syntax.id),
+                                "{syntax.label}"
+                            }
+                        }
+                    }
+                    button {
+                        class: "px-3 py-1 rounded-md bg-slate-700/50 text-xs text-slate-300 hover:bg-slate-600/50 hover:text-white transition-colors",
+                        onclick: move |_| preview.toggle(),
+                        if preview() { "Edit" } else { "Preview" }
+                    }
+                }
+                if preview() {
+                    if shown_language == "markdown" {
+                        div {
+                            class: "prose prose-invert prose-sm max-w-none min-h-[200px] bg-slate-900/50 border border-slate-600/50 rounded-lg p-4",
+                            dangerous_inner_html: render_markdown(&text_content.read()),
+                        }
+                    } else {
+                        pre { class: "min-h-[200px] bg-slate-900/50 border border-slate-600/50 rounded-lg p-4 text-slate-100 font-mono text-sm whitespace-pre-wrap break-words",
+                            for (token, class) in highlight(&text_content.read(), syntax_for(&shown_language)) {
+                                span { class: class, "{token}" }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Stays in place while a snippet or the preview is shown, so that edits
+            // made by others keep being merged into it.
+            div { class: if active_snippet.read().is_some() || preview() { "hidden" } else { "relative" },
+                textarea {
+                    id: TEXT_BIN_ID,
+                    class: "w-full min-h-[200px] bg-slate-900/50 border border-slate-600/50 rounded-lg p-4 text-slate-100 placeholder-slate-400 focus:border-blue-500/50 focus:ring-2 focus:ring-blue-500/20 focus:outline-none transition-all duration-300 font-mono text-sm resize-none",
+                    placeholder: "Enter your text content here...",
+                    value: "{text_content}",
+                    oninput: move |event| {
+                        let text = event.value();
+                        let operation = diff_text(&text_content.peek(), &text);
+                        text_content.set(text);
+                        sync.write().edit(operation);
+                        send_caret();
+
+                        *edit_count.write() += 1;
+                        let count = edit_count();
+                        spawn(async move {
+                            sleep(Duration::from_millis(AUTOSAVE_DELAY_MS)).await;
+                            if edit_count() == count {
+                                save_edits();
+                            }
+                        });
+                    },
+                    onselect: move |_| send_caret(),
+                    onkeyup: move |_| send_caret(),
+                    onmouseup: move |_| send_caret(),
+                    onscroll: move |_| {
+                        if let Some(textarea) = text_bin_element() {
+                            scroll_top.set(textarea.scroll_top());
+                        }
+                    },
+                }
+                // Carets of everyone else, over text laid out exactly like the textarea's.
+                div { class: "absolute inset-0 overflow-hidden pointer-events-none rounded-lg border border-transparent",
+                    div {
+                        class: "p-4 font-mono text-sm whitespace-pre-wrap break-words text-transparent",
+                        style: "transform: translateY(-{scroll_top}px);",
+                        for (segment, color) in caret_segments {
+                            "{segment}"
+                            if let Some(color) = color {
+                                span {
+                                    class: "relative",
+                                    style: "border-left: 2px solid {color}; margin: 0 -1px;",
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let (Some(list), None) = (revisions(), active_snippet()) {
+                div { class: "mt-4 grid gap-3 md:grid-cols-[14rem_1fr]",
+                    div { class: "max-h-72 overflow-y-auto space-y-1",
+                        if list.is_empty() {
+                            p { class: "text-sm text-slate-400", "No earlier versions yet." }
+                        }
+                        for info in list.into_iter().rev() {
+                            button {
+                                key: "{info.revision}",
+                                class: if picked_revision.read().as_ref().is_some_and(|(r, _)| *r == info.revision) {
+                                    "w-full text-left px-3 py-2 rounded-lg bg-blue-500/20 border border-blue-500/40"
+                                } else {
+                                    "w-full text-left px-3 py-2 rounded-lg hover:bg-slate-700/40 border border-transparent"
+                                },
+                                onclick: move |_| history_coroutine.send(HistoryRequest::Pick(info.revision)),
+                                div { class: "text-sm text-slate-200",
+                                    "#{info.revision} · {info.source.label()}"
+                                }
+                                div { class: "text-xs text-slate-400",
+                                    "{revision_time(&info.at)} · {info.length} characters"
+                                }
+                            }
+                        }
+                    }
+                    if let Some((revision, diff)) = picked_revision() {
+                        div { class: "min-w-0",
+                            div { class: "flex items-center gap-2 mb-2",
+                                span { class: "text-sm text-slate-300", "Changes since #{revision}" }
+                                button {
+                                    class: "ml-auto px-3 py-1 rounded-md bg-blue-600/80 text-xs text-white hover:bg-blue-500 transition-colors",
+                                    onclick: move |_| history_coroutine.send(HistoryRequest::Restore(revision)),
+                                    "Restore this version"
+                                }
+                            }
+                            pre { class: "max-h-64 overflow-auto bg-slate-900/50 border border-slate-600/50 rounded-lg p-3 font-mono text-xs",
+                                if diff.is_empty() {
+                                    span { class: "text-slate-400", "No changes since." }
+                                }
+                                for line in diff.lines().map(str::to_string).collect::<Vec<_>>() {
+                                    div { class: diff_line_class(&line), "{line}" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A sub-component for the File Drop UI.
+#[derive(PartialEq, Props, Clone)]
+struct FileDropProps {
+    hub_id: String,
+    files: Vec<FileInfo>,
+    hub_resource: Resource<Option<HubData>>,
+}
+
+#[allow(non_snake_case)]
+fn FileDrop(props: FileDropProps) -> Element {
+    let is_uploading = use_signal(|| false);
+    let upload_error = use_signal::<Option<String>>(|| None);
+
+    // The coroutine now expects a Vec containing the filename and its bytes.
+    let upload_coroutine: Coroutine<Vec<(String, Vec<u8>)>> = use_coroutine({
+        let hub_id = props.hub_id.clone();
+        move |mut rx: UnboundedReceiver<Vec<(String, Vec<u8>)>>| {
+            let hub_id = hub_id.clone();
+            let mut hub_resource = props.hub_resource.clone();
+            let mut is_uploading = is_uploading.clone();
+            let mut upload_error = upload_error;
+            async move {
+                while let Some(files_with_data) = rx.next().await {
+                    is_uploading.set(true);
+                    let mut form = multipart::Form::new();
+                    for (filename, file_bytes) in files_with_data {
+                        let part = multipart::Part::bytes(file_bytes).file_name(filename);
+                        form = form.part("file", part);
+                    }
+
+                    let client = reqwest::Client::new();
+                    let api_url =
+                        format!("https://api.ephemeral-hub.com/api/hubs/{}/files", hub_id);
+
+                    let res = client.post(api_url).multipart(form).send().await;
+
+                    match res {
+                        Ok(res) if res.status().is_success() => {
+                            upload_error.set(None);
+                            hub_resource.restart();
+                        }
+                        Ok(res) => upload_error.set(Some(error_message(res).await)),
+                        Err(e) => upload_error.set(Some(e.to_string())),
+                    }
+                    is_uploading.set(false);
+                }
+            }
+        }
+    });
+
+    rsx! {
+        div {
+            class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300",
+              div { class: "flex items-center gap-3 mb-4",
+                div { class: "w-10 h-10 bg-blue-500/20 rounded-lg flex items-center justify-center",
+                    svg {
+                        class: "w-5 h-5 text-blue-400",
+                        fill: "none",
+                        stroke: "currentColor",
+                        view_box: "0 0 24 24",
+                        path {
+                            stroke_linecap: "round",
+                            stroke_linejoin: "round",
+                            stroke_width: "2",
+                            d: "M9 12h6m-6 4h6m2 5H7a2 2 0 01-2-2V5a2 2 0 012-2h5.586a1 1 0 01.707.293l5.414 5.414a1 1 0 01.293.707V19a2 2 0 01-2 2z"
+                        }
+                    }
+                }
+                h2 { class: "text-xl font-semibold text-white", "FILES" }
+            }
+            p {
+                class: "text-white py-4",
+                "Upload files to share them temporarily."
+            }
+
+            input {
+                r#type: "file",
+                multiple: true,
+                id: "file-upload",
+                class: "hidden",
+                onchange: move |evt| {
+                    // Spawn a task to handle the async file reading.
+                    spawn({
+                        let upload_coroutine = upload_coroutine.clone();
+                        async move {
+                            // Use `.files()` to get the file engine.
+                            if let Some(file_engine) = evt.files() {
+                                // Get the list of file names.
+                                let files = file_engine.files();
+                                let mut files_with_data = Vec::new();
+                                // Iterate over the file names and read each one.
+                                for file_name in &files {
+                                    if let Some(file_bytes) = file_engine.read_file(file_name).await {
+                                        files_with_data.push((file_name.clone(), file_bytes));
+                                    }
+                                }
+                                if !files_with_data.is_empty() {
+                                    upload_coroutine.send(files_with_data);
+                                }
+                            }
+                        }
+                    });
+                }
+            }
+
+            // The button that triggers the file input.
+            label {
+                r#for: "file-upload",
+                class: "cursor-pointer inline-flex items-center gap-2 px-4 py-2 bg-indigo-600 text-white font-semibold rounded-lg shadow-sm hover:bg-indigo-700 focus:outline-none focus:ring-2 focus:ring-indigo-500 focus:ring-offset-2 transition-all duration-150",
+                if is_uploading() {
+                    "Uploading..."
+                } else {
+                    "Upload Files"
+                }
+            }
+            if let Some(error) = upload_error() {
+                p { class: "mt-2 text-sm text-red-400", "Upload failed: {error}" }
+            }
+
+            p {
+                class: "text-white pt-20",
+                "Files uploaded:"
+            }
+            ul {
+                class: "list-disc pl-5 mt-4 text-white",
+                for file in props.files.iter() {
+                    li {
+                        class: "mb-2",
+                        a {
+                            class: "text-blue-300 hover:text-blue-200 underline",
+                            href: file_url(&props.hub_id, &file.filename),
+                            "{file.filename}"
+                        }
+                        " ({file.size} bytes)"
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Data structure for a single drawing path.
+// Like every whiteboard element, its points are in world coordinates, which
+// don't depend on how far the viewer has panned or zoomed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct PathData {
+    id: String,
+    #[serde(
+        serialize_with = "serialize_points",
+        deserialize_with = "deserialize_points"
+    )]
+    points: Vec<StrokePoint>,
+    color: String,
+    stroke_width: f64,
+    #[serde(default)]
+    author: String,
+    #[serde(default = "default_opacity")]
+    opacity: f64,
+    // Highlighter strokes are blended with what is underneath them
+    #[serde(default)]
+    highlighter: bool,
+}
+
+fn default_opacity() -> f64 {
+    1.0
+}
+
+// A point along a path, with the pen pressure (0.0 to 1.0) if it was drawn with one.
+// Serialized as `[x, y]` or `[x, y, pressure]`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(from = "StrokePointRepr", into = "StrokePointRepr")]
+struct StrokePoint {
+    x: f64,
+    y: f64,
+    pressure: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum StrokePointRepr {
+    Plain(f64, f64),
+    WithPressure(f64, f64, f64),
+}
+
+impl From<StrokePointRepr> for StrokePoint {
+    fn from(repr: StrokePointRepr) -> Self {
+        match repr {
+            StrokePointRepr::Plain(x, y) => StrokePoint {
+                x,
+                y,
+                pressure: None,
+            },
+            StrokePointRepr::WithPressure(x, y, pressure) => StrokePoint {
+                x,
+                y,
+                pressure: Some(pressure),
+            },
+        }
+    }
+}
+
+impl From<StrokePoint> for StrokePointRepr {
+    fn from(point: StrokePoint) -> Self {
+        match point.pressure {
+            Some(pressure) => StrokePointRepr::WithPressure(point.x, point.y, pressure),
+            None => StrokePointRepr::Plain(point.x, point.y),
+        }
+    }
+}
+
+// A rectangle or an ellipse, described by its bounding box
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct ShapeData {
+    id: String,
+    author: String,
+    bounds: Bounds,
+    color: String,
+    stroke_width: f64,
+}
+
+// A straight line or an arrow pointing from `start` to `end`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct LineData {
+    id: String,
+    author: String,
+    start: (f64, f64),
+    end: (f64, f64),
+    color: String,
+    stroke_width: f64,
+}
+
+// A free-standing text label; `position` is its top-left corner
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct TextData {
+    id: String,
+    author: String,
+    position: (f64, f64),
+    text: String,
+    color: String,
+    font_size: f64,
+}
+
+// A colored sticky note with some text on it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct StickyNoteData {
+    id: String,
+    author: String,
+    bounds: Bounds,
+    text: String,
+    color: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+struct Bounds {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+// Anything that can be placed on a whiteboard page.
+// Mirrors `BoardElement` in the backend's shared types.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum BoardElement {
+    Path(PathData),
+    Rectangle(ShapeData),
+    Ellipse(ShapeData),
+    Line(LineData),
+    Arrow(LineData),
+    Text(TextData),
+    StickyNote(StickyNoteData),
+}
+
+// Text labels are measured the same way as on the server.
+const TEXT_LINE_HEIGHT: f64 = 1.2;
+const TEXT_CHAR_WIDTH: f64 = 0.6;
+// The smallest size an element can be drawn or resized to.
+const MIN_ELEMENT_SIZE: f64 = 4.0;
+const STICKY_NOTE_FONT_SIZE: f64 = 14.0;
+const STICKY_NOTE_PADDING: f64 = 8.0;
+// Width of a stroke at zero pen pressure, relative to its stroke width.
+const MIN_PRESSURE_WIDTH: f64 = 0.25;
+
+impl BoardElement {
+    fn id(&self) -> &str {
+        match self {
+            BoardElement::Path(p) => &p.id,
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => &s.id,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => &l.id,
+            BoardElement::Text(t) => &t.id,
+            BoardElement::StickyNote(n) => &n.id,
+        }
+    }
+
+    fn bounds(&self) -> Bounds {
+        match self {
+            BoardElement::Path(p) => Bounds::around(p.points.iter().map(|pt| (pt.x, pt.y))),
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => s.bounds,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => Bounds::around([l.start, l.end]),
+            BoardElement::Text(t) => {
+                let longest_line = t.text.lines().map(|l| l.chars().count()).max();
+                let line_count = t.text.lines().count().max(1);
+                Bounds {
+                    x: t.position.0,
+                    y: t.position.1,
+                    width: longest_line.unwrap_or(0) as f64 * t.font_size * TEXT_CHAR_WIDTH,
+                    height: line_count as f64 * t.font_size * TEXT_LINE_HEIGHT,
+                }
+            }
+            BoardElement::StickyNote(n) => n.bounds,
+        }
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        let target = Bounds {
+            x,
+            y,
+            ..self.bounds()
+        };
+        self.set_bounds(target);
+    }
+
+    fn set_bounds(&mut self, target: Bounds) {
+        let current = self.bounds();
+        let map = |(x, y): (f64, f64)| current.map_point_to(&target, (x, y));
+        match self {
+            BoardElement::Path(p) => p.points.iter_mut().for_each(|pt| {
+                (pt.x, pt.y) = map((pt.x, pt.y));
+            }),
+            BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => s.bounds = target,
+            BoardElement::Line(l) | BoardElement::Arrow(l) => {
+                l.start = map(l.start);
+                l.end = map(l.end);
+            }
+            BoardElement::Text(t) => {
+                if current.height > 0.0 {
+                    t.font_size *= target.height / current.height;
+                }
+                t.position = (target.x, target.y);
+            }
+            BoardElement::StickyNote(n) => n.bounds = target,
+        }
+    }
+
+    // Whether a freshly drawn element is big enough to keep.
+    fn is_visible(&self) -> bool {
+        match self {
+            BoardElement::Path(p) => !p.points.is_empty(),
+            BoardElement::Line(_) | BoardElement::Arrow(_) => {
+                let b = self.bounds();
+                b.width.max(b.height) >= MIN_ELEMENT_SIZE
+            }
+            _ => {
+                let b = self.bounds();
+                b.width >= MIN_ELEMENT_SIZE && b.height >= MIN_ELEMENT_SIZE
+            }
+        }
+    }
+}
+
+impl Bounds {
+    fn around(points: impl IntoIterator<Item = (f64, f64)>) -> Bounds {
+        let mut points = points.into_iter();
+        let Some((x, y)) = points.next() else {
+            return Bounds {
+                x: 0.0,
+                y: 0.0,
+                width: 0.0,
+                height: 0.0,
+            };
+        };
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+        for (x, y) in points {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        Bounds {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        }
+    }
+
+    fn map_point_to(&self, target: &Bounds, (x, y): (f64, f64)) -> (f64, f64) {
+        let scale_x = if self.width > 0.0 {
+            target.width / self.width
+        } else {
+            1.0
+        };
+        let scale_y = if self.height > 0.0 {
+            target.height / self.height
+        } else {
+            1.0
+        };
+        (
+            target.x + (x - self.x) * scale_x,
+            target.y + (y - self.y) * scale_y,
+        )
+    }
+}
+
+// Metadata for a single whiteboard page (tab)
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct PageInfo {
+    id: String,
+    name: String,
+}
+
+// Metadata for a named text snippet of a hub (tab)
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct SnippetInfo {
+    name: String,
+    content_type: String,
+    size: u64,
+}
+
+// Message format for WebSocket communication.
+// `Undo` and `Redo` are requests; the server answers with the resulting
+// edits. `PageCreated`, `TextSnapshot`, `TextUpdated`, `ServerRestarting`
+// and the snippet messages are only sent by the server.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+enum WsMessage {
+    ElementAdded {
+        page_id: String,
+        element: BoardElement,
+    },
+    ElementDeleted {
+        page_id: String,
+        id: String,
+        user_id: String,
+    },
+    ElementMoved {
+        page_id: String,
+        id: String,
+        user_id: String,
+        x: f64,
+        y: f64,
+    },
+    ElementResized {
+        page_id: String,
+        id: String,
+        user_id: String,
+        bounds: Bounds,
+    },
+    BoardCleared {
+        page_id: String,
+        user_id: String,
+    },
+    Undo {
+        page_id: String,
+        user_id: String,
+    },
+    Redo {
+        page_id: String,
+        user_id: String,
+    },
+    PageCreated(PageInfo),
+    // Sent with the revision the edit was made on; broadcast with the
+    // revision it produced.
+    TextEdit {
+        revision: u64,
+        operation: TextOperation,
+        user_id: String,
+    },
+    TextCursor {
+        user_id: String,
+        anchor: usize,
+        head: usize,
+    },
+    TextSnapshot {
+        content: String,
+        revision: u64,
+    },
+    // The whole text was replaced through the REST API.
+    TextUpdated {
+        content: String,
+        revision: u64,
+        operation: TextOperation,
+    },
+    SnippetUpdated {
+        snippet: SnippetInfo,
+        content: String,
+    },
+    SnippetDeleted {
+        name: String,
+    },
+    // `None` to detect the language from the text.
+    TextLanguageChanged {
+        language: Option<String>,
+    },
+    // The server is about to close the connection; reconnect after it does.
+    ServerRestarting,
+}
+
+impl WsMessage {
+    // The whiteboard page this message applies to, if any.
+    fn page_id(&self) -> Option<&str> {
+        match self {
+            WsMessage::ElementAdded { page_id, .. }
+            | WsMessage::ElementDeleted { page_id, .. }
+            | WsMessage::ElementMoved { page_id, .. }
+            | WsMessage::ElementResized { page_id, .. }
+            | WsMessage::BoardCleared { page_id, .. }
+            | WsMessage::Undo { page_id, .. }
+            | WsMessage::Redo { page_id, .. } => Some(page_id),
+            WsMessage::PageCreated(_)
+            | WsMessage::TextEdit { .. }
+            | WsMessage::TextCursor { .. }
+            | WsMessage::TextSnapshot { .. }
+            | WsMessage::TextUpdated { .. }
+            | WsMessage::SnippetUpdated { .. }
+            | WsMessage::SnippetDeleted { .. }
+            | WsMessage::TextLanguageChanged { .. }
+            | WsMessage::ServerRestarting => None,
+        }
+    }
+}
+
+// One change to a whiteboard page, as recorded by the server for replays.
+// `at` is an RFC 3339 timestamp.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct LoggedOperation {
+    at: String,
+    user_id: String,
+    message: WsMessage,
+}
+
+// Applies a recorded change to a board being replayed.
+fn replay_effect(board: &mut Vec<BoardElement>, msg: &WsMessage) {
+    match msg {
+        WsMessage::ElementAdded { element, .. } => {
+            board.retain(|e| e.id() != element.id());
+            board.push(element.clone());
+        }
+        WsMessage::ElementDeleted { id, .. } => board.retain(|e| e.id() != id),
+        WsMessage::ElementMoved { id, x, y, .. } => {
+            if let Some(element) = board.iter_mut().find(|e| e.id() == id) {
+                element.move_to(*x, *y);
+            }
+        }
+        WsMessage::ElementResized { id, bounds, .. } => {
+            if let Some(element) = board.iter_mut().find(|e| e.id() == id) {
+                element.set_bounds(*bounds);
+            }
+        }
+        WsMessage::BoardCleared { .. } => board.clear(),
+        WsMessage::Undo { .. }
+        | WsMessage::Redo { .. }
+        | WsMessage::PageCreated(_)
+        | WsMessage::TextEdit { .. }
+        | WsMessage::TextCursor { .. }
+        | WsMessage::TextSnapshot { .. }
+        | WsMessage::TextUpdated { .. }
+        | WsMessage::SnippetUpdated { .. }
+        | WsMessage::SnippetDeleted { .. }
+        | WsMessage::TextLanguageChanged { .. }
+        | WsMessage::ServerRestarting => {}
+    }
+}
+
+// The tool currently selected in the whiteboard toolbar.
+#[derive(PartialEq, Clone, Copy)]
+enum WhiteboardTool {
+    Select,
+    Pan,
+    Pen,
+    Highlighter,
+    Eraser,
+    Rectangle,
+    Ellipse,
+    Line,
+    Arrow,
+    Text,
+    StickyNote,
+}
+
+impl WhiteboardTool {
+    const ALL: [WhiteboardTool; 11] = [
+        WhiteboardTool::Select,
+        WhiteboardTool::Pan,
+        WhiteboardTool::Pen,
+        WhiteboardTool::Highlighter,
+        WhiteboardTool::Eraser,
+        WhiteboardTool::Rectangle,
+        WhiteboardTool::Ellipse,
+        WhiteboardTool::Line,
+        WhiteboardTool::Arrow,
+        WhiteboardTool::Text,
+        WhiteboardTool::StickyNote,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            WhiteboardTool::Select => "Select",
+            WhiteboardTool::Pan => "Pan",
+            WhiteboardTool::Pen => "Pen",
+            WhiteboardTool::Highlighter => "Highlighter",
+            WhiteboardTool::Eraser => "Eraser",
+            WhiteboardTool::Rectangle => "Rectangle",
+            WhiteboardTool::Ellipse => "Ellipse",
+            WhiteboardTool::Line => "Line",
+            WhiteboardTool::Arrow => "Arrow",
+            WhiteboardTool::Text => "Text",
+            WhiteboardTool::StickyNote => "Sticky note",
+        }
+    }
+
+    // Whether the tool draws with the chosen color, width and opacity.
+    fn uses_stroke_style(self) -> bool {
+        !matches!(
+            self,
+            WhiteboardTool::Select
+                | WhiteboardTool::Pan
+                | WhiteboardTool::Eraser
+                | WhiteboardTool::StickyNote
+        )
+    }
+}
+
+// What the user is currently doing with a pointer held down.
+#[derive(Clone, PartialEq)]
+enum Interaction {
+    // Drawing a new element, starting where the pointer went down.
+    Drawing {
+        origin: (f64, f64),
+        element: BoardElement,
+    },
+    // Dragging an element; `grab` is the pointer's offset from its top-left corner.
+    Moving {
+        id: String,
+        grab: (f64, f64),
+        from: (f64, f64),
+    },
+    // Dragging the bottom-right resize handle of an element.
+    Resizing {
+        id: String,
+        before: Bounds,
+    },
+    // Erasing every element the pointer passes over.
+    Erasing,
+    // Dragging the view around; `last` is the previous pointer position on the canvas.
+    Panning {
+        last: (f64, f64),
+    },
+}
+
+const STICKY_NOTE_COLORS: [&str; 4] = ["#fde68a", "#bbf7d0", "#bfdbfe", "#fbcfe8"];
+const PEN_COLORS: [&str; 8] = [
+    "#111827", "#ef4444", "#f97316", "#eab308", "#22c55e", "#3b82f6", "#8b5cf6", "#ffffff",
+];
+const MAX_LINE_WIDTH: f64 = 24.0;
+// Highlighter strokes are this much wider and more transparent than pen strokes.
+const HIGHLIGHTER_WIDTH: f64 = 4.0;
+const HIGHLIGHTER_OPACITY: f64 = 0.4;
+const CANVAS_ID: &str = "whiteboard-canvas";
+const MIN_ZOOM: f64 = 0.1;
+const MAX_ZOOM: f64 = 8.0;
+// Zoom factor applied by each button press.
+const ZOOM_STEP: f64 = 1.25;
+// How much each pixel of mouse wheel scrolling zooms in or out.
+const WHEEL_ZOOM_SPEED: f64 = 0.0015;
+// Space kept free around the content by "Fit to content", in screen pixels.
+const FIT_PADDING: f64 = 40.0;
+// Touches this soon after the pen was last seen are taken for the palm
+// of the hand holding it, and ignored.
+const PALM_REJECTION_MS: f64 = 1000.0;
+
+// Time between two steps of a playing replay.
+const REPLAY_STEP_MS: u64 = 300;
+// How long the whiteboard waits before reconnecting, doubled after each
+// failed attempt.
+const RECONNECT_MIN_DELAY_MS: u64 = 1000;
+const RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+
+// Which part of the infinite whiteboard is on screen. A world point `p`
+// is drawn at `(p - origin) * zoom` on the canvas.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Viewport {
+    origin: (f64, f64),
+    zoom: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            origin: (0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    // Converts a point on the canvas to world coordinates.
+    fn to_world(self, (x, y): (f64, f64)) -> (f64, f64) {
+        (self.origin.0 + x / self.zoom, self.origin.1 + y / self.zoom)
+    }
+
+    // Zooms by `factor` while keeping the world point under `anchor` in place.
+    fn zoom_at(&self, anchor: (f64, f64), factor: f64) -> Viewport {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let (x, y) = self.to_world(anchor);
+        Viewport {
+            origin: (x - anchor.0 / zoom, y - anchor.1 / zoom),
+            zoom,
+        }
+    }
+
+    // Moves the view along with a pointer that was dragged by `(dx, dy)` pixels.
+    fn pan_by(&self, (dx, dy): (f64, f64)) -> Viewport {
+        Viewport {
+            origin: (
+                self.origin.0 - dx / self.zoom,
+                self.origin.1 - dy / self.zoom,
+            ),
+            zoom: self.zoom,
+        }
+    }
+
+    // The viewport that shows all of `content` centered on a canvas of the given size.
+    fn fit(content: Bounds, (width, height): (f64, f64)) -> Viewport {
+        let zoom_x = (width - 2.0 * FIT_PADDING).max(1.0) / content.width.max(1.0);
+        let zoom_y = (height - 2.0 * FIT_PADDING).max(1.0) / content.height.max(1.0);
+        let zoom = zoom_x.min(zoom_y).clamp(MIN_ZOOM, MAX_ZOOM);
+        Viewport {
+            origin: (
+                content.x + content.width / 2.0 - width / 2.0 / zoom,
+                content.y + content.height / 2.0 - height / 2.0 / zoom,
+            ),
+            zoom,
+        }
+    }
+
+    // The SVG transform that maps world coordinates onto the canvas.
+    fn transform(&self) -> String {
+        format!(
+            "scale({}) translate({} {})",
+            self.zoom, -self.origin.0, -self.origin.1
+        )
+    }
+}
+
+// The smallest box containing every element, or `None` for an empty page.
+fn content_bounds(elements: &[BoardElement]) -> Option<Bounds> {
+    if elements.is_empty() {
+        return None;
+    }
+    Some(Bounds::around(elements.iter().flat_map(|e| {
+        let b = e.bounds();
+        [(b.x, b.y), (b.x + b.width, b.y + b.height)]
+    })))
+}
+
+fn canvas_rect() -> Option<web_sys::DomRect> {
+    window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.get_element_by_id(CANVAS_ID))
+        .map(|canvas| canvas.get_bounding_client_rect())
+}
+
+// Converts a pointer position to coordinates relative to the whiteboard canvas,
+// regardless of which element inside the canvas received the event.
+fn canvas_point(client: ClientPoint) -> (f64, f64) {
+    match canvas_rect() {
+        Some(rect) => (client.x - rect.left(), client.y - rect.top()),
+        None => (client.x, client.y),
+    }
+}
+
+// The local time of day of an RFC 3339 timestamp, for the replay timeline.
+fn replay_time(at: &str) -> String {
+    let date = js_sys::Date::new(&JsValue::from_str(at));
+    date.to_locale_time_string("default").into()
+}
+
+// The size of the whiteboard canvas on screen.
+fn canvas_size() -> (f64, f64) {
+    canvas_rect()
+        .map(|rect| (rect.width(), rect.height()))
+        .unwrap_or((0.0, 0.0))
+}
+
+// The id of the whiteboard element under a point on the screen, if any.
+// Touch and pen events keep going to the element they started on, so
+// erasing by dragging can't rely on enter events.
+fn element_at(client: ClientPoint) -> Option<String> {
+    window()?
+        .document()?
+        .element_from_point(client.x as f32, client.y as f32)?
+        .closest("[data-element-id]")
+        .ok()??
+        .get_attribute("data-element-id")
+}
+
+// How many pixels a wheel event scrolled, whatever unit the browser reported it in.
+fn wheel_pixels(delta: WheelDelta) -> f64 {
+    match delta {
+        WheelDelta::Pixels(v) => v.y,
+        WheelDelta::Lines(v) => v.y * 16.0,
+        WheelDelta::Pages(v) => v.y * 400.0,
+    }
+}
+
+// Asks the user for some text using the browser's prompt dialog.
+fn prompt_for_text(message: &str) -> Option<String> {
+    let text = window()?.prompt_with_message(message).ok().flatten()?;
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+// How finely point coordinates are sent: 10 means a tenth of a world unit.
+const POINT_SCALE: f64 = 10.0;
+const PRESSURE_SCALE: f64 = 255.0;
+// How much a simplified path may differ from the drawn one, as a fraction of its stroke width.
+const SIMPLIFY_TOLERANCE: f64 = 0.25;
+const PRESSURE_TOLERANCE: f64 = 0.05;
+
+// The compact wire format of a path's points: quantized integer coordinates,
+// each point relative to the one before it.
+#[derive(Serialize, Deserialize)]
+struct EncodedPoints {
+    xy: Vec<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pressure: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPoints {
+    Encoded(EncodedPoints),
+    Plain(Vec<StrokePoint>),
+}
+
+fn serialize_points<S: serde::Serializer>(
+    points: &[StrokePoint],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut xy = Vec::with_capacity(points.len() * 2);
+    let mut previous = (0, 0);
+    for point in points {
+        let current = (
+            (point.x * POINT_SCALE).round() as i64,
+            (point.y * POINT_SCALE).round() as i64,
+        );
+        xy.push(current.0 - previous.0);
+        xy.push(current.1 - previous.1);
+        previous = current;
+    }
+    let pressure = if points.iter().any(|p| p.pressure.is_some()) {
+        points
+            .iter()
+            .map(|p| (p.pressure.unwrap_or(0.5).clamp(0.0, 1.0) * PRESSURE_SCALE).round() as u8)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    EncodedPoints { xy, pressure }.serialize(serializer)
+}
+
+fn deserialize_points<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<StrokePoint>, D::Error> {
+    let encoded = match StoredPoints::deserialize(deserializer)? {
+        StoredPoints::Encoded(encoded) => encoded,
+        StoredPoints::Plain(points) => return Ok(points),
+    };
+    let mut points = Vec::with_capacity(encoded.xy.len() / 2);
+    let (mut x, mut y) = (0i64, 0i64);
+    for (i, delta) in encoded.xy.chunks_exact(2).enumerate() {
+        x = x.saturating_add(delta[0]);
+        y = y.saturating_add(delta[1]);
+        points.push(StrokePoint {
+            x: x as f64 / POINT_SCALE,
+            y: y as f64 / POINT_SCALE,
+            pressure: encoded
+                .pressure
+                .get(i)
+                .map(|&p| f64::from(p) / PRESSURE_SCALE),
+        });
+    }
+    Ok(points)
+}
+
+// Evens out the jitter of a hand-drawn stroke by averaging every point with
+// its neighbours. The ends stay where they are.
+fn smooth(points: &[StrokePoint]) -> Vec<StrokePoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut smoothed = Vec::with_capacity(points.len());
+    smoothed.push(points[0]);
+    for w in points.windows(3) {
+        smoothed.push(StrokePoint {
+            x: (w[0].x + 2.0 * w[1].x + w[2].x) / 4.0,
+            y: (w[0].y + 2.0 * w[1].y + w[2].y) / 4.0,
+            pressure: w[1].pressure,
+        });
+    }
+    smoothed.push(points[points.len() - 1]);
+    smoothed
+}
+
+// Removes the points that don't change the shape of a path by more than
+// `tolerance` (Ramer–Douglas–Peucker). The server does the same, this just
+// saves sending them.
+fn simplify(points: &[StrokePoint], tolerance: f64) -> Vec<StrokePoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let mut worst = (0, 1.0);
+        for i in first + 1..last {
+            let error = deviation(&points[i], &points[first], &points[last], tolerance);
+            if error > worst.1 {
+                worst = (i, error);
+            }
+        }
+        if worst.0 != 0 {
+            keep[worst.0] = true;
+            ranges.push((first, worst.0));
+            ranges.push((worst.0, last));
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect()
+}
+
+// How far `point` is from the segment between `start` and `end`, relative to the tolerance.
+fn deviation(point: &StrokePoint, start: &StrokePoint, end: &StrokePoint, tolerance: f64) -> f64 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let distance = (point.x - (start.x + t * dx)).hypot(point.y - (start.y + t * dy));
+    let pressure_error = match (point.pressure, start.pressure, end.pressure) {
+        (Some(p), Some(a), Some(b)) => (p - (a + t * (b - a))).abs(),
+        _ => 0.0,
+    };
+    (distance / tolerance).max(pressure_error / PRESSURE_TOLERANCE)
+}
+
+fn to_svg_path(points: &[StrokePoint]) -> String {
+    if points.is_empty() {
+        return String::new();
+    }
+    let mut d = format!("M {} {}", points[0].x, points[0].y);
+    for p in points.iter().skip(1) {
+        d.push_str(&format!(" L {} {}", p.x, p.y));
+    }
+    d
+}
+
+// The outline of a stroke whose width follows the pen pressure, as a closed
+// SVG path to be filled. Points without pressure are drawn at half pressure,
+// which is also what the stroke width is chosen for.
+fn pressure_outline(points: &[StrokePoint], stroke_width: f64) -> String {
+    let half_width = |p: &StrokePoint| {
+        stroke_width
+            * (MIN_PRESSURE_WIDTH + (2.0 - 2.0 * MIN_PRESSURE_WIDTH) * p.pressure.unwrap_or(0.5))
+            / 2.0
+    };
+    let mut left = Vec::with_capacity(points.len());
+    let mut right = Vec::with_capacity(points.len());
+    for (i, p) in points.iter().enumerate() {
+        // Offset each point sideways, perpendicular to the direction of the stroke.
+        let prev = &points[i.saturating_sub(1)];
+        let next = &points[(i + 1).min(points.len() - 1)];
+        let (dx, dy) = (next.x - prev.x, next.y - prev.y);
+        let length = dx.hypot(dy);
+        let (nx, ny) = if length > 0.0 {
+            (-dy / length, dx / length)
+        } else {
+            (0.0, 1.0)
+        };
+        let w = half_width(p);
+        left.push((p.x + nx * w, p.y + ny * w));
+        right.push((p.x - nx * w, p.y - ny * w));
+    }
+    let mut d = String::new();
+    for (i, (x, y)) in left.iter().chain(right.iter().rev()).enumerate() {
+        d.push_str(&format!("{} {} {} ", if i == 0 { "M" } else { "L" }, x, y));
+    }
+    d.push('Z');
+    d
+}
+
+// The two short strokes forming the head of an arrow at `line.end`.
+fn arrow_head_path(line: &LineData) -> String {
+    let (dx, dy) = (line.end.0 - line.start.0, line.end.1 - line.start.1);
+    let angle = dy.atan2(dx);
+    let size = 10.0 + line.stroke_width * 2.0;
+    let spread = std::f64::consts::PI / 7.0;
+    let left = (
+        line.end.0 - size * (angle - spread).cos(),
+        line.end.1 - size * (angle - spread).sin(),
+    );
+    let right = (
+        line.end.0 - size * (angle + spread).cos(),
+        line.end.1 - size * (angle + spread).sin(),
+    );
+    format!(
+        "M {} {} L {} {} L {} {}",
+        left.0, left.1, line.end.0, line.end.1, right.0, right.1
+    )
+}
+
+// Splits text into lines of at most `max_chars` characters, breaking at spaces.
+fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Renders the SVG nodes of a single whiteboard element.
+fn render_element(element: &BoardElement) -> Element {
+    match element {
+        BoardElement::Path(p) if p.points.iter().any(|pt| pt.pressure.is_some()) => rsx! {
+            path {
+                d: "{pressure_outline(&p.points, p.stroke_width)}",
+                fill: "{p.color}",
+                stroke: "{p.color}",
+                stroke_width: "{p.stroke_width * MIN_PRESSURE_WIDTH}",
+                stroke_linejoin: "round",
+                opacity: "{p.opacity}"
+            }
+        },
+        BoardElement::Path(p) => {
+            let blend_mode = if p.highlighter { "multiply" } else { "normal" };
+            rsx! {
+                path {
+                    d: "{to_svg_path(&p.points)}",
+                    stroke: "{p.color}",
+                    stroke_width: "{p.stroke_width}",
+                    fill: "none",
+                    stroke_linecap: "round",
+                    stroke_linejoin: "round",
+                    opacity: "{p.opacity}",
+                    style: "mix-blend-mode: {blend_mode};"
+                }
+            }
+        }
+        BoardElement::Rectangle(s) => rsx! {
+            rect {
+                x: "{s.bounds.x}",
+                y: "{s.bounds.y}",
+                width: "{s.bounds.width}",
+                height: "{s.bounds.height}",
+                stroke: "{s.color}",
+                stroke_width: "{s.stroke_width}",
+                fill: "transparent"
+            }
+        },
+        BoardElement::Ellipse(s) => {
+            let (rx, ry) = (s.bounds.width / 2.0, s.bounds.height / 2.0);
+            let (cx, cy) = (s.bounds.x + rx, s.bounds.y + ry);
+            rsx! {
+                ellipse {
+                    cx: "{cx}",
+                    cy: "{cy}",
+                    rx: "{rx}",
+                    ry: "{ry}",
+                    stroke: "{s.color}",
+                    stroke_width: "{s.stroke_width}",
+                    fill: "transparent"
+                }
+            }
+        }
+        BoardElement::Line(l) => rsx! {
+            line {
+                x1: "{l.start.0}",
+                y1: "{l.start.1}",
+                x2: "{l.end.0}",
+                y2: "{l.end.1}",
+                stroke: "{l.color}",
+                stroke_width: "{l.stroke_width}",
+                stroke_linecap: "round"
+            }
+        },
+        BoardElement::Arrow(l) => rsx! {
+            g {
+                line {
+                    x1: "{l.start.0}",
+                    y1: "{l.start.1}",
+                    x2: "{l.end.0}",
+                    y2: "{l.end.1}",
+                    stroke: "{l.color}",
+                    stroke_width: "{l.stroke_width}",
+                    stroke_linecap: "round"
+                }
+                path {
+                    d: "{arrow_head_path(l)}",
+                    stroke: "{l.color}",
+                    stroke_width: "{l.stroke_width}",
+                    fill: "none",
+                    stroke_linecap: "round",
+                    stroke_linejoin: "round"
+                }
+            }
+        },
+        BoardElement::Text(t) => {
+            let line_height = t.font_size * TEXT_LINE_HEIGHT;
+            let lines = t
+                .text
+                .lines()
+                .enumerate()
+                .map(|(i, line)| (t.position.1 + i as f64 * line_height, line.to_string()));
+            rsx! {
+                text {
+                    fill: "{t.color}",
+                    font_size: "{t.font_size}",
+                    dominant_baseline: "hanging",
+                    class: "select-none",
+                    for (y, line) in lines {
+                        tspan { x: "{t.position.0}", y: "{y}", "{line}" }
+                    }
+                }
+            }
+        }
+        BoardElement::StickyNote(n) => {
+            let text_x = n.bounds.x + STICKY_NOTE_PADDING;
+            let line_height = STICKY_NOTE_FONT_SIZE * TEXT_LINE_HEIGHT;
+            let max_chars = ((n.bounds.width - 2.0 * STICKY_NOTE_PADDING)
+                / (STICKY_NOTE_FONT_SIZE * TEXT_CHAR_WIDTH)) as usize;
+            let max_lines = ((n.bounds.height - 2.0 * STICKY_NOTE_PADDING) / line_height) as usize;
+            let lines = wrap_text(&n.text, max_chars)
+                .into_iter()
+                .take(max_lines)
+                .enumerate()
+                .map(|(i, line)| {
+                    (
+                        n.bounds.y + STICKY_NOTE_PADDING + i as f64 * line_height,
+                        line,
+                    )
+                });
+            rsx! {
+                g {
+                    rect {
+                        x: "{n.bounds.x}",
+                        y: "{n.bounds.y}",
+                        width: "{n.bounds.width}",
+                        height: "{n.bounds.height}",
+                        rx: "4",
+                        fill: "{n.color}",
+                        stroke: "#00000022"
+                    }
+                    text {
+                        fill: "#1e293b",
+                        font_size: "{STICKY_NOTE_FONT_SIZE}",
+                        dominant_baseline: "hanging",
+                        class: "select-none",
+                        for (y, line) in lines {
+                            tspan { x: "{text_x}", y: "{y}", "{line}" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Props, Clone)]
+struct WhiteboardProps {
+    hub_id: String,
+    initial_pages: Vec<PageInfo>,
+}
+#[allow(non_snake_case)]
+fn Whiteboard(props: WhiteboardProps) -> Element {
+    let mut pages = use_signal(|| props.initial_pages.clone());
+    // The page currently shown; its elements are fetched whenever it changes.
+    let mut active_page = use_signal(|| {
+        props
+            .initial_pages
+            .first()
+            .map(|page| page.id.clone())
+            .unwrap_or_default()
+    });
+    let mut elements = use_signal(Vec::<BoardElement>::new);
+    // How many times the connection to the server was opened again.
+    let mut reconnects = use_signal(|| 0u32);
+    // What the user is doing with the mouse held down, if anything
+    let mut interaction = use_signal::<Option<Interaction>>(|| None);
+    let mut selected = use_signal::<Option<String>>(|| None);
+    let mut tool = use_signal(|| WhiteboardTool::Pen);
+    let mut sticky_color = use_signal(|| STICKY_NOTE_COLORS[0]);
+    let mut viewport = use_signal(Viewport::default);
+    // When a pen was last seen over the canvas, for palm rejection.
+    let mut last_pen_seen = use_hook(|| CopyValue::new(f64::NEG_INFINITY));
+    // The pointer driving the current interaction. Other fingers and the
+    // palm are ignored until it is lifted.
+    let mut active_pointer = use_hook(|| CopyValue::new(None::<i32>));
+    // Generate a unique ID for this user
+    let user_id = use_memo(|| Uuid::new_v4().to_string());
+
+    // Style of the next stroke, shape or label. Everyone starts with their own color.
+    let mut stroke_color = use_signal(|| color_for_user(&user_id()));
+    let mut line_width = use_signal(|| 2.0);
+    let mut opacity = use_signal(|| 1.0_f64);
+
+    // The recorded history of the page while it is being replayed, and how
+    // many of its operations are shown.
+    let mut replay = use_signal::<Option<Vec<LoggedOperation>>>(|| None);
+    let mut replay_position = use_signal(|| 0usize);
+    let mut replay_playing = use_signal(|| false);
+
+    // Exported images of the page on screen.
+    let export_url = format!(
+        "https://api.ephemeral-hub.com/api/hubs/{}/whiteboard",
+        props.hub_id
+    );
+
+    let _page_loader = use_resource({
+        let hub_id = props.hub_id.clone();
+        move || {
+            let hub_id = hub_id.clone();
+            let page_id = active_page();
+            // Loaded again after reconnecting, to catch up on missed edits.
+            let _ = reconnects();
+            async move {
+                if page_id.is_empty() {
+                    return;
+                }
+                let api_url = format!(
+                    "https://api.ephemeral-hub.com/api/hubs/{}/pages/{}",
+                    hub_id, page_id
+                );
+                match reqwest::get(&api_url).await {
+                    Ok(res) => match res.json::<Vec<BoardElement>>().await {
+                        Ok(page_elements) => elements.set(page_elements),
+                        Err(e) => log::error!("Failed to parse whiteboard page: {}", e),
+                    },
+                    Err(e) => log::error!("Failed to load whiteboard page: {}", e),
+                }
+            }
+        }
+    });
+
+    let new_page_coroutine = use_coroutine({
+        let hub_id = props.hub_id.clone();
+        move |mut rx: UnboundedReceiver<String>| {
+            let hub_id = hub_id.clone();
+            async move {
+                while let Some(name) = rx.next().await {
+                    let api_url =
+                        format!("https://api.ephemeral-hub.com/api/hubs/{}/pages", hub_id);
+                    let client = reqwest::Client::new();
+                    let res = client
+                        .post(api_url)
+                        .json(&serde_json::json!({ "name": name }))
+                        .send()
+                        .await;
+
+                    match res {
+                        Ok(res) => {
+                            if let Ok(page) = res.json::<PageInfo>().await {
+                                if !pages.read().iter().any(|p| p.id == page.id) {
+                                    pages.write().push(page.clone());
+                                }
+                                active_page.set(page.id);
+                            }
+                        }
+                        Err(e) => log::error!("Failed to create page: {}", e),
+                    }
+                }
+            }
+        }
+    });
+
+    let replay_loader = use_coroutine({
+        let hub_id = props.hub_id.clone();
+        move |mut rx: UnboundedReceiver<String>| {
+            let hub_id = hub_id.clone();
+            async move {
+                while let Some(page_id) = rx.next().await {
+                    let api_url = format!(
+                        "https://api.ephemeral-hub.com/api/hubs/{}/whiteboard/history?page={}",
+                        hub_id, page_id
+                    );
+                    match reqwest::get(&api_url).await {
+                        Ok(res) => match res.json::<Vec<LoggedOperation>>().await {
+                            Ok(log) => {
+                                interaction.set(None);
+                                selected.set(None);
+                                replay_position.set(0);
+                                replay_playing.set(true);
+                                replay.set(Some(log));
+                            }
+                            Err(e) => log::error!("Failed to parse whiteboard history: {}", e),
+                        },
+                        Err(e) => log::error!("Failed to load whiteboard history: {}", e),
+                    }
+                }
+            }
+        }
+    });
+
+    // Steps through the replay while it is playing.
+    use_future(move || async move {
+        loop {
+            sleep(Duration::from_millis(REPLAY_STEP_MS)).await;
+            if !*replay_playing.peek() {
+                continue;
+            }
+            let len = replay.peek().as_ref().map_or(0, |log| log.len());
+            if *replay_position.peek() < len {
+                *replay_position.write() += 1;
+            } else {
+                replay_playing.set(false);
+            }
+        }
+    });
+
+    // Reconnects whenever the connection drops, e.g. when the server
+    // restarts for a deploy. Messages sent meanwhile wait in `rx`.
+    let ws_coroutine = use_coroutine(move |mut rx: UnboundedReceiver<WsMessage>| {
+        let ws_url = format!("wss://api.ephemeral-hub.com/ws/hubs/{}", props.hub_id);
+
+        // Incoming messages
+        let mut receive = move |server_msg: WsMessage| {
+            if let WsMessage::PageCreated(page) = server_msg {
+                if !pages.read().iter().any(|p| p.id == page.id) {
+                    pages.write().push(page);
+                }
+                return;
+            }
+            if server_msg == WsMessage::ServerRestarting {
+                log::info!("Server restarting, reconnecting once it closes the connection.");
+                return;
+            }
+            // Whiteboard edits are only relevant to the page on screen.
+            if server_msg.page_id() != Some(active_page().as_str()) {
+                return;
+            }
+            match server_msg {
+                WsMessage::ElementAdded { element, .. } => {
+                    // Our own elements are echoed back after being drawn locally.
+                    if !elements.read().iter().any(|e| e.id() == element.id()) {
+                        elements.write().push(element);
+                    }
+                }
+                WsMessage::ElementDeleted { id, .. } => {
+                    elements.write().retain(|e| e.id() != id);
+                    if selected.read().as_deref() == Some(id.as_str()) {
+                        selected.set(None);
+                    }
+                }
+                WsMessage::ElementMoved { id, x, y, .. } => {
+                    if let Some(element) = elements.write().iter_mut().find(|e| e.id() == id) {
+                        element.move_to(x, y);
+                    }
+                }
+                WsMessage::ElementResized { id, bounds, .. } => {
+                    if let Some(element) = elements.write().iter_mut().find(|e| e.id() == id) {
+                        element.set_bounds(bounds);
+                    }
+                }
+                WsMessage::BoardCleared { .. } => {
+                    elements.write().clear();
+                    selected.set(None);
+                }
+                // Only ever sent by clients, handled above or for the text bin.
+                WsMessage::Undo { .. }
+                | WsMessage::Redo { .. }
+                | WsMessage::PageCreated(_)
+                | WsMessage::TextEdit { .. }
+                | WsMessage::TextCursor { .. }
+                | WsMessage::TextSnapshot { .. }
+                | WsMessage::TextUpdated { .. }
+                | WsMessage::SnippetUpdated { .. }
+                | WsMessage::SnippetDeleted { .. }
+                | WsMessage::TextLanguageChanged { .. }
+                | WsMessage::ServerRestarting => {}
+            }
+        };
+
+        async move {
+            let mut retry_delay = RECONNECT_MIN_DELAY_MS;
+            let mut first_attempt = true;
+            loop {
+                if !first_attempt {
+                    sleep(Duration::from_millis(retry_delay)).await;
+                    retry_delay = (retry_delay * 2).min(RECONNECT_MAX_DELAY_MS);
+                    // Edits made while disconnected were missed.
+                    *reconnects.write() += 1;
+                }
+                first_attempt = false;
+
+                let ws = match WebSocket::open(&ws_url) {
+                    Ok(ws) => ws,
+                    Err(e) => {
+                        log::error!("Failed to connect to WebSocket: {:?}", e);
+                        continue;
+                    }
+                };
+                let (mut write, read) = ws.split();
+                let mut read = read.fuse();
+
+                loop {
+                    futures::select! {
+                        incoming = read.next() => match incoming {
+                            Some(Ok(GlooWsMessage::Text(text))) => {
+                                retry_delay = RECONNECT_MIN_DELAY_MS;
+                                if let Ok(server_msg) = serde_json::from_str::<WsMessage>(&text) {
+                                    receive(server_msg);
+                                }
+                            }
+                            Some(Ok(GlooWsMessage::Bytes(_))) => {}
+                            Some(Err(e)) => {
+                                log::error!("WebSocket connection lost: {:?}", e);
+                                break;
+                            }
+                            None => break,
+                        },
+                        // Outgoing messages
+                        outgoing = rx.next() => {
+                            // The whiteboard is gone.
+                            let Some(msg_to_send) = outgoing else {
+                                return;
+                            };
+                            let json_msg = serde_json::to_string(&msg_to_send).unwrap();
+                            if write.send(GlooWsMessage::Text(json_msg)).await.is_err() {
+                                log::error!("WebSocket connection closed. Cannot send message.");
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    let mut erase_element = move |id: String| {
+        elements.write().retain(|e| e.id() != id);
+        if selected.read().as_deref() == Some(id.as_str()) {
+            selected.set(None);
+        }
+        ws_coroutine.send(WsMessage::ElementDeleted {
+            page_id: active_page(),
+            id,
+            user_id: user_id(),
+        });
+    };
+
+    let mut add_element = move |element: BoardElement| {
+        elements.write().push(element.clone());
+        ws_coroutine.send(WsMessage::ElementAdded {
+            page_id: active_page(),
+            element,
+        });
+    };
+
+    // Whether a pointer going down should start an interaction.
+    // If so, it becomes the active pointer until it is lifted.
+    let mut accept_pointer = move |evt: &PointerData| {
+        // A replay can be panned around, but not edited.
+        if replay.peek().is_some()
+            && tool() != WhiteboardTool::Pan
+            && evt.trigger_button() != Some(MouseButton::Auxiliary)
+        {
+            return false;
+        }
+        let now = js_sys::Date::now();
+        match evt.pointer_type().as_str() {
+            "pen" => last_pen_seen.set(now),
+            "touch" if now - last_pen_seen() < PALM_REJECTION_MS => return false,
+            _ => {}
+        }
+        if interaction.peek().is_some() && active_pointer() != Some(evt.pointer_id()) {
+            return false;
+        }
+        active_pointer.set(Some(evt.pointer_id()));
+        true
+    };
+
+    // Commits whatever the user was doing when the pointer is lifted.
+    let mut finish_interaction = move || {
+        active_pointer.set(None);
+        let Some(finished) = interaction.take() else {
+            return;
+        };
+        match finished {
+            Interaction::Drawing { mut element, .. } => {
+                if let BoardElement::Path(p) = &mut element {
+                    p.points = simplify(&smooth(&p.points), p.stroke_width * SIMPLIFY_TOLERANCE);
+                }
+                if element.is_visible() {
+                    add_element(element);
+                }
+            }
+            Interaction::Moving { id, from, .. } => {
+                let bounds = elements
+                    .read()
+                    .iter()
+                    .find(|e| e.id() == id)
+                    .map(|e| e.bounds());
+                if let Some(bounds) = bounds {
+                    if (bounds.x, bounds.y) != from {
+                        ws_coroutine.send(WsMessage::ElementMoved {
+                            page_id: active_page(),
+                            id,
+                            user_id: user_id(),
+                            x: bounds.x,
+                            y: bounds.y,
+                        });
+                    }
+                }
+            }
+            Interaction::Resizing { id, before } => {
+                let bounds = elements
+                    .read()
+                    .iter()
+                    .find(|e| e.id() == id)
+                    .map(|e| e.bounds());
+                if let Some(bounds) = bounds {
+                    if bounds != before {
+                        ws_coroutine.send(WsMessage::ElementResized {
+                            page_id: active_page(),
+                            id,
+                            user_id: user_id(),
+                            bounds,
+                        });
+                    }
+                }
+            }
+            Interaction::Erasing | Interaction::Panning { .. } => {}
+        }
+    };
+
+    let canvas_class = format!(
+        "w-full h-[70vh] min-h-[400px] border border-gray-300 rounded-md bg-gray-50 select-none touch-none {}",
+        match tool() {
+            _ if matches!(*interaction.read(), Some(Interaction::Panning { .. })) => {
+                "cursor-grabbing"
+            }
+            WhiteboardTool::Select => "cursor-default",
+            WhiteboardTool::Pan => "cursor-grab",
+            WhiteboardTool::Eraser => "cursor-cell",
+            WhiteboardTool::Text | WhiteboardTool::StickyNote => "cursor-text",
+            _ => "cursor-crosshair",
+        }
+    );
+
+    let tool_button_class = |active: bool| {
+        format!(
+            "px-3 py-1 text-sm font-semibold rounded-md transition-colors duration-200 {}",
+            if active {
+                "bg-blue-600 text-white"
+            } else {
+                "bg-slate-700/50 text-slate-300 hover:bg-slate-600/50"
+            }
+        )
+    };
+
+    let swatch_class = |active: bool| {
+        if active {
+            "w-6 h-6 rounded-full border-2 border-white"
+        } else {
+            "w-6 h-6 rounded-full border border-slate-500"
+        }
+    };
+
+    // While replaying, the board is shown as it was after the operations
+    // up to the current position.
+    let replayed = replay.read().as_ref().map(|log| {
+        let mut board = Vec::new();
+        for op in &log[..replay_position().min(log.len())] {
+            replay_effect(&mut board, &op.message);
+        }
+        board
+    });
+    let current_elements = elements.read();
+    let shown_elements = replayed.as_deref().unwrap_or(&current_elements);
+
+    let element_nodes = shown_elements
+        .iter()
+        .map(|element| {
+            let id = element.id().to_string();
+            let bounds = element.bounds();
+            rsx! {
+                g {
+                    key: "{id}",
+                    "data-element-id": "{id}",
+                    onpointerdown: {
+                        let id = id.clone();
+                        move |evt: Event<PointerData>| match tool() {
+                            // The middle button always pans, which the canvas handles.
+                            _ if evt.trigger_button() == Some(MouseButton::Auxiliary) => {}
+                            WhiteboardTool::Eraser if accept_pointer(&evt) => {
+                                erase_element(id.clone());
+                            }
+                            WhiteboardTool::Eraser => {}
+                            WhiteboardTool::Select => {
+                                // Keep the canvas from clearing the selection.
+                                evt.stop_propagation();
+                                if !accept_pointer(&evt) {
+                                    return;
+                                }
+                                let (x, y) = viewport().to_world(canvas_point(evt.client_coordinates()));
+                                selected.set(Some(id.clone()));
+                                interaction.set(Some(Interaction::Moving {
+                                    id: id.clone(),
+                                    grab: (x - bounds.x, y - bounds.y),
+                                    from: (bounds.x, bounds.y),
+                                }));
+                            }
+                            _ => {}
+                        }
+                    },
+                    {render_element(element)}
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Outline and resize handle of the selected element.
+    // They are kept the same size on screen at any zoom.
+    let px = 1.0 / viewport().zoom;
+    let selection = selected
+        .read()
+        .as_ref()
+        .filter(|_| tool() == WhiteboardTool::Select && replay.read().is_none())
+        .and_then(|id| elements.read().iter().find(|e| e.id() == id).cloned())
+        .map(|element| (element.id().to_string(), element.bounds()));
+
+    rsx! {
+        div {
+            class: "bg-slate-800/40 backdrop-blur-sm border border-slate-700/50 rounded-xl p-6 hover:bg-slate-700/30 transition-all duration-300 col-span-1 lg:col-span-2 focus:outline-none",
+            tabindex: "0",
+            // Ctrl+Z undoes, Ctrl+Shift+Z redoes (Cmd on macOS).
+            // Delete or Backspace removes the selected element.
+            onkeydown: move |evt| {
+                if replay.peek().is_some() {
+                    return;
+                }
+                let modifiers = evt.modifiers();
+                match evt.key() {
+                    Key::Delete | Key::Backspace => {
+                        if let Some(id) = selected() {
+                            evt.prevent_default();
+                            erase_element(id);
+                        }
+                    }
+                    Key::Character(c)
+                        if c.eq_ignore_ascii_case("z") && (modifiers.ctrl() || modifiers.meta()) =>
+                    {
+                        evt.prevent_default();
+                        let page_id = active_page();
+                        let user_id = user_id();
+                        if modifiers.shift() {
+                            ws_coroutine.send(WsMessage::Redo { page_id, user_id });
+                        } else {
+                            ws_coroutine.send(WsMessage::Undo { page_id, user_id });
+                        }
+                    }
+                    _ => {}
+                }
+            },
+
+            div { class: "flex items-center justify-between mb-4",
+                h2 { class: "text-xl font-bold text-white", "Collaborative Whiteboard" }
+                div { class: "flex items-center gap-2",
+                    button {
+                        class: "{tool_button_class(false)}",
+                        title: "Undo (Ctrl+Z)",
+                        onclick: move |_| {
+                            ws_coroutine.send(WsMessage::Undo {
+                                page_id: active_page(),
+                                user_id: user_id(),
+                            })
+                        },
+                        "Undo"
+                    }
+                    button {
+                        class: "{tool_button_class(false)}",
+                        title: "Redo (Ctrl+Shift+Z)",
+                        onclick: move |_| {
+                            ws_coroutine.send(WsMessage::Redo {
+                                page_id: active_page(),
+                                user_id: user_id(),
+                            })
+                        },
+                        "Redo"
+                    }
+                    button {
+                        class: "{tool_button_class(false)}",
+                        title: "Clear the board for everyone",
+                        onclick: move |_| {
+                            elements.write().clear();
+                            selected.set(None);
+                            ws_coroutine.send(WsMessage::BoardCleared {
+                                page_id: active_page(),
+                                user_id: user_id(),
+                            });
+                        },
+                        "Clear"
+                    }
+                    a {
+                        class: "{tool_button_class(false)}",
+                        href: "{export_url}.svg?page={active_page}",
+                        target: "_blank",
+                        title: "Export this page as an SVG image",
+                        "Export SVG"
+                    }
+                    a {
+                        class: "{tool_button_class(false)}",
+                        href: "{export_url}.png?page={active_page}",
+                        target: "_blank",
+                        title: "Export this page as a PNG image",
+                        "Export PNG"
+                    }
+                    button {
+                        class: "{tool_button_class(replay.read().is_some())}",
+                        title: "Replay how this page was drawn",
+                        onclick: move |_| {
+                            if replay.peek().is_some() {
+                                replay_playing.set(false);
+                                replay.set(None);
+                            } else {
+                                replay_loader.send(active_page());
+                            }
+                        },
+                        "Replay"
+                    }
+                }
+            }
+
+            // Tool palette
+            div { class: "flex flex-wrap items-center gap-2 mb-2",
+                for t in WhiteboardTool::ALL {
+                    button {
+                        class: "{tool_button_class(tool() == t)}",
+                        onclick: move |_| {
+                            tool.set(t);
+                            selected.set(None);
+                        },
+                        "{t.label()}"
+                    }
+                }
+                if tool() == WhiteboardTool::StickyNote {
+                    for color in STICKY_NOTE_COLORS {
+                        button {
+                            class: "{swatch_class(sticky_color() == color)}",
+                            style: "background-color: {color};",
+                            title: "Sticky note color",
+                            onclick: move |_| sticky_color.set(color),
+                        }
+                    }
+                }
+            }
+
+            // Stroke style
+            if tool().uses_stroke_style() {
+                div { class: "flex flex-wrap items-center gap-2 mb-2 text-sm text-slate-300",
+                    for color in PEN_COLORS {
+                        button {
+                            class: "{swatch_class(stroke_color() == color)}",
+                            style: "background-color: {color};",
+                            title: "{color}",
+                            onclick: move |_| stroke_color.set(color.to_string()),
+                        }
+                    }
+                    input {
+                        r#type: "color",
+                        class: "w-8 h-6 bg-transparent cursor-pointer",
+                        title: "Custom color",
+                        value: "{stroke_color}",
+                        oninput: move |event| stroke_color.set(event.value()),
+                    }
+                    label { class: "flex items-center gap-2 ml-2",
+                        "Width"
+                        input {
+                            r#type: "range",
+                            min: "1",
+                            max: "{MAX_LINE_WIDTH}",
+                            step: "1",
+                            value: "{line_width}",
+                            oninput: move |event| {
+                                if let Ok(width) = event.value().parse::<f64>() {
+                                    line_width.set(width.clamp(1.0, MAX_LINE_WIDTH));
+                                }
+                            },
+                        }
+                    }
+                    label { class: "flex items-center gap-2 ml-2",
+                        "Opacity"
+                        input {
+                            r#type: "range",
+                            min: "10",
+                            max: "100",
+                            step: "5",
+                            value: "{(opacity() * 100.0).round()}",
+                            oninput: move |event| {
+                                if let Ok(percent) = event.value().parse::<f64>() {
+                                    opacity.set((percent / 100.0).clamp(0.1, 1.0));
+                                }
+                            },
+                        }
+                    }
+                }
+            }
+
+            // Page tabs
+            div { class: "flex items-center gap-2 mb-2 overflow-x-auto",
+                for page in pages.read().iter() {
+                    button {
+                        key: "{page.id}",
+                        class: "{tool_button_class(page.id == active_page())}",
+                        onclick: {
+                            let page_id = page.id.clone();
+                            move |_| {
+                                interaction.set(None);
+                                selected.set(None);
+                                viewport.set(Viewport::default());
+                                replay.set(None);
+                                active_page.set(page_id.clone());
+                            }
+                        },
+                        "{page.name}"
+                    }
+                }
+                button {
+                    class: "{tool_button_class(false)}",
+                    title: "Add a page",
+                    onclick: move |_| {
+                        let name = format!("Page {}", pages.read().len() + 1);
+                        new_page_coroutine.send(name);
+                    },
+                    "+"
+                }
+            }
+
+            // Zoom controls
+            div { class: "flex items-center gap-2 mb-2",
+                button {
+                    class: "{tool_button_class(false)}",
+                    title: "Zoom out",
+                    onclick: move |_| {
+                        let (width, height) = canvas_size();
+                        viewport.set(viewport().zoom_at((width / 2.0, height / 2.0), 1.0 / ZOOM_STEP));
+                    },
+                    "−"
+                }
+                button {
+                    class: "{tool_button_class(false)}",
+                    title: "Reset zoom",
+                    onclick: move |_| {
+                        let (width, height) = canvas_size();
+                        let zoom = viewport().zoom;
+                        viewport.set(viewport().zoom_at((width / 2.0, height / 2.0), 1.0 / zoom));
+                    },
+                    "{(viewport().zoom * 100.0).round()}%"
+                }
+                button {
+                    class: "{tool_button_class(false)}",
+                    title: "Zoom in",
+                    onclick: move |_| {
+                        let (width, height) = canvas_size();
+                        viewport.set(viewport().zoom_at((width / 2.0, height / 2.0), ZOOM_STEP));
+                    },
+                    "+"
+                }
+                button {
+                    class: "{tool_button_class(false)}",
+                    title: "Fit to content",
+                    onclick: move |_| {
+                        let fitted = content_bounds(&elements.read())
+                            .map(|content| Viewport::fit(content, canvas_size()))
+                            .unwrap_or_default();
+                        viewport.set(fitted);
+                    },
+                    "Fit to content"
+                }
+            }
+
+            // Replay timeline
+            if let Some(log) = replay.read().as_ref() {
+                div { class: "flex items-center gap-2 mb-2 text-sm text-slate-300",
+                    button {
+                        class: "{tool_button_class(false)}",
+                        onclick: move |_| {
+                            let len = replay.peek().as_ref().map_or(0, |log| log.len());
+                            if !replay_playing() && replay_position() >= len {
+                                replay_position.set(0);
+                            }
+                            replay_playing.set(!replay_playing());
+                        },
+                        if replay_playing() { "Pause" } else { "Play" }
+                    }
+                    input {
+                        r#type: "range",
+                        class: "flex-1",
+                        min: "0",
+                        max: "{log.len()}",
+                        step: "1",
+                        value: "{replay_position}",
+                        oninput: move |event| {
+                            if let Ok(position) = event.value().parse::<usize>() {
+                                replay_playing.set(false);
+                                replay_position.set(position);
+                            }
+                        },
+                    }
+                    span { class: "whitespace-nowrap",
+                        "{replay_position} / {log.len()}"
+                    }
+                    if let Some(op) = replay_position().checked_sub(1).and_then(|i| log.get(i)) {
+                        span {
+                            class: "whitespace-nowrap",
+                            style: "color: {color_for_user(&op.user_id)};",
+                            "{replay_time(&op.at)}"
+                        }
+                    }
+                    a {
+                        class: "{tool_button_class(false)}",
+                        href: "{export_url}/replay.svg?page={active_page}",
+                        target: "_blank",
+                        title: "Download the replay as an animated SVG",
+                        "Export replay"
+                    }
+                }
+            }
+
+            svg {
+                id: CANVAS_ID,
+                class: "{canvas_class}",
+                prevent_default: "onpointerdown onpointermove onwheel",
+
+                onpointerdown: move |evt| {
+                    if !accept_pointer(&evt) {
+                        return;
+                    }
+                    let screen = canvas_point(evt.client_coordinates());
+                    // The middle button pans with any tool.
+                    if tool() == WhiteboardTool::Pan
+                        || evt.trigger_button() == Some(MouseButton::Auxiliary)
+                    {
+                        interaction.set(Some(Interaction::Panning { last: screen }));
+                        return;
+                    }
+                    let point = viewport().to_world(screen);
+                    // Only pens report a meaningful pressure.
+                    let pressure = (evt.pointer_type() == "pen").then(|| evt.pressure() as f64);
+                    let id = format!("{}-{}", user_id, Uuid::new_v4());
+                    let author = user_id();
+                    let color = stroke_color();
+                    // Lines are as thick on screen as they are at 100% zoom.
+                    let stroke_width = line_width() / viewport().zoom;
+                    let drawing = |element| Some(Interaction::Drawing { origin: point, element });
+                    match tool() {
+                        // Clicking on empty space clears the selection.
+                        WhiteboardTool::Select => selected.set(None),
+                        WhiteboardTool::Pan => {}
+                        WhiteboardTool::Eraser => interaction.set(Some(Interaction::Erasing)),
+                        WhiteboardTool::Pen => interaction.set(drawing(BoardElement::Path(PathData {
+                            id,
+                            points: vec![StrokePoint {
+                                x: point.0,
+                                y: point.1,
+                                pressure,
+                            }],
+                            color,
+                            stroke_width,
+                            author,
+                            opacity: opacity(),
+                            highlighter: false,
+                        }))),
+                        // Highlighter strokes keep an even width, whatever the pen pressure.
+                        WhiteboardTool::Highlighter => interaction.set(drawing(BoardElement::Path(PathData {
+                            id,
+                            points: vec![StrokePoint {
+                                x: point.0,
+                                y: point.1,
+                                pressure: None,
+                            }],
+                            color,
+                            stroke_width: stroke_width * HIGHLIGHTER_WIDTH,
+                            author,
+                            opacity: opacity() * HIGHLIGHTER_OPACITY,
+                            highlighter: true,
+                        }))),
+                        WhiteboardTool::Rectangle | WhiteboardTool::Ellipse => {
+                            let shape = ShapeData {
+                                id,
+                                author,
+                                bounds: Bounds::around([point]),
+                                color,
+                                stroke_width,
+                            };
+                            interaction.set(drawing(if tool() == WhiteboardTool::Rectangle {
+                                BoardElement::Rectangle(shape)
+                            } else {
+                                BoardElement::Ellipse(shape)
+                            }));
+                        }
+                        WhiteboardTool::Line | WhiteboardTool::Arrow => {
+                            let line = LineData {
+                                id,
+                                author,
+                                start: point,
+                                end: point,
+                                color,
+                                stroke_width,
+                            };
+                            interaction.set(drawing(if tool() == WhiteboardTool::Line {
+                                BoardElement::Line(line)
+                            } else {
+                                BoardElement::Arrow(line)
+                            }));
+                        }
+                        WhiteboardTool::Text => {
+                            if let Some(text) = prompt_for_text("Label text") {
+                                add_element(BoardElement::Text(TextData {
+                                    id,
+                                    author,
+                                    position: point,
+                                    text,
+                                    color,
+                                    font_size: 18.0,
+                                }));
+                            }
+                        }
+                        WhiteboardTool::StickyNote => {
+                            if let Some(text) = prompt_for_text("Sticky note text") {
+                                add_element(BoardElement::StickyNote(StickyNoteData {
+                                    id,
+                                    author,
+                                    bounds: Bounds {
+                                        x: point.0,
+                                        y: point.1,
+                                        width: 160.0,
+                                        height: 120.0,
+                                    },
+                                    text,
+                                    color: sticky_color().to_string(),
+                                }));
+                            }
+                        }
+                    }
+                },
+
+                onpointermove: move |evt| {
+                    if evt.pointer_type() == "pen" {
+                        last_pen_seen.set(js_sys::Date::now());
+                    }
+                    if active_pointer() != Some(evt.pointer_id()) {
+                        return;
+                    }
+                    let screen = canvas_point(evt.client_coordinates());
+                    let (x, y) = viewport().to_world(screen);
+                    let current = interaction.read().clone();
+                    match current {
+                        Some(Interaction::Panning { last }) => {
+                            viewport.set(viewport().pan_by((screen.0 - last.0, screen.1 - last.1)));
+                            interaction.set(Some(Interaction::Panning { last: screen }));
+                        }
+                        Some(Interaction::Drawing { origin, .. }) => {
+                            if let Some(Interaction::Drawing { element, .. }) = interaction.write().as_mut() {
+                                match element {
+                                    BoardElement::Path(p) => p.points.push(StrokePoint {
+                                        x,
+                                        y,
+                                        pressure: (evt.pointer_type() == "pen" && !p.highlighter)
+                                            .then(|| evt.pressure() as f64),
+                                    }),
+                                    BoardElement::Rectangle(s) | BoardElement::Ellipse(s) => {
+                                        s.bounds = Bounds::around([origin, (x, y)]);
+                                    }
+                                    BoardElement::Line(l) | BoardElement::Arrow(l) => l.end = (x, y),
+                                    BoardElement::Text(_) | BoardElement::StickyNote(_) => {}
+                                }
+                            }
+                        }
+                        Some(Interaction::Moving { id, grab, .. }) => {
+                            if let Some(element) = elements.write().iter_mut().find(|e| e.id() == id) {
+                                element.move_to(x - grab.0, y - grab.1);
+                            }
+                        }
+                        Some(Interaction::Resizing { id, before }) => {
+                            if let Some(element) = elements.write().iter_mut().find(|e| e.id() == id) {
+                                element.set_bounds(Bounds {
+                                    x: before.x,
+                                    y: before.y,
+                                    width: (x - before.x).max(MIN_ELEMENT_SIZE),
+                                    height: (y - before.y).max(MIN_ELEMENT_SIZE),
+                                });
+                            }
+                        }
+                        Some(Interaction::Erasing) => {
+                            if let Some(id) = element_at(evt.client_coordinates()) {
+                                erase_element(id);
+                            }
+                        }
+                        None => {}
+                    }
+                },
+
+                onpointerup: move |evt| {
+                    if active_pointer() == Some(evt.pointer_id()) {
+                        finish_interaction();
+                    }
+                },
+                onpointercancel: move |evt| {
+                    if active_pointer() == Some(evt.pointer_id()) {
+                        finish_interaction();
+                    }
+                },
+                onpointerleave: move |evt| {
+                    if active_pointer() == Some(evt.pointer_id()) {
+                        finish_interaction();
+                    }
+                },
+
+                // Zoom around the pointer
+                onwheel: move |evt| {
+                    let anchor = canvas_point(evt.client_coordinates());
+                    let factor = (-wheel_pixels(evt.delta()) * WHEEL_ZOOM_SPEED).exp();
+                    viewport.set(viewport().zoom_at(anchor, factor));
+                },
+
+                // Everything inside is in world coordinates.
+                g { transform: "{viewport().transform()}",
+                    // Render all elements
+                    {element_nodes.into_iter()}
+
+                    // Render the element currently being drawn by this user
+                    if let Some(Interaction::Drawing { element, .. }) = interaction.read().as_ref() {
+                        {render_element(element)}
+                    }
+
+                    if let Some((id, bounds)) = selection {
+                        rect {
+                            x: "{bounds.x - 4.0 * px}",
+                            y: "{bounds.y - 4.0 * px}",
+                            width: "{bounds.width + 8.0 * px}",
+                            height: "{bounds.height + 8.0 * px}",
+                            fill: "none",
+                            stroke: "#3b82f6",
+                            stroke_width: "{px}",
+                            stroke_dasharray: "{4.0 * px} {4.0 * px}",
+                            pointer_events: "none"
+                        }
+                        rect {
+                            x: "{bounds.x + bounds.width - 2.0 * px}",
+                            y: "{bounds.y + bounds.height - 2.0 * px}",
+                            width: "{10.0 * px}",
+                            height: "{10.0 * px}",
+                            fill: "#3b82f6",
+                            class: "cursor-nwse-resize",
+                            onpointerdown: move |evt| {
+                                evt.stop_propagation();
+                                if !accept_pointer(&evt) {
+                                    return;
+                                }
+                                interaction.set(Some(Interaction::Resizing {
+                                    id: id.clone(),
+                                    before: bounds,
+                                }));
+                            }
+                        }
                     }
                 }
             }